@@ -0,0 +1,181 @@
+// Frame times for the grid at the sizes the toolbox allows, against the entity with its own mesh
+// and material per hex the grid used to be drawn with. Run with `cargo bench`.
+//
+// The apps are headless, so what's timed is the work each frame before anything reaches the
+// GPU: rebuilding meshes, propagating transforms and culling every drawn entity against the
+// camera. Drawing itself isn't timed
+use bevy::{prelude::*, render::view::VisibilityPlugin, sprite::MaterialMesh2dBundle};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use hexalon::{
+    cell::Cell,
+    chunk,
+    grid::{self, Grid, GridEvent, GridSettings},
+    layer::Layers,
+    mesh,
+    shape::GridShape,
+    texture,
+};
+
+const SIZES: [i32; 3] = [10, 100, 250];
+
+fn square(size: i32) -> GridShape {
+    GridShape::Rectangle {
+        width: size,
+        height: size,
+    }
+}
+
+// Plugins both ways of drawing the grid need, and a camera zoomed out to see all of it
+fn headless_app(size: i32) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        AssetPlugin::default(),
+        TransformPlugin,
+        HierarchyPlugin,
+        VisibilityPlugin,
+    ))
+    .init_asset::<Mesh>()
+    .init_asset::<ColorMaterial>()
+    .init_asset::<Image>()
+    .add_systems(Startup, move |mut commands: Commands| {
+        // The area is normally kept up to date by the renderer
+        let extent = size as f32 * grid::HEX_SIZE;
+        commands.spawn(Camera2dBundle {
+            projection: OrthographicProjection {
+                area: Rect::new(-extent, -extent, extent, extent),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    });
+
+    app
+}
+
+// The grid drawn in chunks, every chunk loaded and every mesh already built
+fn chunked_app(size: i32) -> App {
+    let mut app = headless_app(size);
+    app.add_plugins((grid::Plugin, chunk::Plugin, texture::Plugin, mesh::Plugin))
+        .add_systems(Startup, move |mut commands: Commands| {
+            Grid::create(square(size), &mut commands);
+            commands.spawn(Layers::default());
+        });
+
+    // Spawns the grid, loads its chunks, then builds their meshes
+    app.update();
+    app.update();
+    app.update();
+
+    app
+}
+
+// The grid drawn with an entity, mesh and material for every hex
+fn per_hex_app(size: i32) -> App {
+    let mut app = headless_app(size);
+    app.add_systems(
+        Startup,
+        move |mut commands: Commands,
+              mut meshes: ResMut<Assets<Mesh>>,
+              mut materials: ResMut<Assets<ColorMaterial>>| {
+            let grid = Grid::new(square(size), GridSettings::default());
+            for coord in grid.shape.coords(grid.settings.layout) {
+                commands.spawn(MaterialMesh2dBundle {
+                    mesh: meshes
+                        .add(Mesh::from(shape::RegularPolygon::new(grid::HEX_SIZE, 6)))
+                        .into(),
+                    material: materials.add(ColorMaterial::from(Color::WHITE)),
+                    transform: Transform::from_translation(
+                        grid.hex_coord_to_pos(&coord).extend(0.0),
+                    ),
+                    ..Default::default()
+                });
+            }
+        },
+    );
+
+    app.update();
+    app.update();
+
+    app
+}
+
+fn next_color(color: Color) -> Color {
+    if color == Color::RED {
+        Color::BLUE
+    } else {
+        Color::RED
+    }
+}
+
+// Painting a cell, the most common change to the map
+fn paint(c: &mut Criterion) {
+    let mut group = c.benchmark_group("paint");
+    for size in SIZES {
+        let mut app = chunked_app(size);
+        let cells: Vec<Entity> = app
+            .world
+            .query::<&Grid>()
+            .single(&app.world)
+            .cells
+            .values()
+            .copied()
+            .collect();
+        let mut i = 0;
+
+        group.bench_function(BenchmarkId::new("chunks", size), |b| {
+            b.iter(|| {
+                let mut cell = app.world.get_mut::<Cell>(cells[i % cells.len()]).unwrap();
+                cell.color = next_color(cell.color);
+                i += 1;
+
+                app.update();
+            })
+        });
+
+        let mut app = per_hex_app(size);
+        let handles: Vec<Handle<ColorMaterial>> = app
+            .world
+            .query::<&Handle<ColorMaterial>>()
+            .iter(&app.world)
+            .cloned()
+            .collect();
+        let mut i = 0;
+
+        group.bench_function(BenchmarkId::new("per_hex", size), |b| {
+            b.iter(|| {
+                let mut materials = app.world.resource_mut::<Assets<ColorMaterial>>();
+                let material = materials.get_mut(&handles[i % handles.len()]).unwrap();
+                material.color = next_color(material.color);
+                i += 1;
+
+                app.update();
+            })
+        });
+    }
+    group.finish();
+}
+
+// Changing the hex size, which moves every cell
+fn relayout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("relayout");
+    group.sample_size(10);
+    for size in SIZES {
+        let mut app = chunked_app(size);
+        let mut settings = app.world.query::<&Grid>().single(&app.world).settings;
+
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                settings.hex_size = if settings.hex_size > 35.0 { 35.0 } else { 40.0 };
+                app.world.send_event(GridEvent::Settings(settings));
+
+                app.update();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, paint, relayout);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+
+use crate::{
+    grid::{Grid, GridEvent},
+    layer::{Layer, Layers},
+    view::MainCamera,
+};
+
+// Alpha of unpainted cells while they're see through
+pub const TRANSPARENT_CELL_ALPHA: f32 = 0.1;
+
+#[derive(Event)]
+pub enum BackgroundEvent {
+    // Path relative to the assets folder
+    Load(String),
+    Clear,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BackgroundEvent>()
+            .add_systems(Update, (on_background_event, calibrate));
+    }
+}
+
+#[derive(Component, Default)]
+pub struct Background {
+    pub path: String,
+    pub transparent_cells: bool,
+    // Hex centres clicked so far, Some while calibrating
+    pub calibration: Option<Vec<Vec2>>,
+    pub error: Option<String>,
+
+    sprite: Option<Entity>,
+    // Path of the image currently shown
+    loaded: Option<String>,
+}
+
+impl Background {
+    pub fn loaded(&self) -> Option<&str> {
+        self.loaded.as_deref()
+    }
+
+    pub fn is_calibrating(&self) -> bool {
+        self.calibration.is_some()
+    }
+}
+
+fn on_background_event(
+    mut events: EventReader<BackgroundEvent>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut background_q: Query<&mut Background>,
+) {
+    let mut background = background_q.single_mut();
+
+    for e in events.read() {
+        if let Some(sprite) = background.sprite.take() {
+            commands.entity(sprite).despawn_recursive();
+        }
+
+        match e {
+            BackgroundEvent::Load(path) => {
+                let sprite = commands
+                    .spawn((
+                        SpriteBundle {
+                            texture: asset_server.load(path.clone()),
+                            transform: Transform::from_translation(Vec3::Z * Layer::Background.z()),
+                            ..Default::default()
+                        },
+                        Layer::Background,
+                    ))
+                    .id();
+
+                background.sprite = Some(sprite);
+                background.path = path.clone();
+                background.loaded = Some(path.clone());
+            }
+            BackgroundEvent::Clear => {
+                background.path.clear();
+                background.loaded = None;
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn calibrate(
+    mut contexts: EguiContexts,
+    buttons: Res<Input<MouseButton>>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    cam_q: Query<(&Camera, &GlobalTransform), MainCamera>,
+    mut background_q: Query<&mut Background>,
+    grid_q: Query<&Grid>,
+    mut grid_event: EventWriter<GridEvent>,
+    layers_q: Query<&Layers>,
+) {
+    let mut background = background_q.single_mut();
+    if !background.is_calibrating()
+        || layers_q.single().is_locked(Layer::Background)
+        || !buttons.just_pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+
+    let (cam, cam_transform) = cam_q.single();
+    let Some(pos) = window_q
+        .single()
+        .cursor_position()
+        .and_then(|cursor| cam.viewport_to_world_2d(cam_transform, cursor))
+    else {
+        return;
+    };
+
+    let Some(points) = background.calibration.as_mut() else {
+        return;
+    };
+    points.push(pos);
+
+    if let [a, b, c] = points[..] {
+        background.calibration = None;
+        match grid_q.single().calibrate(&[a, b, c]) {
+            Ok(settings) => {
+                background.error = None;
+                grid_event.send(GridEvent::Settings(settings));
+            }
+            Err(e) => background.error = Some(e),
+        }
+    }
+}
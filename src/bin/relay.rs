@@ -0,0 +1,105 @@
+// Relay for multiplayer sessions, run by the GM on the LAN:
+// hexalon-relay [address] [GM key]
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    collections::HashMap,
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use hexalon::session::{pump, ClientId, ClientMessage, Relay, ServerMessage, DEFAULT_PORT};
+
+#[cfg(not(target_arch = "wasm32"))]
+type Clients = Arc<Mutex<HashMap<ClientId, mpsc::Sender<String>>>>;
+
+// Browsers can't listen for connections, so there's no relay on the web
+#[cfg(target_arch = "wasm32")]
+fn main() {}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let mut args = std::env::args().skip(1);
+    let address = args
+        .next()
+        .unwrap_or_else(|| format!("0.0.0.0:{}", DEFAULT_PORT));
+    let key = args.next();
+
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Couldn't listen on {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Relay listening on ws://{}", address);
+
+    let relay = Arc::new(Mutex::new(Relay::new(key)));
+    let clients = Clients::default();
+
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Couldn't accept a connection: {}", e);
+                continue;
+            }
+        };
+
+        let (relay, clients) = (relay.clone(), clients.clone());
+        thread::spawn(move || {
+            let id = id as ClientId;
+            if let Err(e) = serve(id, stream, &relay, &clients) {
+                log::warn!("Client {}: {}", id, e);
+            }
+
+            relay.lock().unwrap().leave(id);
+            clients.lock().unwrap().remove(&id);
+            log::info!("Client {} left", id);
+        });
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn serve(
+    id: ClientId,
+    stream: TcpStream,
+    relay: &Mutex<Relay>,
+    clients: &Clients,
+) -> Result<(), String> {
+    let mut socket = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+    socket
+        .get_mut()
+        .set_nonblocking(true)
+        .map_err(|e| e.to_string())?;
+    log::info!("Client {} connected", id);
+
+    let (outgoing, outgoing_rx) = mpsc::channel();
+    clients.lock().unwrap().insert(id, outgoing);
+
+    pump(&mut socket, &outgoing_rx, |text| {
+        let replies = match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(message) => {
+                if let ClientMessage::Join { role, .. } = &message {
+                    log::info!("Client {} joining as {}", id, role.name());
+                }
+                relay.lock().unwrap().handle(id, message)
+            }
+            Err(e) => vec![(id, ServerMessage::Rejected(format!("Bad message: {}", e)))],
+        };
+
+        let clients = clients.lock().unwrap();
+        for (to, reply) in replies {
+            match (clients.get(&to), serde_json::to_string(&reply)) {
+                (Some(client), Ok(text)) => {
+                    let _ = client.send(text);
+                }
+                (_, Err(e)) => log::error!("Couldn't send to client {}: {}", to, e),
+                (None, _) => {}
+            }
+        }
+    })
+}
@@ -0,0 +1,284 @@
+use bevy::{input::mouse::MouseWheel, prelude::*};
+use bevy_pancam::PanCam;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cell::Cell, grid::Grid, initiative_tracker::TrackerEvent, preferences::Preferences,
+    shape::GridShape, token::Token, view::MainCamera,
+};
+
+// How quickly the camera closes in on where it's going, higher is snappier
+const GLIDE_SPEED: f32 = 8.0;
+// Room left around the grid when zooming to fit
+const FIT_MARGIN: f32 = 1.1;
+
+// A place on the map the GM can go back to
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Viewpoint {
+    pub name: String,
+    pub centre: Vec2,
+    pub scale: f32,
+}
+
+// Saved with the preferences
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CameraSettings {
+    // Bounds of the projection scale, smaller is closer in
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Move to the creature whose turn it is
+    pub follow_turns: bool,
+    pub viewpoints: Vec<Viewpoint>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            min_scale: 0.1,
+            max_scale: 10.0,
+            follow_turns: true,
+            viewpoints: Vec::new(),
+        }
+    }
+}
+
+#[derive(Event)]
+pub enum CameraEvent {
+    // Shows the whole grid, or everything drawn on an unbounded one
+    ZoomToFit,
+    // Centres on a point of the map, keeping the zoom
+    CenterOn(Vec2),
+    // Centres on the token of the creature with the id
+    CenterOnToken(String),
+    // Goes to the saved viewpoint at the index
+    GoTo(usize),
+    // Saves where the camera is as a viewpoint with the name
+    SaveViewpoint(String),
+}
+
+#[derive(Component, Default)]
+pub struct CameraControl {
+    // Name typed in for the next viewpoint
+    pub viewpoint_name: String,
+    // Centre and scale the camera is gliding to
+    target: Option<(Vec2, f32)>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CameraEvent>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (
+                    follow_turns,
+                    on_camera_event,
+                    apply_zoom_limits,
+                    glide.after(on_camera_event),
+                ),
+            );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(CameraControl::default());
+}
+
+// World position at the centre of the camera's viewport, where things are placed when there's
+// nowhere else to put them
+pub fn view_centre(camera: &Camera, transform: &GlobalTransform) -> Option<Vec2> {
+    let size = camera.logical_viewport_size()?;
+    camera.viewport_to_world_2d(transform, size / 2.0)
+}
+
+// Area of the whole grid, or of everything drawn on an unbounded one
+pub fn map_bounds(grid: &Grid, cell_q: &Query<&Cell>) -> Option<Rect> {
+    match grid.shape {
+        GridShape::Unbounded => points_bounds(
+            grid,
+            grid.cells
+                .values()
+                .filter_map(|e| cell_q.get(*e).ok())
+                .chain(grid.stored_cells())
+                .filter(|c| !c.is_blank(grid))
+                .map(|c| grid.hex_coord_to_pos(&c.pos)),
+        ),
+        _ => shape_bounds(grid),
+    }
+}
+
+// Area taken up by every hex of a bounded grid, holes and all
+fn shape_bounds(grid: &Grid) -> Option<Rect> {
+    let edges = grid.shape.edge_coords(grid.settings.layout);
+    points_bounds(grid, edges.iter().map(|c| grid.hex_coord_to_pos(c)))
+}
+
+// Smallest rect holding whole hexes centred on the points
+fn points_bounds(grid: &Grid, points: impl Iterator<Item = Vec2>) -> Option<Rect> {
+    let rect = points.fold(None, |rect: Option<Rect>, p| {
+        Some(rect.map_or(Rect::from_center_size(p, Vec2::ZERO), |r| r.union_point(p)))
+    })?;
+    Some(rect.inset(grid.settings.hex_size + grid.settings.spacing))
+}
+
+// Centre and scale showing all of the bounds, for a view of the given size at scale 1
+fn fit_view(bounds: Rect, view: Vec2) -> (Vec2, f32) {
+    let scale = (bounds.size() / view).max_element() * FIT_MARGIN;
+    (bounds.center(), scale)
+}
+
+fn follow_turns(
+    mut events: EventReader<TrackerEvent>,
+    preferences_q: Query<&Preferences>,
+    mut camera_event: EventWriter<CameraEvent>,
+) {
+    for e in events.read() {
+        let TrackerEvent::TurnUpdate(creature) = e;
+        if preferences_q.single().camera.follow_turns {
+            camera_event.send(CameraEvent::CenterOnToken(creature.id.clone()));
+        }
+    }
+}
+
+fn on_camera_event(
+    mut events: EventReader<CameraEvent>,
+    mut control_q: Query<&mut CameraControl>,
+    mut preferences_q: Query<&mut Preferences>,
+    cam_q: Query<(&Transform, &OrthographicProjection), MainCamera>,
+    grid_q: Query<&Grid>,
+    cell_q: Query<&Cell>,
+    token_q: Query<&Token>,
+) {
+    let Ok((transform, projection)) = cam_q.get_single() else {
+        events.clear();
+        return;
+    };
+    let mut control = control_q.single_mut();
+    let grid = grid_q.single();
+    let centre = transform.translation.truncate();
+
+    for e in events.read() {
+        let target = match e {
+            CameraEvent::ZoomToFit => map_bounds(grid, &cell_q)
+                .map(|b| fit_view(b, projection.area.size() / projection.scale)),
+            CameraEvent::CenterOn(pos) => Some((*pos, projection.scale)),
+            CameraEvent::CenterOnToken(id) => token_q
+                .iter()
+                .find(|t| t.creature_id() == id)
+                .map(|t| (grid.hex_coord_to_pos(&t.coords), projection.scale)),
+            CameraEvent::GoTo(i) => preferences_q
+                .single()
+                .camera
+                .viewpoints
+                .get(*i)
+                .map(|v| (v.centre, v.scale)),
+            CameraEvent::SaveViewpoint(name) => {
+                preferences_q
+                    .single_mut()
+                    .camera
+                    .viewpoints
+                    .push(Viewpoint {
+                        name: name.clone(),
+                        centre,
+                        scale: projection.scale,
+                    });
+                None
+            }
+        };
+
+        if let Some((centre, scale)) = target {
+            let settings = &preferences_q.single().camera;
+            control.target = Some((centre, scale.clamp(settings.min_scale, settings.max_scale)));
+        }
+    }
+}
+
+// Keeps scrolling within the limits
+fn apply_zoom_limits(
+    preferences_q: Query<&Preferences, Changed<Preferences>>,
+    mut cam_q: Query<(&mut PanCam, &mut OrthographicProjection), MainCamera>,
+) {
+    let Ok(preferences) = preferences_q.get_single() else {
+        return;
+    };
+    let settings = &preferences.camera;
+
+    for (mut pancam, mut projection) in &mut cam_q {
+        pancam.min_scale = settings.min_scale;
+        pancam.max_scale = Some(settings.max_scale);
+
+        let scale = projection
+            .scale
+            .clamp(settings.min_scale, settings.max_scale);
+        if scale != projection.scale {
+            projection.scale = scale;
+        }
+    }
+}
+
+// Eases the camera towards its target, letting go as soon as the GM pans or zooms
+fn glide(
+    time: Res<Time>,
+    buttons: Res<Input<MouseButton>>,
+    mut wheel: EventReader<MouseWheel>,
+    mut control_q: Query<&mut CameraControl>,
+    mut cam_q: Query<(&mut Transform, &mut OrthographicProjection), MainCamera>,
+) {
+    let mut control = control_q.single_mut();
+    let scrolled = wheel.read().count() > 0;
+    let Some((centre, scale)) = control.target else {
+        return;
+    };
+    if scrolled || buttons.just_pressed(MouseButton::Middle) {
+        control.target = None;
+        return;
+    }
+    let Ok((mut transform, mut projection)) = cam_q.get_single_mut() else {
+        return;
+    };
+
+    let t = 1.0 - (-GLIDE_SPEED * time.delta_seconds()).exp();
+    let pos = transform.translation.truncate().lerp(centre, t);
+    let new_scale = projection.scale + (scale - projection.scale) * t;
+
+    // Close enough not to see the difference
+    if pos.distance(centre) < 0.5 * new_scale && (new_scale - scale).abs() < 1e-3 * scale {
+        transform.translation = centre.extend(transform.translation.z);
+        projection.scale = scale;
+        control.target = None;
+    } else {
+        transform.translation = pos.extend(transform.translation.z);
+        projection.scale = new_scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::GridSettings;
+
+    #[test]
+    fn fitting_shows_the_whole_grid() {
+        let grid = Grid::new(GridShape::Hexagon { radius: 3 }, GridSettings::default());
+        let bounds = shape_bounds(&grid).unwrap();
+        assert!(bounds.center().length() < 1e-3);
+
+        // Every hex is inside, corners and all
+        for coord in grid.shape.coords(grid.settings.layout) {
+            let pos = grid.hex_coord_to_pos(&coord);
+            let hex = Rect::from_center_size(pos, Vec2::splat(grid.settings.hex_size * 2.0));
+            assert_eq!(bounds.union(hex), bounds);
+        }
+
+        // A wide window fits the height
+        let view = Vec2::new(1600.0, 400.0);
+        let (centre, scale) = fit_view(bounds, view);
+        assert_eq!(centre, bounds.center());
+        assert!(view.y * scale >= bounds.height());
+        assert!((view.y * scale - bounds.height() * FIT_MARGIN).abs() < 1e-3);
+
+        let empty = Grid::new(GridShape::Unbounded, GridSettings::default());
+        assert_eq!(shape_bounds(&empty), None);
+    }
+}
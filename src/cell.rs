@@ -1,7 +1,25 @@
-use bevy::{math::vec4, prelude::*, sprite::MaterialMesh2dBundle};
-use bevy_mod_picking::{prelude::*, PickableBundle};
+use std::collections::{HashMap, HashSet};
 
-use crate::hex::HexCoord;
+use bevy::{
+    prelude::*,
+    render::view::RenderLayers,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    window::PrimaryWindow,
+};
+use bevy_egui::EguiContexts;
+use bevy_mod_picking::prelude::PointerButton;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grid::Grid,
+    hex::HexCoord,
+    layer::{Layer, Paint},
+    light::Light,
+    mesh::build_mesh,
+    texture::CellTexture,
+    token::Token,
+    view::{MainCamera, ViewMode},
+};
 
 lazy_static! {
     pub static ref HEX_COLOR: Color = Color::Rgba {
@@ -12,108 +30,222 @@ lazy_static! {
     };
 }
 
+// Laid over hovered cells and the cells a stroke would draw on
+const HINT_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.25);
+
 #[derive(Event)]
 pub enum CellEvent {
-    Pressed(Entity),
+    Pressed(Entity, PointerButton),
     // Distance from the starting cell
     Released(Vec2),
     Over(Entity),
+    // The pointer left the grid, or went over the UI
+    Out,
 }
 
+// Cells shown darkened, kept apart from the cells so hovering doesn't change the map
+#[derive(Component, Default)]
+pub struct Hints {
+    pub hovered: Option<HexCoord>,
+    // Cells the box or line stroke in progress would draw on
+    pub stroke: Vec<HexCoord>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CellEvent>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, pick_cells)
+            .add_systems(PostUpdate, sync_hints);
+    }
+}
+
+// Hints are only shown on the screen they're made on, never to the players
 #[derive(Component)]
+struct HintMesh;
+
+fn setup(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+    commands.spawn(Hints::default());
+    commands.spawn((
+        HintMesh,
+        MaterialMesh2dBundle {
+            material: materials.add(ColorMaterial::default()),
+            transform: Transform::from_translation(Vec3::Z * (Layer::Terrain.z() + 0.5)),
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        },
+        ViewMode::default().own_layer(),
+    ));
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Cell {
-    _size: f32,
     pub pos: HexCoord,
     pub color: Color,
+    // The color the cell is reset to when erased
+    pub base_color: Color,
+    pub texture: CellTexture,
+    pub base_texture: CellTexture,
+    // Index into the Palette
+    pub terrain: Option<usize>,
+    // Paint in the layers above the terrain
+    pub overlays: HashMap<Layer, Paint>,
+    // Light set on the cell, like a campfire or a brazier
+    pub light: Option<Light>,
+    // Name written on the cell, like a town's or a dungeon room's
+    pub label: Option<String>,
+    // Height above the ground in feet, for hills, ledges and pits
+    #[serde(default)]
+    pub elevation: i32,
+    // Only shown to the GM
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
 impl Cell {
-    pub fn create(
-        world_pos: Vec2,
-        size: f32,
-        pos: HexCoord,
-        mut commands: &mut Commands,
-        meshes: &mut ResMut<Assets<Mesh>>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
-    ) -> Entity {
-        let c = Cell {
-            _size: size,
+    pub fn new(grid: &Grid, pos: HexCoord) -> Self {
+        Cell::blank(pos, grid.blank_color)
+    }
+
+    // A cell nothing has been drawn on
+    pub fn blank(pos: HexCoord, color: Color) -> Self {
+        Cell {
             pos,
-            color: *HEX_COLOR,
-        };
-
-        let mesh = MaterialMesh2dBundle {
-            mesh: meshes
-                .add(Mesh::from(shape::RegularPolygon::new(size, 6)))
-                .into(),
-            material: materials.add((*HEX_COLOR).into()),
-            transform: Transform::default().with_translation(world_pos.extend(0.1)),
-            ..Default::default()
-        };
-
-        commands
-            .spawn((
-                mesh,
-                c,
-                RaycastPickTarget::default(),
-                PickableBundle::default(),
-                On::<Pointer<Over>>::run(on_hover_enter),
-                On::<Pointer<Out>>::run(on_hover_out),
-                On::<Pointer<DragStart>>::run(on_drag_start),
-                On::<Pointer<DragEnd>>::run(on_drag_end),
-            ))
-            .id()
+            color,
+            base_color: color,
+            texture: CellTexture::Solid,
+            base_texture: CellTexture::Solid,
+            terrain: None,
+            overlays: HashMap::new(),
+            light: None,
+            label: None,
+            elevation: 0,
+            note: None,
+        }
     }
-}
 
-fn on_hover_enter(
-    event: Listener<Pointer<Over>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    cell_q: Query<(&Cell, &Handle<ColorMaterial>)>,
-    mut cell_event: EventWriter<CellEvent>,
-) {
-    // Update the color of the cell
-    if let Ok((cell, mat)) = cell_q.get(event.target) {
-        let material = materials.get_mut(mat).unwrap();
-        material.color = cell.color + vec4(-0.2, -0.2, -0.2, 0.0);
+    // Cells hold no mesh of their own, they're drawn in chunks by the mesh plugin
+    pub fn create(grid: &Grid, pos: HexCoord, commands: &mut Commands) -> Entity {
+        commands.spawn(Cell::new(grid, pos)).id()
     }
 
-    cell_event.send(CellEvent::Over(event.target));
-}
+    // Nothing has been drawn on the cell
+    pub fn is_blank(&self, grid: &Grid) -> bool {
+        *self == Cell::new(grid, self.pos)
+    }
 
-fn on_hover_out(
-    event: Listener<Pointer<Out>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-    cell_q: Query<(&Cell, &Handle<ColorMaterial>)>,
-) {
-    // Revert the color of the cell
-    if let Ok((cell, mat)) = cell_q.get(event.target) {
-        let material = materials.get_mut(mat).unwrap();
-        material.color = cell.color;
+    // What's drawn for the cell in one of the painted layers
+    pub fn paint(&self, layer: Layer) -> Option<Paint> {
+        match layer {
+            Layer::Terrain => Some(Paint {
+                color: self.color,
+                texture: self.texture,
+            }),
+            _ => self.overlays.get(&layer).copied(),
+        }
     }
 }
 
-fn on_drag_start(event: Listener<Pointer<DragStart>>, mut cell_event: EventWriter<CellEvent>) {
-    if event.button != PointerButton::Primary {
+// Cells don't have meshes to pick, so the hex under the cursor is worked out from the grid
+#[allow(clippy::too_many_arguments)]
+fn pick_cells(
+    mut contexts: EguiContexts,
+    buttons: Res<Input<MouseButton>>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    cam_q: Query<(&Camera, &GlobalTransform), MainCamera>,
+    grid_q: Query<&Grid>,
+    token_q: Query<&Token>,
+    mut hints_q: Query<&mut Hints>,
+    mut cell_event: EventWriter<CellEvent>,
+    mut hovered: Local<Option<Entity>>,
+    // Where the pointer went down on the grid
+    mut press: Local<Option<Vec2>>,
+) {
+    let Ok(grid) = grid_q.get_single() else {
         return;
+    };
+    let (cam, cam_transform) = cam_q.single();
+    let over_ui = contexts.ctx_mut().wants_pointer_input();
+
+    let pos = window_q
+        .get_single()
+        .ok()
+        .and_then(|w| w.cursor_position())
+        .and_then(|cursor| cam.viewport_to_world_2d(cam_transform, cursor))
+        .filter(|_| !over_ui);
+    let coord = pos.map(|p| grid.pos_to_hex_coord(&p));
+    let cell = coord.and_then(|c| grid.get_cell(&c)).copied();
+
+    if cell != *hovered {
+        hints_q.single_mut().hovered = cell.and(coord);
+
+        if let Some(e) = cell {
+            cell_event.send(CellEvent::Over(e));
+        } else {
+            cell_event.send(CellEvent::Out);
+        }
+
+        *hovered = cell;
     }
 
-    cell_event.send(CellEvent::Pressed(event.target));
+    // Tokens take clicks on their own hex
+    let on_token = coord.is_some_and(|c| token_q.iter().any(|t| t.coords == c));
+
+    for (mouse, button) in [
+        (MouseButton::Left, PointerButton::Primary),
+        (MouseButton::Right, PointerButton::Secondary),
+    ] {
+        if buttons.just_pressed(mouse) && press.is_none() && !on_token {
+            if let (Some(e), Some(pos)) = (cell, pos) {
+                *press = Some(pos);
+                cell_event.send(CellEvent::Pressed(e, button));
+            }
+        }
+
+        if buttons.just_released(mouse) {
+            if let Some(start) = press.take() {
+                cell_event.send(CellEvent::Released(pos.unwrap_or(start) - start));
+            }
+        }
+    }
 }
 
-fn on_drag_end(
-    event: Listener<Pointer<DragEnd>>,
-    mut cell_event: EventWriter<CellEvent>,
-    cam_q: Query<&OrthographicProjection, With<Camera>>,
+fn sync_hints(
+    mut hint_q: Query<(&mut Mesh2dHandle, &mut Visibility, &mut RenderLayers), With<HintMesh>>,
+    grid_q: Query<Ref<Grid>>,
+    hints_q: Query<Ref<Hints>>,
+    mode: Res<State<ViewMode>>,
+    mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    if event.button != PointerButton::Primary {
+    let (Ok((mut handle, mut visibility, mut layers)), Ok(grid), Ok(hints)) = (
+        hint_q.get_single_mut(),
+        grid_q.get_single(),
+        hints_q.get_single(),
+    ) else {
+        return;
+    };
+    if *layers != mode.own_layer() {
+        *layers = mode.own_layer();
+    }
+    if !hints.is_changed() && !grid.is_changed() {
         return;
     }
 
-    let cam = cam_q.single();
-    let dst = Vec2 {
-        x: event.distance.x,
-        y: -event.distance.y,
-    } * cam.scale;
-    cell_event.send(CellEvent::Released(dst));
+    // Cut cells aren't hinted
+    let hinted: Vec<(HexCoord, Color)> = hints
+        .hovered
+        .iter()
+        .chain(&hints.stroke)
+        .filter(|c| grid.get_cell(c).is_some())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|c| (*c, HINT_COLOR))
+        .collect();
+    if hinted.is_empty() {
+        *visibility = Visibility::Hidden;
+        return;
+    }
+    *handle = meshes.add(build_mesh(&grid, &hinted)).into();
+    *visibility = Visibility::Inherited;
 }
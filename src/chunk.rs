@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::{cell::Cell, grid::Grid, hex::HexCoord, view::MainCamera};
+
+// Hexes along each axis of a chunk, cells are loaded and drawn a chunk at a time
+pub const CHUNK_SIZE: i32 = 16;
+// Furthest a loaded chunk can be from the one in the middle of the screen, so zooming far out
+// over an unbounded grid doesn't spawn millions of cells
+const MAX_CHUNK_DISTANCE: i32 = 16;
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct ChunkCoord {
+    pub q: i32,
+    pub r: i32,
+}
+
+impl ChunkCoord {
+    pub fn of(coord: &HexCoord) -> Self {
+        ChunkCoord {
+            q: coord.q.div_euclid(CHUNK_SIZE),
+            r: coord.r.div_euclid(CHUNK_SIZE),
+        }
+    }
+
+    // Every hex coord inside the chunk
+    pub fn coords(&self) -> impl Iterator<Item = HexCoord> {
+        let (q0, r0) = (self.q * CHUNK_SIZE, self.r * CHUNK_SIZE);
+        (r0..r0 + CHUNK_SIZE)
+            .flat_map(move |r| (q0..q0 + CHUNK_SIZE).map(move |q| HexCoord { q, r }))
+    }
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, stream_chunks);
+    }
+}
+
+// Chunks with any hex inside the area
+pub fn chunks_in_area(grid: &Grid, area: Rect) -> HashSet<ChunkCoord> {
+    // Hex coords are a linear map of world space, so the corners bound the whole area. One hex
+    // of slack covers hexes whose centres fall just outside it
+    let corners = [
+        area.min,
+        area.max,
+        Vec2::new(area.min.x, area.max.y),
+        Vec2::new(area.max.x, area.min.y),
+    ]
+    .map(|p| grid.pos_to_hex_coord(&p));
+    let min = ChunkCoord::of(&HexCoord {
+        q: corners.iter().map(|c| c.q).min().unwrap() - 1,
+        r: corners.iter().map(|c| c.r).min().unwrap() - 1,
+    });
+    let max = ChunkCoord::of(&HexCoord {
+        q: corners.iter().map(|c| c.q).max().unwrap() + 1,
+        r: corners.iter().map(|c| c.r).max().unwrap() + 1,
+    });
+
+    let centre = ChunkCoord::of(&grid.pos_to_hex_coord(&area.center()));
+    let (min_q, max_q) = (
+        min.q.max(centre.q - MAX_CHUNK_DISTANCE),
+        max.q.min(centre.q + MAX_CHUNK_DISTANCE),
+    );
+    let (min_r, max_r) = (
+        min.r.max(centre.r - MAX_CHUNK_DISTANCE),
+        max.r.min(centre.r + MAX_CHUNK_DISTANCE),
+    );
+
+    (min_r..=max_r)
+        .flat_map(|r| (min_q..=max_q).map(move |q| ChunkCoord { q, r }))
+        .collect()
+}
+
+// Loads the chunks around the camera and unloads those that have gone far off screen
+fn stream_chunks(
+    mut commands: Commands,
+    mut grid_q: Query<&mut Grid>,
+    cam_q: Query<(&OrthographicProjection, &GlobalTransform), MainCamera>,
+    cell_q: Query<&Cell>,
+) {
+    let (Ok(mut grid), Ok((projection, transform))) = (grid_q.get_single_mut(), cam_q.get_single())
+    else {
+        return;
+    };
+
+    let centre = transform.translation().truncate();
+    let size = projection.area.size();
+    let chunk_width =
+        CHUNK_SIZE as f32 * 3_f32.sqrt() * (grid.settings.hex_size + grid.settings.spacing);
+
+    // Chunks are kept a little further out than they're loaded so panning back and forth
+    // doesn't reload them every frame
+    let wanted = chunks_in_area(
+        &grid,
+        Rect::from_center_size(centre, size + Vec2::splat(chunk_width)),
+    );
+    let kept = chunks_in_area(
+        &grid,
+        Rect::from_center_size(centre, size + Vec2::splat(chunk_width * 2.0)),
+    );
+
+    let unload: Vec<ChunkCoord> = grid
+        .loaded
+        .iter()
+        .filter(|c| !kept.contains(c))
+        .copied()
+        .collect();
+    let load: Vec<ChunkCoord> = wanted
+        .into_iter()
+        .filter(|c| !grid.loaded.contains(c))
+        .collect();
+
+    for chunk in unload {
+        grid.unload_chunk(chunk, |e| cell_q.get(e).ok().cloned(), &mut commands);
+    }
+
+    for chunk in load {
+        grid.load_chunk(chunk, &mut commands);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::grid::tests::test_grid;
+
+    #[test]
+    fn chunks_cover_negative_coords() {
+        for coord in [
+            HexCoord { q: 0, r: 0 },
+            HexCoord { q: -1, r: -1 },
+            HexCoord {
+                q: -CHUNK_SIZE,
+                r: CHUNK_SIZE - 1,
+            },
+            HexCoord { q: 40, r: -17 },
+        ] {
+            let chunk = ChunkCoord::of(&coord);
+            assert!(chunk.coords().any(|c| c == coord));
+        }
+
+        assert_eq!(ChunkCoord::of(&HexCoord { q: -1, r: 0 }).q, -1);
+        assert_eq!(
+            ChunkCoord { q: 0, r: 0 }.coords().count(),
+            (CHUNK_SIZE * CHUNK_SIZE) as usize
+        );
+    }
+
+    #[test]
+    fn area_chunks_hold_every_hex_in_the_area() {
+        let grid = test_grid(10);
+        let area = Rect::new(-700.0, -300.0, 1200.0, 500.0);
+        let chunks = chunks_in_area(&grid, area);
+
+        for x in (-700..=1200).step_by(50) {
+            for y in (-300..=500).step_by(50) {
+                let coord = grid.pos_to_hex_coord(&Vec2::new(x as f32, y as f32));
+                assert!(chunks.contains(&ChunkCoord::of(&coord)));
+            }
+        }
+
+        // Zoomed far out only the chunks near the middle load
+        let huge = chunks_in_area(&grid, Rect::new(-1e7, -1e7, 1e7, 1e7));
+        let side = (MAX_CHUNK_DISTANCE * 2 + 1) as usize;
+        assert!(huge.len() <= side * side);
+    }
+}
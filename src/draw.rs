@@ -1,11 +1,20 @@
-use std::cmp;
+use std::collections::HashMap;
 
-use bevy::{math::vec4, prelude::*};
+use bevy::prelude::*;
+use bevy_mod_picking::prelude::PointerButton;
 
 use crate::{
-    cell::{Cell, CellEvent},
-    grid::Grid,
+    background::Background,
+    cell::{Cell, CellEvent, Hints},
+    fog::FogEvent,
+    grid::{Grid, GridEvent},
     hex::HexCoord,
+    history::History,
+    layer::{Layer, Layers, Paint},
+    light::Light,
+    terrain::Palette,
+    texture::CellTexture,
+    wall::DoorTool,
 };
 
 #[derive(PartialEq, Eq)]
@@ -15,6 +24,22 @@ pub enum DrawMode {
     Line,
 }
 
+#[derive(PartialEq, Eq)]
+pub enum DrawTool {
+    Paint,
+    Terrain,
+    // Resets cells back to their base color
+    Erase,
+    // Removes cells from the grid
+    Cut,
+}
+
+#[derive(PartialEq, Eq)]
+pub enum BoxShape {
+    Rect,
+    Parallelogram,
+}
+
 #[derive(Clone, Copy)]
 pub struct Selection {
     pub start: Entity,
@@ -22,220 +47,305 @@ pub struct Selection {
 }
 
 enum DrawColor {
-    Color(Color),
-    Hint,
+    Color(Color, CellTexture),
+    Terrain(usize, Color, CellTexture),
+    Erase,
+    Overlay(Layer, Paint),
+    ClearOverlay(Layer),
+    Light(Option<Light>),
 }
 
 #[derive(Component)]
 pub struct Draw {
     pub draw_mode: DrawMode,
+    pub tool: DrawTool,
+    pub box_shape: BoxShape,
     pub fill: bool,
     pub color: Color,
+    pub texture: CellTexture,
+    // Index into the Palette
+    pub terrain: usize,
+    // Placed on cells drawn on the lighting layer
+    pub light: Option<Light>,
 
-    start_cell: Option<Entity>,
-
-    last_hint: Vec<Entity>,
+    // Look of the selected terrain and the layer being drawn on, refreshed every frame
+    terrain_color: Color,
+    terrain_texture: CellTexture,
+    layer: Layer,
+    // Where the stroke started, kept as a coord as its cell may be unloaded mid stroke
+    start: Option<HexCoord>,
+    // Set while a right click stroke is in progress
+    erasing: bool,
+    // Cells marked by the cut tool, removed from the grid at the end of the frame
+    cut: Vec<HexCoord>,
+    // Cells drawn on the fog layer, revealed or hidden at the end of the frame
+    reveal: Vec<HexCoord>,
+    hide: Vec<HexCoord>,
+    // Cells touched by the stroke in progress, as they were before it
+    stroke: HashMap<HexCoord, Cell>,
 }
 
 impl Default for Draw {
     fn default() -> Self {
         Self {
             draw_mode: DrawMode::Cell,
+            tool: DrawTool::Terrain,
+            box_shape: BoxShape::Rect,
             fill: true,
             color: Color::BLUE,
-            start_cell: None,
-            last_hint: Vec::new(),
+            texture: CellTexture::Solid,
+            terrain: 0,
+            light: Some(Light::PRESETS[1].1),
+            terrain_color: Color::WHITE,
+            terrain_texture: CellTexture::Solid,
+            layer: Layer::Terrain,
+            start: None,
+            erasing: false,
+            cut: Vec::new(),
+            reveal: Vec::new(),
+            hide: Vec::new(),
+            stroke: HashMap::new(),
         }
     }
 }
 
 impl Draw {
-    fn reset_hints(
-        &mut self,
-        cell_q: &mut Query<(&mut Cell, &Handle<ColorMaterial>)>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
-    ) {
-        for cell in &self.last_hint {
-            let (c, _) = cell_q.get(*cell).unwrap();
-            Self::draw_cell_color(cell, DrawColor::Color(c.color), cell_q, materials);
-        }
-
-        self.last_hint.clear();
-    }
-
-    fn draw_box(
-        &mut self,
-        start: &Entity,
+    // Cells a box or line stroke from the start to the end cell draws on
+    fn stroke_cells(
+        &self,
+        start: HexCoord,
         end: &Entity,
-        cell_q: &mut Query<(&mut Cell, &Handle<ColorMaterial>)>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
+        cell_q: &Query<&mut Cell>,
         grid: &Grid,
-        hint: bool,
-    ) {
-        let (start_cell, _) = cell_q.get(*start).unwrap();
-        let (end_cell, _) = cell_q.get(*end).unwrap();
-        let start_pos = start_cell.pos;
-        let end_pos = end_cell.pos;
-
-        let cells = grid.get_cells_in_box(&start_pos, &end_pos);
+    ) -> Vec<Entity> {
+        let Ok(end) = cell_q.get(*end) else {
+            return Vec::new();
+        };
 
-        for cell in &cells {
-            self.draw_cell(cell, cell_q, materials, hint);
+        if self.draw_mode == DrawMode::Line {
+            return grid.get_cells_in_line(&start, &end.pos);
         }
 
+        let region = match self.box_shape {
+            BoxShape::Rect => grid.cells_in_rect(&start, &end.pos),
+            BoxShape::Parallelogram => grid.cells_in_parallelogram(&start, &end.pos),
+        };
+
         if self.fill {
-            let start_q = cmp::min(start_pos.q, end_pos.q);
-            let end_q = cmp::max(start_pos.q, end_pos.q);
-            let start_r = cmp::min(start_pos.r, end_pos.r);
-            let end_r = cmp::max(start_pos.r, end_pos.r);
-
-            for q in start_q..end_q {
-                for r in start_r..end_r {
-                    let pos = HexCoord { q, r };
-                    let c = grid.get_cell(&pos);
-                    if let Some(cell) = c {
-                        self.draw_cell(cell, cell_q, materials, hint);
-                    }
+            grid.get_cells(&region)
+        } else {
+            grid.get_cells(&Grid::get_outline(&region))
+        }
+    }
+
+    fn draw_cell(&mut self, cell: &Entity, cell_q: &mut Query<&mut Cell>) {
+        // Left click reveals the fog whatever the tool, right click hides it
+        if self.layer == Layer::Fog {
+            if let Ok(c) = cell_q.get(*cell) {
+                if self.erasing {
+                    self.hide.push(c.pos);
+                } else {
+                    self.reveal.push(c.pos);
                 }
             }
+            return;
         }
 
-        self.last_hint = cells;
-    }
-
-    fn draw_line(
-        &mut self,
-        start: &Entity,
-        end: &Entity,
-        cell_q: &mut Query<(&mut Cell, &Handle<ColorMaterial>)>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
-        grid: &Grid,
-        hint: bool,
-    ) {
-        let start = cell_q.get(*start).unwrap().0;
-        let end = cell_q.get(*end).unwrap().0;
+        if self.tool == DrawTool::Cut && !self.erasing {
+            if let Ok(c) = cell_q.get(*cell) {
+                self.cut.push(c.pos);
+            }
+            return;
+        }
 
-        let cells = grid.get_cells_in_line(&start.pos, &end.pos);
+        let color = if self.layer == Layer::Lighting {
+            match self.tool {
+                _ if self.erasing => DrawColor::Light(None),
+                DrawTool::Erase | DrawTool::Cut => DrawColor::Light(None),
+                DrawTool::Paint | DrawTool::Terrain => DrawColor::Light(self.light),
+            }
+        } else if self.layer != Layer::Terrain {
+            // The layers above only hold paint, terrain paints its look
+            match self.tool {
+                _ if self.erasing => DrawColor::ClearOverlay(self.layer),
+                DrawTool::Paint => DrawColor::Overlay(
+                    self.layer,
+                    Paint {
+                        color: self.color,
+                        texture: self.texture,
+                    },
+                ),
+                DrawTool::Terrain => DrawColor::Overlay(
+                    self.layer,
+                    Paint {
+                        color: self.terrain_color,
+                        texture: self.terrain_texture,
+                    },
+                ),
+                DrawTool::Erase | DrawTool::Cut => DrawColor::ClearOverlay(self.layer),
+            }
+        } else if self.erasing {
+            DrawColor::Erase
+        } else {
+            match self.tool {
+                DrawTool::Paint => DrawColor::Color(self.color, self.texture),
+                DrawTool::Terrain => {
+                    DrawColor::Terrain(self.terrain, self.terrain_color, self.terrain_texture)
+                }
+                DrawTool::Erase | DrawTool::Cut => DrawColor::Erase,
+            }
+        };
 
-        for cell in &cells {
-            self.draw_cell(cell, cell_q, materials, hint);
+        if let Ok(c) = cell_q.get(*cell) {
+            self.stroke.entry(c.pos).or_insert_with(|| c.clone());
         }
-
-        self.last_hint = cells;
+        Self::draw_cell_color(cell, color, cell_q);
     }
 
-    fn draw_cell(
-        &self,
-        cell: &Entity,
-        cell_q: &mut Query<(&mut Cell, &Handle<ColorMaterial>)>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
-        hint: bool,
-    ) {
-        Self::draw_cell_color(
-            cell,
-            if hint {
-                DrawColor::Hint
-            } else {
-                DrawColor::Color(self.color)
-            },
-            cell_q,
-            materials,
-        );
+    // Hands the cells the stroke changed to the history
+    fn end_stroke(&mut self, grid: &Grid, cell_q: &Query<&mut Cell>, history: &mut History) {
+        let changes = self
+            .stroke
+            .drain()
+            .filter_map(|(pos, before)| {
+                let after = cell_q.get(*grid.get_cell(&pos)?).ok()?;
+                Some((before, after.clone()))
+            })
+            .collect();
+        history.record(changes);
     }
 
-    fn draw_cell_color(
-        cell: &Entity,
-        color: DrawColor,
-        cell_q: &mut Query<(&mut Cell, &Handle<ColorMaterial>)>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
-    ) {
-        let (mut cell, mat) = cell_q.get_mut(*cell).unwrap();
-        let mat = materials.get_mut(mat).unwrap();
+    fn draw_cell_color(cell: &Entity, color: DrawColor, cell_q: &mut Query<&mut Cell>) {
+        let Ok(mut cell) = cell_q.get_mut(*cell) else {
+            return;
+        };
 
         match color {
-            DrawColor::Color(c) => {
-                mat.color = c;
+            DrawColor::Color(c, texture) => {
                 cell.color = c;
+                cell.texture = texture;
             }
-            DrawColor::Hint => mat.color = cell.color + vec4(-0.2, -0.2, -0.2, 0.0),
+            DrawColor::Terrain(t, c, texture) => {
+                cell.color = c;
+                cell.base_color = c;
+                cell.texture = texture;
+                cell.base_texture = texture;
+                cell.terrain = Some(t);
+            }
+            DrawColor::Erase => {
+                cell.color = cell.base_color;
+                cell.texture = cell.base_texture;
+            }
+            DrawColor::Overlay(layer, paint) => {
+                cell.overlays.insert(layer, paint);
+            }
+            DrawColor::ClearOverlay(layer) => {
+                cell.overlays.remove(&layer);
+            }
+            DrawColor::Light(light) => cell.light = light,
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn on_draw(
     mut draw_event: EventReader<CellEvent>,
     mut draw_q: Query<&mut Draw>,
-    mut cell_q: Query<(&mut Cell, &Handle<ColorMaterial>)>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut cell_q: Query<&mut Cell>,
     grid_q: Query<&Grid>,
+    palette_q: Query<&Palette>,
+    background_q: Query<&Background>,
+    door_tool_q: Query<&DoorTool>,
+    layers_q: Query<&Layers>,
+    mut hints_q: Query<&mut Hints>,
+    mut history_q: Query<&mut History>,
+    mut grid_event: EventWriter<GridEvent>,
+    mut fog_event: EventWriter<FogEvent>,
 ) {
+    let layers = layers_q.single();
+
+    // Clicks are picking hex centres or doors instead, or there's nothing to draw on
+    if background_q.single().is_calibrating()
+        || door_tool_q.single().active
+        || !layers.selected.is_drawable()
+        || layers.is_locked(layers.selected)
+    {
+        draw_event.clear();
+        return;
+    }
+
     let mut draw = draw_q.single_mut();
+    let mut hints = hints_q.single_mut();
+    draw.layer = layers.selected;
     let grid = grid_q.single();
+    let palette = palette_q.single();
+
+    if let Some(terrain) = palette.get(Some(draw.terrain)) {
+        draw.terrain_color = terrain.color;
+        draw.terrain_texture = terrain.texture;
+    }
 
     for event in draw_event.iter() {
         match event {
-            CellEvent::Pressed(cell) => {
-                draw.start_cell = Some(*cell);
+            CellEvent::Pressed(cell, button) => {
+                draw.start = cell_q.get(*cell).ok().map(|c| c.pos);
+                draw.erasing = *button == PointerButton::Secondary;
 
                 if draw.draw_mode == DrawMode::Cell {
-                    draw.draw_cell(cell, &mut cell_q, &mut materials, false);
+                    draw.draw_cell(cell, &mut cell_q);
                 }
             }
             CellEvent::Released(distance) => {
                 // Get end cell
-                draw.reset_hints(&mut cell_q, &mut materials);
-                if let Some(start_entity) = draw.start_cell {
-                    let (start_cell, _) = cell_q.get(start_entity).unwrap();
-                    let c = grid.hex_coord_to_pos(&start_cell.pos) + *distance;
-
-                    let end_cell = grid.get_cell(&grid.pos_to_hex_coord(&c));
-                    if let Some(end_cell) = end_cell {
-                        if draw.draw_mode == DrawMode::Line {
-                            draw.draw_line(
-                                &start_entity,
-                                end_cell,
-                                &mut cell_q,
-                                &mut materials,
-                                grid,
-                                false,
-                            );
-                            draw.last_hint = Vec::new();
-                        } else if draw.draw_mode == DrawMode::Box {
-                            draw.draw_box(
-                                &start_entity,
-                                end_cell,
-                                &mut cell_q,
-                                &mut materials,
-                                grid,
-                                false,
-                            );
+                if !hints.stroke.is_empty() {
+                    hints.stroke.clear();
+                }
+                if let Some(start_pos) = draw.start.filter(|_| draw.draw_mode != DrawMode::Cell) {
+                    let c = grid.hex_coord_to_pos(&start_pos) + *distance;
+
+                    if let Some(end_cell) = grid.get_cell(&grid.pos_to_hex_coord(&c)) {
+                        for cell in draw.stroke_cells(start_pos, end_cell, &cell_q, grid) {
+                            draw.draw_cell(&cell, &mut cell_q);
                         }
                     }
                 }
 
-                draw.start_cell = None;
+                draw.end_stroke(grid, &cell_q, &mut history_q.single_mut());
+                draw.start = None;
+                draw.erasing = false;
             }
+            CellEvent::Out => {}
             CellEvent::Over(cell) => match draw.draw_mode {
                 DrawMode::Cell => {
-                    if draw.start_cell.is_some() {
-                        draw.draw_cell(cell, &mut cell_q, &mut materials, false)
+                    if draw.start.is_some() {
+                        draw.draw_cell(cell, &mut cell_q)
                     }
                 }
-                DrawMode::Box => {
-                    // Draw hints
-                    draw.reset_hints(&mut cell_q, &mut materials);
-                    if let Some(start_cell) = draw.start_cell {
-                        draw.draw_box(&start_cell, cell, &mut cell_q, &mut materials, grid, true);
-                    }
-                }
-                DrawMode::Line => {
-                    // Draw hints
-                    draw.reset_hints(&mut cell_q, &mut materials);
-                    if let Some(start_cell) = draw.start_cell {
-                        draw.draw_line(&start_cell, cell, &mut cell_q, &mut materials, grid, true);
+                DrawMode::Box | DrawMode::Line => {
+                    // Show the cells the stroke would draw on
+                    let Some(start) = draw.start else {
+                        continue;
+                    };
+                    let preview: Vec<HexCoord> = draw
+                        .stroke_cells(start, cell, &cell_q, grid)
+                        .into_iter()
+                        .filter_map(|e| cell_q.get(e).ok().map(|c| c.pos))
+                        .collect();
+                    if hints.stroke != preview {
+                        hints.stroke = preview;
                     }
                 }
             },
         }
     }
+
+    if !draw.cut.is_empty() {
+        grid_event.send(GridEvent::RemoveCells(std::mem::take(&mut draw.cut)));
+    }
+    if !draw.reveal.is_empty() {
+        fog_event.send(FogEvent::Reveal(std::mem::take(&mut draw.reveal)));
+    }
+    if !draw.hide.is_empty() {
+        fog_event.send(FogEvent::Hide(std::mem::take(&mut draw.hide)));
+    }
 }
@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use resvg::{tiny_skia, usvg};
+
+use crate::{
+    cell::Cell,
+    grid::Grid,
+    hex::HexCoord,
+    hexmap::HexMap,
+    layer::Layer,
+    mesh::hex_corners,
+    shape::GridShape,
+    terrain::Palette,
+    token::Token,
+    wall::{WallKind, Walls},
+};
+
+// Labels are drawn with the font the app uses, so exports look the same everywhere
+const FONT: &[u8] = include_bytes!("../assets/fonts/Roboto-Regular.ttf");
+const FONT_FAMILY: &str = "Roboto";
+const OUTLINE_COLOR: &str = "#404040";
+const WALL_COLOR: &str = "#000000";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ExportOptions {
+    // Output pixels per world unit, 1 is the map at its size without zoom
+    pub scale: f32,
+    // Space around the map, in output pixels
+    pub margin: f32,
+    pub tokens: bool,
+    // Axial coordinates on every cell
+    pub coordinates: bool,
+    // GM notes and hidden tokens
+    pub gm_only: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            margin: 20.0,
+            tokens: true,
+            coordinates: false,
+            gm_only: false,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    Svg,
+    Png,
+    // Terrains and labels for other hex tools, see hexmap
+    HexJson,
+    HexCsv,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Svg => "svg",
+            ExportFormat::Png => "png",
+            ExportFormat::HexJson => "json",
+            ExportFormat::HexCsv => "csv",
+        }
+    }
+}
+
+#[derive(Event)]
+pub struct ExportEvent(pub ExportFormat);
+
+#[derive(Component)]
+pub struct Export {
+    // Without the extension, which comes from the format
+    pub path: String,
+    pub options: ExportOptions,
+    // Path written to by the last export, or why it failed
+    pub result: Option<Result<String, String>>,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            path: "map".to_string(),
+            options: ExportOptions::default(),
+            result: None,
+        }
+    }
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportEvent>()
+            .add_systems(Update, on_export_event);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn stroke(color: Color) -> String {
+    let [r, g, b, a] = color.as_rgba_u8();
+    format!(
+        "stroke=\"#{:02x}{:02x}{:02x}\" stroke-opacity=\"{:.3}\"",
+        r,
+        g,
+        b,
+        a as f32 / 255.0
+    )
+}
+
+fn fill(color: Color) -> String {
+    let [r, g, b, a] = color.as_rgba_u8();
+    format!(
+        "fill=\"#{:02x}{:02x}{:02x}\" fill-opacity=\"{:.3}\"",
+        r,
+        g,
+        b,
+        a as f32 / 255.0
+    )
+}
+
+// The map as an SVG drawn straight from the hex geometry, textures are drawn as plain color
+pub fn to_svg(
+    grid: &Grid,
+    cells: &[Cell],
+    tokens: &[Token],
+    palette: &Palette,
+    walls: &Walls,
+    options: &ExportOptions,
+) -> String {
+    let cells: HashMap<HexCoord, &Cell> = cells.iter().map(|c| (c.pos, c)).collect();
+    // Unbounded grids only go as far as they've been drawn on
+    let mut coords: Vec<HexCoord> = match grid.shape {
+        GridShape::Unbounded => cells.keys().copied().collect(),
+        _ => grid
+            .shape
+            .coords(grid.settings.layout)
+            .into_iter()
+            .filter(|c| grid.contains(c))
+            .collect(),
+    };
+    coords.sort_by_key(|c| (c.r, c.q));
+    // The edges of bounded shapes are enough to find how far they reach
+    let edges = match grid.shape {
+        GridShape::Unbounded => coords.clone(),
+        _ => grid.shape.edge_coords(grid.settings.layout),
+    };
+
+    let corners = hex_corners(grid);
+    let (min, max) = edges
+        .iter()
+        .flat_map(|c| corners.map(|p| grid.hex_coord_to_pos(c) + p))
+        .fold(
+            (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        );
+    let size = if coords.is_empty() {
+        Vec2::ZERO
+    } else {
+        max - min
+    };
+    let (width, height) = (
+        (size.x * options.scale + options.margin * 2.0).ceil(),
+        (size.y * options.scale + options.margin * 2.0).ceil(),
+    );
+
+    // SVG has y pointing down
+    let point = |p: Vec2| {
+        Vec2::new(
+            (p.x - min.x) * options.scale + options.margin,
+            (max.y - p.y) * options.scale + options.margin,
+        )
+    };
+    let polygon = |c: &HexCoord| {
+        let centre = grid.hex_coord_to_pos(c);
+        corners
+            .iter()
+            .map(|p| {
+                let p = point(centre + *p);
+                format!("{:.2},{:.2}", p.x, p.y)
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let font_size = |size: f32| grid.settings.hex_size * size * options.scale;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\" font-family=\"{}\">\n",
+        FONT_FAMILY,
+        w = width,
+        h = height,
+    );
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>\n");
+
+    svg.push_str(&format!(
+        "<g stroke=\"{}\" stroke-width=\"{:.2}\">\n",
+        OUTLINE_COLOR, options.scale
+    ));
+    let mut overlays = vec![Layer::Objects];
+    if options.gm_only {
+        overlays.push(Layer::GmNotes);
+    }
+    for c in &coords {
+        let cell = cells.get(c);
+        let points = polygon(c);
+        let color = cell.map_or(grid.blank_color, |cell| cell.color);
+        svg.push_str(&format!(
+            "<polygon points=\"{}\" {}/>\n",
+            points,
+            fill(color)
+        ));
+
+        for paint in overlays
+            .iter()
+            .filter_map(|l| cell.and_then(|cell| cell.overlays.get(l)))
+        {
+            svg.push_str(&format!(
+                "<polygon points=\"{}\" {} stroke=\"none\"/>\n",
+                points,
+                fill(paint.color)
+            ));
+        }
+    }
+    svg.push_str("</g>\n");
+
+    // Walls get a heavier outline so they stand out in print
+    svg.push_str(&format!(
+        "<g fill=\"none\" stroke=\"{}\" stroke-width=\"{:.2}\">\n",
+        WALL_COLOR,
+        options.scale * 3.0
+    ));
+    for c in &coords {
+        let wall = cells
+            .get(c)
+            .and_then(|cell| palette.get(cell.terrain))
+            .is_some_and(|t| t.blocks_movement);
+        if wall {
+            svg.push_str(&format!("<polygon points=\"{}\"/>\n", polygon(c)));
+        }
+    }
+
+    // Walls along hex sides, doors dashed so they can be told apart
+    for (edge, kind) in walls.iter() {
+        let (a, b) = edge.ends(grid);
+        let (a, b) = (point(a), point(b));
+        let dash = match kind {
+            WallKind::Wall => String::new(),
+            WallKind::Door { .. } => format!(" stroke-dasharray=\"{:.2}\"", options.scale * 4.0),
+        };
+        svg.push_str(&format!(
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" {}{}/>\n",
+            a.x,
+            a.y,
+            b.x,
+            b.y,
+            stroke(kind.color()),
+            dash
+        ));
+    }
+    svg.push_str("</g>\n");
+
+    if options.coordinates {
+        svg.push_str(&format!(
+            "<g font-size=\"{:.2}\" text-anchor=\"middle\" fill=\"{}\">\n",
+            font_size(0.3),
+            OUTLINE_COLOR
+        ));
+        for c in &coords {
+            let p = point(grid.hex_coord_to_pos(c));
+            svg.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\">{},{}</text>\n",
+                p.x,
+                p.y + font_size(0.6),
+                c.q,
+                c.r
+            ));
+        }
+        svg.push_str("</g>\n");
+    }
+
+    let labels: Vec<(&HexCoord, &str)> = coords
+        .iter()
+        .filter_map(|c| Some((c, cells.get(c)?.label.as_deref()?)))
+        .collect();
+    if !labels.is_empty() {
+        svg.push_str(&format!(
+            "<g font-size=\"{:.2}\" text-anchor=\"middle\">\n",
+            font_size(0.3)
+        ));
+        for (c, label) in labels {
+            let p = point(grid.hex_coord_to_pos(c));
+            svg.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>\n",
+                p.x,
+                p.y + font_size(0.65),
+                escape(label)
+            ));
+        }
+        svg.push_str("</g>\n");
+    }
+
+    if options.tokens {
+        svg.push_str(&format!(
+            "<g font-size=\"{:.2}\" text-anchor=\"middle\">\n",
+            font_size(0.3)
+        ));
+        for token in tokens
+            .iter()
+            .filter(|t| (options.gm_only || !t.hidden) && grid.contains(&t.coords))
+        {
+            let p = point(grid.hex_coord_to_pos(&token.coords));
+            svg.push_str(&format!(
+                "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" {} stroke=\"{}\"/>\n",
+                p.x,
+                p.y,
+                grid.settings.hex_size * 0.7 * options.scale,
+                fill(token.color()),
+                WALL_COLOR,
+            ));
+            svg.push_str(&format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\">{}</text>\n",
+                p.x,
+                p.y + font_size(0.1),
+                escape(token.name())
+            ));
+        }
+        svg.push_str("</g>\n");
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// The SVG rasterized on the CPU
+pub fn to_png(
+    grid: &Grid,
+    cells: &[Cell],
+    tokens: &[Token],
+    palette: &Palette,
+    walls: &Walls,
+    options: &ExportOptions,
+) -> Result<Vec<u8>, String> {
+    let svg = to_svg(grid, cells, tokens, palette, walls, options);
+
+    let mut usvg_options = usvg::Options::default();
+    usvg_options.fontdb_mut().load_font_data(FONT.to_vec());
+    let tree = usvg::Tree::from_str(&svg, &usvg_options).map_err(|e| e.to_string())?;
+
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or("The map is too large to export")?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+fn on_export_event(
+    mut events: EventReader<ExportEvent>,
+    mut export_q: Query<&mut Export>,
+    grid_q: Query<&Grid>,
+    cell_q: Query<&Cell>,
+    token_q: Query<&Token>,
+    palette_q: Query<&Palette>,
+    walls_q: Query<&Walls>,
+) {
+    for ExportEvent(format) in events.read() {
+        let mut export = export_q.single_mut();
+        let grid = grid_q.single();
+
+        let cells: Vec<Cell> = grid
+            .cells
+            .values()
+            .filter_map(|e| cell_q.get(*e).ok())
+            .chain(grid.stored_cells())
+            .cloned()
+            .collect();
+        let tokens: Vec<Token> = token_q.iter().cloned().collect();
+        let (palette, walls, options) = (palette_q.single(), walls_q.single(), &export.options);
+
+        let data = match format {
+            ExportFormat::Svg => {
+                Ok(to_svg(grid, &cells, &tokens, palette, walls, options).into_bytes())
+            }
+            ExportFormat::Png => to_png(grid, &cells, &tokens, palette, walls, options),
+            ExportFormat::HexJson => HexMap::capture(grid, &cells, palette)
+                .to_json()
+                .map(String::into_bytes),
+            ExportFormat::HexCsv => {
+                Ok(HexMap::capture(grid, &cells, palette).to_csv().into_bytes())
+            }
+        };
+        let path = format!("{}.{}", export.path, format.extension());
+        export.result = Some(
+            data.and_then(|data| std::fs::write(&path, data).map_err(|e| e.to_string()))
+                .map(|()| path),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{grid::tests::test_grid, token::TokenType, wall::Edge};
+
+    fn map() -> (Grid, Vec<Cell>, Vec<Token>, Palette, Walls) {
+        let grid = test_grid(3);
+        let palette = Palette::default();
+        let wall = palette.find("Wall");
+
+        let mut cell = Cell::new(&grid, HexCoord { q: 0, r: 0 });
+        cell.terrain = wall;
+        cell.color = palette.get(wall).unwrap().color;
+        cell.label = Some("Keep".to_string());
+
+        let mut hidden = Token::new(
+            "g",
+            "Goblin",
+            TokenType::Enemy,
+            &HexCoord { q: 0, r: 1 },
+            &Color::RED,
+        );
+        hidden.hidden = true;
+        let tokens = vec![
+            Token::new(
+                "a",
+                "Bob & <Co>",
+                TokenType::Party,
+                &HexCoord { q: 1, r: 0 },
+                &Color::BLUE,
+            ),
+            hidden,
+        ];
+
+        let mut walls = Walls::default();
+        let origin = HexCoord { q: 0, r: 0 };
+        walls.set(
+            Edge::new(origin, HexCoord { q: 0, r: -1 }),
+            Some(WallKind::Wall),
+        );
+        walls.set(
+            Edge::new(origin, HexCoord { q: -1, r: 0 }),
+            Some(WallKind::Door { open: false }),
+        );
+
+        (grid, vec![cell], tokens, palette, walls)
+    }
+
+    #[test]
+    fn svg_has_every_cell_and_no_secrets() {
+        let (grid, cells, tokens, palette, walls) = map();
+        let options = ExportOptions {
+            coordinates: true,
+            ..Default::default()
+        };
+        let svg = to_svg(&grid, &cells, &tokens, &palette, &walls, &options);
+
+        // Every cell, and the wall outlined again
+        assert_eq!(svg.matches("<polygon").count(), 9 + 1);
+        // The wall and the door along their sides, only the door dashed
+        assert_eq!(svg.matches("<line").count(), 2);
+        assert_eq!(svg.matches("stroke-dasharray").count(), 1);
+        assert!(svg.contains(">0,0</text>"));
+        assert!(svg.contains("Bob &amp; &lt;Co&gt;"));
+        assert!(svg.contains(">Keep</text>"));
+        assert!(!svg.contains("Goblin"));
+
+        let gm = to_svg(
+            &grid,
+            &cells,
+            &tokens,
+            &palette,
+            &walls,
+            &ExportOptions {
+                gm_only: true,
+                ..Default::default()
+            },
+        );
+        assert!(gm.contains("Goblin"));
+        assert!(!gm.contains(">0,0</text>"));
+    }
+
+    #[test]
+    fn png_matches_the_svg_size() {
+        let (grid, cells, tokens, palette, walls) = map();
+        let options = ExportOptions {
+            scale: 2.0,
+            margin: 10.0,
+            ..Default::default()
+        };
+        let svg = to_svg(&grid, &cells, &tokens, &palette, &walls, &options);
+        let png = to_png(&grid, &cells, &tokens, &palette, &walls, &options).unwrap();
+
+        assert_eq!(&png[1..4], b"PNG");
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+        assert!(svg.contains(&format!("width=\"{}\" height=\"{}\"", width, height)));
+        assert!(width > 20 && height > 20);
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    prelude::*,
+    render::view::RenderLayers,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cell::Cell,
+    chunk::ChunkCoord,
+    grid::{Grid, GridSettings},
+    hex::HexCoord,
+    layer::{Layer, Layers},
+    light::{update_lighting, LightMap},
+    mesh::build_mesh,
+    pathfinding::line_of_sight,
+    shape::GridShape,
+    terrain::Palette,
+    token::Token,
+    view::{GM_ONLY, PLAYER_ONLY},
+    wall::Walls,
+};
+
+lazy_static! {
+    static ref HIDDEN_COLOR: Color = Color::BLACK;
+    static ref EXPLORED_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+}
+
+#[derive(Event)]
+pub enum FogEvent {
+    Reveal(Vec<HexCoord>),
+    Hide(Vec<HexCoord>),
+    // Covers the whole map again
+    Reset,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FogState {
+    Hidden,
+    // Seen before but out of sight now, drawn dimmed
+    Explored,
+    Visible,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct Fog {
+    pub enabled: bool,
+    // Revealed by hand, stays visible until hidden again
+    revealed: HashSet<HexCoord>,
+    // Seen by the party at some point
+    explored: HashSet<HexCoord>,
+    // In sight of the party right now
+    visible: HashSet<HexCoord>,
+    // Chunks whose fog changed since it was last drawn
+    #[serde(skip)]
+    dirty: HashSet<ChunkCoord>,
+}
+
+impl Fog {
+    pub fn state(&self, coord: &HexCoord) -> FogState {
+        if !self.enabled || self.revealed.contains(coord) || self.visible.contains(coord) {
+            FogState::Visible
+        } else if self.explored.contains(coord) {
+            FogState::Explored
+        } else {
+            FogState::Hidden
+        }
+    }
+
+    fn mark<'a>(&mut self, coords: impl IntoIterator<Item = &'a HexCoord>) {
+        self.dirty.extend(coords.into_iter().map(ChunkCoord::of));
+    }
+
+    pub fn reveal(&mut self, coords: &[HexCoord]) {
+        self.revealed.extend(coords.iter().copied());
+        self.mark(coords);
+    }
+
+    // Hidden cells are forgotten too, the party has to see them again
+    pub fn hide(&mut self, coords: &[HexCoord]) {
+        for c in coords {
+            self.revealed.remove(c);
+            self.explored.remove(c);
+        }
+        self.mark(coords);
+    }
+
+    // Everything the party can't see right now is covered again
+    pub fn reset(&mut self) {
+        let coords: Vec<HexCoord> = self.revealed.drain().chain(self.explored.drain()).collect();
+        self.mark(&coords);
+        self.explored.extend(self.visible.iter().copied());
+    }
+
+    // Takes on the fog of another map
+    pub fn replace(&mut self, other: &Fog) {
+        let coords: Vec<HexCoord> = [&self.revealed, &self.explored, &self.visible]
+            .into_iter()
+            .chain([&other.revealed, &other.explored, &other.visible])
+            .flatten()
+            .copied()
+            .collect();
+        self.mark(&coords);
+
+        self.enabled = other.enabled;
+        self.revealed = other.revealed.clone();
+        self.explored = other.explored.clone();
+        self.visible = other.visible.clone();
+    }
+
+    // Replaces what the party can see, everything seen before stays explored
+    pub fn set_visible(&mut self, visible: HashSet<HexCoord>) {
+        let changed: Vec<HexCoord> = self
+            .visible
+            .symmetric_difference(&visible)
+            .copied()
+            .collect();
+        self.mark(&changed);
+        self.explored.extend(visible.iter().copied());
+        self.visible = visible;
+    }
+}
+
+// The fog meshes, a pair for each chunk with any fog in it
+#[derive(Component, Default)]
+struct FogMeshes {
+    // The GM's see through fog and the players' opaque fog, sharing one mesh
+    meshes: HashMap<ChunkCoord, [Entity; 2]>,
+    // Loaded chunks whose fog has been built, whether or not it needed a mesh
+    drawn: HashSet<ChunkCoord>,
+    settings: Option<GridSettings>,
+    enabled: bool,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FogEvent>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, (on_fog_event, update_vision.after(update_lighting)))
+            .add_systems(PostUpdate, sync_fog);
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(FogMeshes::default());
+}
+
+// Cells within radius of origin that can be seen from it, past neither blocking cells nor walls
+pub fn field_of_view(
+    grid: &Grid,
+    origin: &HexCoord,
+    radius: i32,
+    blocks: impl Fn(&HexCoord) -> bool,
+    walls: &Walls,
+) -> HashSet<HexCoord> {
+    GridShape::Hexagon { radius }
+        .coords(grid.settings.layout)
+        .iter()
+        .map(|c| origin + c)
+        .filter(|c| {
+            grid.contains(c) && line_of_sight(origin, c, &blocks) && !walls.blocks_line(origin, c)
+        })
+        .collect()
+}
+
+fn on_fog_event(mut events: EventReader<FogEvent>, mut fog_q: Query<&mut Fog>) {
+    let mut fog = fog_q.single_mut();
+
+    for event in events.read() {
+        match event {
+            FogEvent::Reveal(coords) => fog.reveal(coords),
+            FogEvent::Hide(coords) => fog.hide(coords),
+            FogEvent::Reset => fog.reset(),
+        }
+    }
+}
+
+// Reveals what the party tokens can see whenever they, the grid, its terrain, walls or lighting
+// change
+#[allow(clippy::too_many_arguments)]
+fn update_vision(
+    mut fog_q: Query<&mut Fog>,
+    light_map_q: Query<Ref<LightMap>>,
+    grid_q: Query<Ref<Grid>>,
+    walls_q: Query<Ref<Walls>>,
+    palette_q: Query<Ref<Palette>>,
+    token_q: Query<&Token>,
+    moved_q: Query<(), Changed<Token>>,
+    changed_q: Query<(), Changed<Cell>>,
+    mut removed: RemovedComponents<Token>,
+    cell_q: Query<&Cell>,
+) {
+    let (Ok(mut fog), Ok(light_map), Ok(grid), Ok(walls), Ok(palette)) = (
+        fog_q.get_single_mut(),
+        light_map_q.get_single(),
+        grid_q.get_single(),
+        walls_q.get_single(),
+        palette_q.get_single(),
+    ) else {
+        return;
+    };
+
+    let removed = removed.read().count() > 0;
+    if !grid.is_changed()
+        && !walls.is_changed()
+        && !palette.is_changed()
+        && !light_map.is_changed()
+        && moved_q.is_empty()
+        && changed_q.is_empty()
+        && !removed
+    {
+        return;
+    }
+
+    let blocks = |c: &HexCoord| {
+        let terrain = grid
+            .lookup_cell(c, |e| cell_q.get(e).ok())
+            .and_then(|c| c.terrain);
+        palette.blocks_sight(terrain)
+    };
+
+    // Only lit cells can be seen, unless they're within darkvision
+    let light_map = &*light_map;
+    let visible = token_q
+        .iter()
+        .filter(|t| t.is_party())
+        .flat_map(|t| {
+            field_of_view(&grid, &t.coords, t.vision, blocks, &walls)
+                .into_iter()
+                .filter(move |c| light_map.sees(t, c))
+        })
+        .collect();
+    fog.set_visible(visible);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sync_fog(
+    mut commands: Commands,
+    mut fog_meshes_q: Query<&mut FogMeshes>,
+    mut fog_q: Query<&mut Fog>,
+    grid_q: Query<&Grid>,
+    layers_q: Query<&Layers>,
+    handle_q: Query<&Mesh2dHandle>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (Ok(mut fog_meshes), Ok(mut fog), Ok(grid), Ok(layers)) = (
+        fog_meshes_q.get_single_mut(),
+        fog_q.get_single_mut(),
+        grid_q.get_single(),
+        layers_q.get_single(),
+    ) else {
+        return;
+    };
+
+    let mut dirty = std::mem::take(&mut fog.bypass_change_detection().dirty);
+
+    if fog_meshes.settings != Some(grid.settings) || fog_meshes.enabled != fog.enabled {
+        fog_meshes.settings = Some(grid.settings);
+        fog_meshes.enabled = fog.enabled;
+        dirty.extend(grid.loaded.iter().copied());
+    }
+
+    // Chunks start out covered when they load, and their fog goes when they unload
+    dirty.extend(
+        grid.loaded
+            .iter()
+            .filter(|c| !fog_meshes.drawn.contains(c))
+            .copied(),
+    );
+    let unloaded: Vec<ChunkCoord> = fog_meshes
+        .drawn
+        .iter()
+        .filter(|c| !grid.loaded.contains(c))
+        .copied()
+        .collect();
+    for chunk in unloaded {
+        fog_meshes.drawn.remove(&chunk);
+        for e in fog_meshes.meshes.remove(&chunk).into_iter().flatten() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+
+    for chunk in dirty {
+        if !grid.loaded.contains(&chunk) {
+            continue;
+        }
+        fog_meshes.drawn.insert(chunk);
+
+        let cells: Vec<(HexCoord, Color)> = chunk
+            .coords()
+            .filter(|c| grid.contains(c))
+            .filter_map(|c| match fog.state(&c) {
+                FogState::Hidden => Some((c, *HIDDEN_COLOR)),
+                FogState::Explored => Some((c, *EXPLORED_COLOR)),
+                FogState::Visible => None,
+            })
+            .collect();
+        let existing = fog_meshes.meshes.get(&chunk).copied();
+
+        match (cells.is_empty(), existing) {
+            (false, Some([e, _])) => {
+                if let Ok(handle) = handle_q.get(e) {
+                    meshes.insert(handle.0.id(), build_mesh(grid, &cells));
+                }
+            }
+            (false, None) => {
+                let settings = layers.get(Layer::Fog);
+                let mesh: Mesh2dHandle = meshes.add(build_mesh(grid, &cells)).into();
+                let transform = Transform::from_translation(Vec3::Z * Layer::Fog.z());
+
+                let gm = commands
+                    .spawn((
+                        MaterialMesh2dBundle {
+                            mesh: mesh.clone(),
+                            material: materials
+                                .add(ColorMaterial::from(Color::WHITE.with_a(settings.opacity))),
+                            transform,
+                            visibility: if settings.visible {
+                                Visibility::Inherited
+                            } else {
+                                Visibility::Hidden
+                            },
+                            ..Default::default()
+                        },
+                        Layer::Fog,
+                        RenderLayers::layer(GM_ONLY),
+                    ))
+                    .id();
+                // Left out of the layer so the GM's settings don't lift the players' fog
+                let player = commands
+                    .spawn((
+                        MaterialMesh2dBundle {
+                            mesh,
+                            material: materials.add(ColorMaterial::from(Color::WHITE)),
+                            transform,
+                            ..Default::default()
+                        },
+                        RenderLayers::layer(PLAYER_ONLY),
+                    ))
+                    .id();
+                fog_meshes.meshes.insert(chunk, [gm, player]);
+            }
+            (true, Some(entities)) => {
+                for e in entities {
+                    commands.entity(e).despawn_recursive();
+                }
+                fog_meshes.meshes.remove(&chunk);
+            }
+            (true, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bevy::ecs::system::CommandQueue;
+
+    use crate::{grid::tests::test_grid, pathfinding::find_path};
+
+    #[test]
+    fn walls_block_the_view() {
+        let grid = test_grid(10);
+        let origin = HexCoord { q: 0, r: 0 };
+        let wall = HexCoord { q: 1, r: 0 };
+
+        let open = field_of_view(&grid, &origin, 2, |_| false, &Walls::default());
+        assert_eq!(open.len(), 19);
+
+        let seen = field_of_view(&grid, &origin, 2, |c| *c == wall, &Walls::default());
+        // The wall itself can be seen, but not what's straight behind it
+        assert!(seen.contains(&wall));
+        assert!(!seen.contains(&HexCoord { q: 2, r: 0 }));
+        assert!(seen.contains(&HexCoord { q: -2, r: 0 }));
+
+        // Nothing past the radius or off the grid
+        let edge = field_of_view(
+            &grid,
+            &HexCoord { q: 0, r: 4 },
+            3,
+            |_| false,
+            &Walls::default(),
+        );
+        assert!(edge.iter().all(|c| grid.contains(c)));
+        assert!(edge.len() < 37);
+    }
+
+    #[test]
+    fn unloaded_walls_still_block() {
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut grid = Grid::new(GridShape::Unbounded, GridSettings::default());
+        let palette = Palette::default();
+        let (near, far) = (ChunkCoord { q: 0, r: 0 }, ChunkCoord { q: 1, r: 0 });
+        let token = HexCoord { q: 14, r: 0 };
+        let wall = HexCoord { q: 16, r: 0 };
+        let target = HexCoord { q: 18, r: 0 };
+
+        for chunk in [near, far] {
+            grid.load_chunk(chunk, &mut Commands::new(&mut queue, &world));
+        }
+        queue.apply(&mut world);
+        world.get_mut::<Cell>(grid.cells[&wall]).unwrap().terrain = palette.find("Wall");
+        grid.unload_chunk(
+            far,
+            |e| world.get::<Cell>(e).cloned(),
+            &mut Commands::new(&mut queue, &world),
+        );
+        queue.apply(&mut world);
+
+        let terrain = |c: &HexCoord| {
+            grid.lookup_cell(c, |e| world.get::<Cell>(e))
+                .and_then(|c| c.terrain)
+        };
+        let seen = field_of_view(
+            &grid,
+            &token,
+            5,
+            |c| palette.blocks_sight(terrain(c)),
+            &Walls::default(),
+        );
+        assert!(seen.contains(&wall));
+        assert!(!seen.contains(&target));
+
+        // Routes lead into the unloaded chunk, around the wall
+        let path = find_path(&grid, &token, &target, |_, c| {
+            palette.movement_cost(terrain(c))
+        })
+        .unwrap();
+        assert!(!path.cells.contains(&wall));
+        assert_eq!(path.cost, 5.0);
+    }
+
+    #[test]
+    fn explored_cells_stay_dimmed() {
+        let mut fog = Fog {
+            enabled: true,
+            ..Default::default()
+        };
+        let a = HexCoord { q: 0, r: 0 };
+        let b = HexCoord { q: 5, r: 5 };
+        assert_eq!(fog.state(&a), FogState::Hidden);
+
+        fog.set_visible([a].into());
+        assert_eq!(fog.state(&a), FogState::Visible);
+
+        fog.set_visible([b].into());
+        assert_eq!(fog.state(&a), FogState::Explored);
+        assert_eq!(fog.state(&b), FogState::Visible);
+        assert!(fog.dirty.contains(&ChunkCoord::of(&a)));
+
+        fog.reveal(&[a]);
+        assert_eq!(fog.state(&a), FogState::Visible);
+        fog.hide(&[a]);
+        assert_eq!(fog.state(&a), FogState::Hidden);
+
+        fog.reset();
+        assert_eq!(fog.state(&b), FogState::Visible);
+        fog.set_visible(HashSet::new());
+        assert_eq!(fog.state(&b), FogState::Explored);
+
+        fog.enabled = false;
+        assert_eq!(fog.state(&a), FogState::Visible);
+    }
+}
@@ -1,26 +1,102 @@
-use std::{cmp::max, collections::HashMap};
+use std::{
+    cmp::{max, min},
+    collections::{HashMap, HashSet},
+};
 
 use crate::{
-    cell::Cell,
+    cell::{Cell, HEX_COLOR},
+    chunk::ChunkCoord,
     hex::{FractionalHexCoord, HexCoord},
+    shape::GridShape,
 };
 
-use bevy::{prelude::*, sprite::ColorMaterial};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
-const HEX_SIZE: f32 = 35.0;
+pub const HEX_SIZE: f32 = 35.0;
 const HEX_SPACING: f32 = 1.0;
-
-lazy_static! {
-    static ref HEX_GRID_HORIZONTAL_OFFSET: f32 = 3_f32.sqrt();
-}
+// How far from the centre of a shared hex its tokens sit, as a fraction of the hex size
+const STACK_SPREAD: f32 = 0.35;
 
 #[derive(Event)]
 pub enum GridEvent {
-    Resize(i32, i32),
+    Resize(GridShape),
+    // Cuts cells out of the grid
+    RemoveCells(Vec<HexCoord>),
+    // Puts back every cell that's been cut out
+    RestoreCells,
+    Settings(GridSettings),
+    // Color of cells that haven't been painted
+    BlankColor(Color),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Layout {
+    #[default]
+    PointyTop,
+    FlatTop,
+}
+
+impl Layout {
+    fn orientation(&self) -> Orientation {
+        match self {
+            Layout::PointyTop => Orientation {
+                f0: 3.0_f32.sqrt(),
+                f1: 3.0_f32.sqrt() / 2.0,
+                f2: 0.0,
+                f3: 3.0 / 2.0,
+                b0: 3.0_f32.sqrt() / 3.0,
+                b1: -1.0 / 3.0,
+                b2: 0.0,
+                b3: 2.0 / 3.0,
+            },
+            Layout::FlatTop => Orientation {
+                f0: 3.0 / 2.0,
+                f1: 0.0,
+                f2: 3.0_f32.sqrt() / 2.0,
+                f3: 3.0_f32.sqrt(),
+                b0: 2.0 / 3.0,
+                b1: 0.0,
+                b2: -1.0 / 3.0,
+                b3: 3.0_f32.sqrt() / 3.0,
+            },
+        }
+    }
+
+    // Rotation of the hex mesh, RegularPolygon starts with a corner at the top
+    fn angle(&self) -> f32 {
+        match self {
+            Layout::PointyTop => 0.0,
+            Layout::FlatTop => std::f32::consts::FRAC_PI_6,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct GridSettings {
+    // Radius of a hex
+    pub hex_size: f32,
+    // Gap between neighbouring hexes
+    pub spacing: f32,
+    pub layout: Layout,
+    // World position of the centre of hex 0, 0
+    pub origin: Vec2,
+    // Radians, counter clockwise
+    pub rotation: f32,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            hex_size: HEX_SIZE,
+            spacing: HEX_SPACING,
+            layout: Layout::PointyTop,
+            origin: Vec2::ZERO,
+            rotation: 0.0,
+        }
+    }
 }
 
-// TODO replace with normal matrices
-#[derive(Default)]
 struct Orientation {
     f0: f32,
     f1: f32,
@@ -43,149 +119,339 @@ impl bevy::prelude::Plugin for Plugin {
 
 #[derive(Component, Default)]
 pub struct Grid {
-    pub size: i32,
+    pub shape: GridShape,
+    // Coords cut out of the shape
+    pub holes: HashSet<HexCoord>,
+    // Cells of the loaded chunks, the only cells with entities
     pub cells: HashMap<HexCoord, Entity>,
+    pub loaded: HashSet<ChunkCoord>,
+    // Cells of chunks that have been unloaded, blank cells aren't kept
+    stored: HashMap<ChunkCoord, Vec<Cell>>,
+    // Tokens on each hex and the hex each token is on, kept up to date by the token systems
+    occupants: HashMap<HexCoord, Vec<Entity>>,
+    occupied: HashMap<Entity, HexCoord>,
 
-    orientation: Orientation,
-    // forward: Mat4,
+    pub settings: GridSettings,
+    pub blank_color: Color,
 }
 
 impl Grid {
-    // Returns the left, right, top, bottom, edges for a grid of a certain size
-    fn get_edges(size: i32) -> (i32, i32, i32, i32) {
-        (-size / 2, size / 2, -size / 2, size / 2)
+    pub fn new(shape: GridShape, settings: GridSettings) -> Self {
+        Grid {
+            shape,
+            holes: HashSet::new(),
+            cells: HashMap::new(),
+            loaded: HashSet::new(),
+            stored: HashMap::new(),
+            occupants: HashMap::new(),
+            occupied: HashMap::new(),
+            settings,
+            blank_color: *HEX_COLOR,
+        }
+    }
+
+    // Cells are spawned as their chunks come into view
+    pub fn create(shape: GridShape, commands: &mut Commands) {
+        commands.spawn(Grid::new(shape, GridSettings::default()));
+    }
+
+    // Whether the coord is part of the grid, loaded or not
+    pub fn contains(&self, coord: &HexCoord) -> bool {
+        self.shape.contains(coord, self.settings.layout) && !self.holes.contains(coord)
     }
 
-    pub fn create(
-        size: i32,
+    pub fn load_chunk(&mut self, chunk: ChunkCoord, commands: &mut Commands) {
+        let mut stored: HashMap<HexCoord, Cell> = self
+            .stored
+            .remove(&chunk)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| (c.pos, c))
+            .collect();
+
+        let coords: Vec<HexCoord> = chunk.coords().filter(|c| self.contains(c)).collect();
+        for coord in coords {
+            let cell = stored
+                .remove(&coord)
+                .unwrap_or_else(|| Cell::new(self, coord));
+
+            self.cells.insert(coord, commands.spawn(cell).id());
+        }
+
+        self.loaded.insert(chunk);
+    }
+
+    // Despawns the cells of a chunk, keeping any that have been drawn on
+    pub fn unload_chunk(
+        &mut self,
+        chunk: ChunkCoord,
+        get_cell: impl Fn(Entity) -> Option<Cell>,
         commands: &mut Commands,
-        meshes: &mut ResMut<Assets<Mesh>>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
     ) {
-        let mut grid = Grid {
-            size,
-            cells: HashMap::new(),
+        let mut stored = Vec::new();
+        for coord in chunk.coords() {
+            let Some(e) = self.cells.remove(&coord) else {
+                continue;
+            };
 
-            orientation: Orientation {
-                f0: 3.0_f32.sqrt(),
-                f1: 3.0_f32.sqrt() / 2.0,
-                f2: 0.0,
-                f3: 3.0 / 2.0,
-                b0: 3.0_f32.sqrt() / 3.0,
-                b1: -1.0 / 3.0,
-                b2: 0.0,
-                b3: 2.0 / 3.0,
-            },
-        };
-
-        let left: i32 = -size / 2;
-        let right: i32 = size / 2;
-        let top: i32 = -size / 2;
-        let bottom: i32 = size / 2;
-
-        for r in top..=bottom {
-            let offset_r = (r as f32 / 2.0).floor() as i32;
-
-            for q in (left - offset_r)..=(right - offset_r) {
-                let id = Cell::create(
-                    grid.hex_coord_to_pos(&HexCoord { q, r }),
-                    HEX_SIZE,
-                    HexCoord { q, r },
-                    commands,
-                    meshes,
-                    materials,
-                );
-
-                grid.cells.insert(HexCoord { q, r }, id);
+            if let Some(cell) = get_cell(e).filter(|c| !c.is_blank(self)) {
+                stored.push(cell);
             }
+            commands.entity(e).despawn_recursive();
+        }
+
+        if !stored.is_empty() {
+            self.stored.insert(chunk, stored);
         }
+        self.loaded.remove(&chunk);
+    }
+
+    // Cells of the unloaded chunks, so edits to every cell can reach them too
+    pub fn stored_cells_mut(&mut self) -> impl Iterator<Item = &mut Cell> {
+        self.stored.values_mut().flatten()
+    }
 
-        commands.spawn(grid);
+    pub fn stored_cells(&self) -> impl Iterator<Item = &Cell> {
+        self.stored.values().flatten()
     }
 
-    pub fn recreate(
+    // Takes on the shape and settings of another grid. Every cell is unloaded, the chunks
+    // stream back in with the new shape
+    pub fn reshape(
         &mut self,
-        size: i32,
+        shape: GridShape,
+        holes: HashSet<HexCoord>,
+        settings: GridSettings,
+        blank_color: Color,
         commands: &mut Commands,
-        meshes: &mut ResMut<Assets<Mesh>>,
-        materials: &mut ResMut<Assets<ColorMaterial>>,
     ) {
-        let old_size = self.size;
-        let new_size = size;
-        self.size = new_size;
-
-        if new_size < old_size {
-            let (left, right, top, bottom) = Grid::get_edges(old_size);
-            for r in top..=bottom {
-                let offset_r = (r as f32 / 2.0).floor() as i32;
-
-                for q in (left - offset_r)..=(right - offset_r) {
-                    let coord = HexCoord { q, r };
-                    let e = self.cells.get(&coord);
-                    match e {
-                        Some(e) => {
-                            commands.entity(*e).despawn_recursive();
-
-                            self.cells.remove(&coord);
-                        }
-                        None => todo!(),
-                    }
-                }
+        for (_, e) in self.cells.drain() {
+            commands.entity(e).despawn_recursive();
+        }
+        self.loaded.clear();
+        self.stored.clear();
+
+        self.shape = shape;
+        self.holes = holes;
+        self.settings = settings;
+        self.blank_color = blank_color;
+    }
+
+    // Takes on the drawn cells of another grid, every other cell becomes blank. Loaded cells are
+    // handed to set_cell, the rest are kept until their chunk loads
+    pub fn replace_cells(&mut self, cells: Vec<Cell>, mut set_cell: impl FnMut(Entity, Cell)) {
+        let mut by_pos: HashMap<HexCoord, Cell> = cells
+            .into_iter()
+            .filter(|c| self.contains(&c.pos))
+            .map(|c| (c.pos, c))
+            .collect();
+
+        for (coord, e) in &self.cells {
+            let cell = by_pos
+                .remove(coord)
+                .unwrap_or_else(|| Cell::new(self, *coord));
+            set_cell(*e, cell);
+        }
+
+        self.stored.clear();
+        for cell in by_pos.into_values() {
+            self.stored
+                .entry(ChunkCoord::of(&cell.pos))
+                .or_default()
+                .push(cell);
+        }
+    }
+
+    // Puts cells back as they were, leaving every other cell alone. Loaded cells are handed to
+    // set_cell, the rest are stored
+    pub fn restore_cells(&mut self, cells: Vec<Cell>, mut set_cell: impl FnMut(Entity, Cell)) {
+        for cell in cells {
+            if !self.contains(&cell.pos) {
+                continue;
+            }
+            if let Some(e) = self.cells.get(&cell.pos) {
+                set_cell(*e, cell);
+                continue;
+            }
+
+            let chunk = ChunkCoord::of(&cell.pos);
+            let blank = cell.is_blank(self);
+            let stored = self.stored.entry(chunk).or_default();
+            stored.retain(|c| c.pos != cell.pos);
+            if !blank {
+                stored.push(cell);
+            }
+            if stored.is_empty() {
+                self.stored.remove(&chunk);
             }
         }
+    }
 
-        let (left, right, top, bottom) = Grid::get_edges(new_size);
-        for r in top..=bottom {
-            let offset_r = (r as f32 / 2.0).floor() as i32;
+    // Cuts cells out of the grid
+    fn remove_cells(&mut self, coords: &[HexCoord], commands: &mut Commands) {
+        self.holes.extend(coords.iter().copied());
+        self.refresh(commands);
+    }
 
-            for q in (left - offset_r)..=(right - offset_r) {
-                let coord = HexCoord { q, r };
+    // Returns the coords of the loaded chunks that leave and join the grid when it changes to a
+    // shape
+    fn diff(&self, shape: &GridShape) -> (Vec<HexCoord>, Vec<HexCoord>) {
+        let contains =
+            |c: &HexCoord| shape.contains(c, self.settings.layout) && !self.holes.contains(c);
+
+        let removed = self
+            .cells
+            .keys()
+            .filter(|c| !contains(c))
+            .copied()
+            .collect();
+        let added = self
+            .loaded
+            .iter()
+            .flat_map(|chunk| chunk.coords())
+            .filter(|c| contains(c) && !self.cells.contains_key(c))
+            .collect();
+
+        (removed, added)
+    }
+
+    // Changes the shape of the grid, cells in both the old and new shape are left untouched
+    fn resize(&mut self, shape: GridShape, commands: &mut Commands) {
+        let (removed, added) = self.diff(&shape);
+        self.shape = shape;
+
+        for coord in removed {
+            if let Some(e) = self.cells.remove(&coord) {
+                commands.entity(e).despawn_recursive();
+            }
+        }
+
+        for coord in added {
+            let id = Cell::create(self, coord, commands);
 
-                let id = Cell::create(
-                    self.hex_coord_to_pos(&coord),
-                    HEX_SIZE,
-                    HexCoord { q, r },
-                    commands,
-                    meshes,
-                    materials,
-                );
+            self.cells.insert(coord, id);
+        }
 
-                self.cells.insert(HexCoord { q, r }, id);
+        let layout = self.settings.layout;
+        let (shape, holes) = (&self.shape, &self.holes);
+        for cells in self.stored.values_mut() {
+            cells.retain(|c| shape.contains(&c.pos, layout) && !holes.contains(&c.pos));
+        }
+        self.stored.retain(|_, cells| !cells.is_empty());
+    }
+
+    // Brings the cells in line with the shape and holes
+    fn refresh(&mut self, commands: &mut Commands) {
+        let shape = self.shape.clone();
+        self.resize(shape, commands);
+    }
+
+    // Closest cell in the grid that isn't taken, ties go to the lowest coord so it's repeatable
+    pub fn nearest_free_cell(
+        &self,
+        start: &HexCoord,
+        taken: impl Fn(&HexCoord) -> bool,
+    ) -> Option<HexCoord> {
+        self.cells
+            .keys()
+            .filter(|c| !taken(c))
+            .min_by_key(|c| (c.distance(start), c.r, c.q))
+            .copied()
+    }
+
+    // Tokens on the hex, in the order they arrived
+    pub fn occupants(&self, coord: &HexCoord) -> &[Entity] {
+        self.occupants.get(coord).map_or(&[], |o| o.as_slice())
+    }
+
+    pub fn is_occupied(&self, coord: &HexCoord) -> bool {
+        !self.occupants(coord).is_empty()
+    }
+
+    // Puts the token on the hex, taking it off the one it was on. Returns the hex it left
+    pub fn occupy(&mut self, token: Entity, coord: HexCoord) -> Option<HexCoord> {
+        if self.occupied.get(&token) == Some(&coord) {
+            return None;
+        }
+        let left = self.vacate(token);
+        self.occupied.insert(token, coord);
+        self.occupants.entry(coord).or_default().push(token);
+        left
+    }
+
+    // Takes the token off the grid, returning the hex it was on
+    pub fn vacate(&mut self, token: Entity) -> Option<HexCoord> {
+        let coord = self.occupied.remove(&token)?;
+        if let Some(occupants) = self.occupants.get_mut(&coord) {
+            occupants.retain(|e| *e != token);
+            if occupants.is_empty() {
+                self.occupants.remove(&coord);
             }
         }
+        Some(coord)
+    }
+
+    // Where the token sits on its hex, tokens sharing one are spread around its centre
+    pub fn occupant_pos(&self, token: Entity, coord: &HexCoord) -> Vec2 {
+        let centre = self.hex_coord_to_pos(coord);
+        let occupants = self.occupants(coord);
+        match occupants.iter().position(|e| *e == token) {
+            Some(i) if occupants.len() > 1 => {
+                let angle = std::f32::consts::TAU * i as f32 / occupants.len() as f32;
+                centre + Vec2::from_angle(angle) * self.settings.hex_size * STACK_SPREAD
+            }
+            _ => centre,
+        }
     }
 
     pub fn get_cell(&self, pos: &HexCoord) -> Option<&Entity> {
         self.cells.get(pos)
     }
 
-    pub fn get_neighbours(&self, pos: &HexCoord) -> Vec<HexCoord> {
-        let directions = [
-            HexCoord { q: 1, r: 0 },
-            HexCoord { q: 1, r: -1 },
-            HexCoord { q: 0, r: -1 },
-            HexCoord { q: -1, r: 0 },
-            HexCoord { q: -1, r: 1 },
-            HexCoord { q: 0, r: 1 },
-        ];
+    // Cell at the coord whether its chunk is loaded or not. None off the grid, and for blank
+    // cells of unloaded chunks since those aren't stored
+    pub fn lookup_cell<'a>(
+        &'a self,
+        pos: &HexCoord,
+        get_cell: impl Fn(Entity) -> Option<&'a Cell>,
+    ) -> Option<&'a Cell> {
+        match self.cells.get(pos) {
+            Some(e) => get_cell(*e),
+            None => self
+                .stored
+                .get(&ChunkCoord::of(pos))?
+                .iter()
+                .find(|c| c.pos == *pos),
+        }
+    }
 
-        let mut neighbours = Vec::new();
+    // Neighbours on the grid, loaded or not
+    pub fn get_neighbours(&self, pos: &HexCoord) -> Vec<HexCoord> {
+        HexCoord::DIRECTIONS
+            .iter()
+            .map(|d| pos + d)
+            .filter(|n| self.contains(n))
+            .collect()
+    }
 
-        directions.iter().for_each(|d| {
-            if self.cells.contains_key(&(pos + d)) {
-                neighbours.push(pos + d);
-            }
-        });
+    // How much larger than the default the hexes currently are
+    pub fn scale(&self) -> f32 {
+        self.settings.hex_size / HEX_SIZE
+    }
 
-        neighbours
+    // Rotation of the cell meshes
+    pub fn cell_rotation(&self) -> f32 {
+        self.settings.rotation + self.settings.layout.angle()
     }
 
     pub fn pos_to_hex_coord(&self, pos: &Vec2) -> HexCoord {
-        let ori = &self.orientation;
+        let settings = &self.settings;
+        let ori = settings.layout.orientation();
 
+        let pos = Vec2::from_angle(-settings.rotation).rotate(*pos - settings.origin);
         let pt = Vec2 {
-            x: pos.x / (HEX_SIZE + HEX_SPACING),
-            y: pos.y / (HEX_SIZE + HEX_SPACING),
+            x: pos.x / (settings.hex_size + settings.spacing),
+            y: pos.y / (settings.hex_size + settings.spacing),
         };
 
         let q = ori.b0 * pt.x + ori.b1 * pt.y;
@@ -195,36 +461,123 @@ impl Grid {
     }
 
     pub fn hex_coord_to_pos(&self, coord: &HexCoord) -> Vec2 {
-        let ori = &self.orientation;
+        let settings = &self.settings;
+        let ori = settings.layout.orientation();
 
-        let x = (ori.f0 * coord.q as f32 + ori.f1 * coord.r as f32) * (HEX_SIZE + HEX_SPACING);
-        let y = (ori.f2 * coord.q as f32 + ori.f3 * coord.r as f32) * (HEX_SIZE + HEX_SPACING);
+        let spacing = settings.hex_size + settings.spacing;
+        let x = (ori.f0 * coord.q as f32 + ori.f1 * coord.r as f32) * spacing;
+        let y = (ori.f2 * coord.q as f32 + ori.f3 * coord.r as f32) * spacing;
 
-        Vec2 { x, y }
+        settings.origin + Vec2::from_angle(settings.rotation).rotate(Vec2 { x, y })
     }
 
-    pub fn get_cells_in_box(&self, start: &HexCoord, end: &HexCoord) -> Vec<Entity> {
-        let mut cells = vec![
-            *self.cells.get(start).unwrap(),
-            *self.cells.get(end).unwrap(),
+    // Works out the hex size, origin and rotation from the centres of three touching hexes. The
+    // hex currently under the first point stays under it
+    pub fn calibrate(&self, points: &[Vec2; 3]) -> Result<GridSettings, String> {
+        let edges = [
+            points[1] - points[0],
+            points[2] - points[0],
+            points[2] - points[1],
         ];
 
-        let c1 = HexCoord {
-            q: start.q,
-            r: end.r,
-        };
+        let distance = edges.iter().map(|e| e.length()).sum::<f32>() / 3.0;
+        if edges
+            .iter()
+            .any(|e| (e.length() - distance).abs() > distance * 0.25)
+        {
+            return Err("Points must be the centres of three touching hexes".to_string());
+        }
 
-        let c2 = HexCoord {
-            q: end.q,
-            r: start.r,
-        };
+        // Neighbours are 60 degrees apart, so average the edge angles modulo 60
+        let direction: Vec2 = edges
+            .iter()
+            .map(|e| Vec2::from_angle(e.y.atan2(e.x) * 6.0))
+            .sum();
+        // Flat top neighbours already sit 30 degrees round from pointy top ones, any multiple of 60
+        // degrees gives the same grid so pick the smallest
+        let rotation = direction.y.atan2(direction.x) / 6.0 - self.settings.layout.angle();
+        let sixth = std::f32::consts::FRAC_PI_3;
+        let rotation = rotation - (rotation / sixth).round() * sixth;
 
-        cells.append(&mut self.get_cells_in_line(start, &c1));
-        cells.append(&mut self.get_cells_in_line(start, &c2));
-        cells.append(&mut self.get_cells_in_line(&c1, end));
-        cells.append(&mut self.get_cells_in_line(&c2, end));
+        let hex_size = distance / 3_f32.sqrt() - self.settings.spacing;
+        if hex_size <= 0.0 {
+            return Err("Points are too close together".to_string());
+        }
 
-        cells
+        let coord = self.pos_to_hex_coord(&points[0]);
+        let mut calibrated = Grid::new(
+            self.shape.clone(),
+            GridSettings {
+                hex_size,
+                rotation,
+                origin: Vec2::ZERO,
+                ..self.settings
+            },
+        );
+        calibrated.settings.origin = points[0] - calibrated.hex_coord_to_pos(&coord);
+
+        Ok(calibrated.settings)
+    }
+
+    // Returns the coords whose centres lie inside the screen aligned rectangle spanned by the
+    // centres of start and end, edges included
+    pub fn cells_in_rect(&self, start: &HexCoord, end: &HexCoord) -> Vec<HexCoord> {
+        let a = self.hex_coord_to_pos(start);
+        let b = self.hex_coord_to_pos(end);
+
+        // Centres on the edges may land a hair outside due to float error
+        let min = a.min(b) - Vec2::splat(0.01);
+        let max = a.max(b) + Vec2::splat(0.01);
+
+        self.cells
+            .keys()
+            .filter(|c| {
+                let pos = self.hex_coord_to_pos(c);
+                pos.cmpge(min).all() && pos.cmple(max).all()
+            })
+            .copied()
+            .collect()
+    }
+
+    // Returns the coords between start and end along the q and r axes, edges included
+    pub fn cells_in_parallelogram(&self, start: &HexCoord, end: &HexCoord) -> Vec<HexCoord> {
+        let (start_q, end_q) = (min(start.q, end.q), max(start.q, end.q));
+        let (start_r, end_r) = (min(start.r, end.r), max(start.r, end.r));
+
+        let mut coords = Vec::new();
+        for r in start_r..=end_r {
+            for q in start_q..=end_q {
+                let coord = HexCoord { q, r };
+                if self.cells.contains_key(&coord) {
+                    coords.push(coord);
+                }
+            }
+        }
+
+        coords
+    }
+
+    pub fn get_cells(&self, coords: &[HexCoord]) -> Vec<Entity> {
+        coords
+            .iter()
+            .filter_map(|c| self.cells.get(c))
+            .copied()
+            .collect()
+    }
+
+    // Returns the coords of a region that touch a coord outside of it
+    pub fn get_outline(region: &[HexCoord]) -> Vec<HexCoord> {
+        let set: HashSet<&HexCoord> = region.iter().collect();
+
+        region
+            .iter()
+            .filter(|c| {
+                HexCoord::DIRECTIONS
+                    .iter()
+                    .any(|d| !set.contains(&(*c + d)))
+            })
+            .copied()
+            .collect()
     }
 
     pub fn get_cells_in_line(&self, start: &HexCoord, end: &HexCoord) -> Vec<Entity> {
@@ -250,48 +603,437 @@ impl Grid {
 fn on_grid_event(
     mut events: EventReader<GridEvent>,
     mut grid_q: Query<&mut Grid>,
+    mut cell_q: Query<&mut Cell>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
     let mut grid = grid_q.single_mut();
 
     for e in events.iter() {
         match e {
-            GridEvent::Resize(q, r) => {
-                grid.recreate(*q, &mut commands, &mut meshes, &mut materials);
+            GridEvent::Resize(shape) => {
+                grid.resize(shape.clone(), &mut commands);
+            }
+            GridEvent::RemoveCells(coords) => grid.remove_cells(coords, &mut commands),
+            GridEvent::RestoreCells => {
+                grid.holes.clear();
+                grid.refresh(&mut commands);
+            }
+            GridEvent::Settings(settings) => {
+                let layout_changed = settings.layout != grid.settings.layout;
+                grid.settings = *settings;
+
+                // The same shape covers different coords in each layout
+                if layout_changed {
+                    grid.refresh(&mut commands);
+                }
+            }
+            GridEvent::BlankColor(color) => {
+                let old = grid.blank_color;
+                grid.blank_color = *color;
+
+                let unpainted = |c: &Cell| c.terrain.is_none() && c.base_color == old;
+                for mut cell in cell_q.iter_mut().filter(|c| unpainted(c)) {
+                    set_blank_color(&mut cell, old, *color);
+                }
+                for cell in grid.stored_cells_mut().filter(|c| unpainted(c)) {
+                    set_blank_color(cell, old, *color);
+                }
             }
         }
     }
 }
 
+fn set_blank_color(cell: &mut Cell, old: Color, new: Color) {
+    if cell.color == old {
+        cell.color = new;
+    }
+    cell.base_color = new;
+}
+
 #[cfg(test)]
-mod tests {
+pub mod tests {
+    use bevy::ecs::system::CommandQueue;
+
     use super::*;
+    use crate::chunk::CHUNK_SIZE;
+
+    fn round_trip(layout: Layout) {
+        let grid = Grid::new(
+            GridShape::default(),
+            GridSettings {
+                layout,
+                ..Default::default()
+            },
+        );
+
+        let shape = GridShape::Rectangle {
+            width: 20,
+            height: 20,
+        };
+        for coord in shape.coords(layout) {
+            let pos = grid.hex_coord_to_pos(&coord);
+            assert_eq!(coord, grid.pos_to_hex_coord(&pos));
+
+            // Anywhere inside the hex maps back to it
+            let inside = pos + Vec2::from_angle(0.7) * HEX_SIZE * 0.8;
+            assert_eq!(coord, grid.pos_to_hex_coord(&inside));
+        }
+    }
 
     #[test]
     fn hex_to_pos() {
-        let grid = Grid {
-            size: 250,
-            cells: HashMap::new(),
+        round_trip(Layout::PointyTop);
+    }
 
-            orientation: Orientation {
-                f0: 3.0_f32.sqrt(),
-                f1: 3.0_f32.sqrt() / 2.0,
-                f2: 0.0,
-                f3: 3.0 / 2.0,
-                b0: 3.0_f32.sqrt() / 3.0,
-                b1: -1.0 / 3.0,
-                b2: 0.0,
-                b3: 2.0 / 3.0,
+    #[test]
+    fn hex_to_pos_flat_top() {
+        round_trip(Layout::FlatTop);
+    }
+
+    #[test]
+    fn hex_to_pos_offset_and_rotated() {
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            let grid = Grid::new(
+                GridShape::default(),
+                GridSettings {
+                    hex_size: 20.0,
+                    spacing: 3.0,
+                    layout,
+                    origin: Vec2 { x: -40.0, y: 15.0 },
+                    rotation: 0.4,
+                },
+            );
+
+            let coord = HexCoord { q: -7, r: 12 };
+            assert_eq!(coord, grid.pos_to_hex_coord(&grid.hex_coord_to_pos(&coord)));
+        }
+    }
+
+    #[test]
+    fn layouts_are_rectangular() {
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            let grid = test_grid_with(10, layout);
+            let positions: Vec<Vec2> = grid
+                .cells
+                .keys()
+                .map(|c| grid.hex_coord_to_pos(c))
+                .collect();
+
+            // Every row or column of a rectangle holds the same number of cells
+            let mut rows: HashMap<i32, usize> = HashMap::new();
+            for p in positions {
+                let key = match layout {
+                    Layout::PointyTop => p.y,
+                    Layout::FlatTop => p.x,
+                };
+                *rows.entry(key.round() as i32).or_default() += 1;
+            }
+
+            assert_eq!(rows.len(), 10);
+            assert!(rows.values().all(|n| *n == 10));
+        }
+    }
+
+    pub fn test_grid(size: i32) -> Grid {
+        test_grid_with(size, Layout::PointyTop)
+    }
+
+    fn test_grid_with(size: i32, layout: Layout) -> Grid {
+        let settings = GridSettings {
+            layout,
+            ..Default::default()
+        };
+
+        let mut grid = Grid::new(
+            GridShape::Rectangle {
+                width: size,
+                height: size,
             },
+            settings,
+        );
+        for (i, coord) in grid.shape.coords(layout).into_iter().enumerate() {
+            grid.cells.insert(coord, Entity::from_raw(i as u32));
+            grid.loaded.insert(ChunkCoord::of(&coord));
+        }
+
+        grid
+    }
+
+    #[test]
+    fn unloading_keeps_painted_cells() {
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut grid = Grid::new(GridShape::Unbounded, GridSettings::default());
+        let chunk = ChunkCoord { q: 0, r: 0 };
+        let painted = HexCoord { q: 3, r: 4 };
+
+        grid.load_chunk(chunk, &mut Commands::new(&mut queue, &world));
+        queue.apply(&mut world);
+        assert_eq!(grid.cells.len(), (CHUNK_SIZE * CHUNK_SIZE) as usize);
+
+        let e = grid.cells[&painted];
+        world.get_mut::<Cell>(e).unwrap().color = Color::RED;
+
+        grid.unload_chunk(
+            chunk,
+            |e| world.get::<Cell>(e).cloned(),
+            &mut Commands::new(&mut queue, &world),
+        );
+        queue.apply(&mut world);
+        assert!(grid.cells.is_empty());
+        assert_eq!(world.query::<&Cell>().iter(&world).count(), 0);
+        assert_eq!(grid.stored_cells_mut().count(), 1);
+
+        // Cutting a hole drops the stored cell with it
+        grid.holes.insert(HexCoord { q: 0, r: 0 });
+        grid.load_chunk(chunk, &mut Commands::new(&mut queue, &world));
+        queue.apply(&mut world);
+        assert_eq!(grid.cells.len(), (CHUNK_SIZE * CHUNK_SIZE - 1) as usize);
+        assert_eq!(
+            world.get::<Cell>(grid.cells[&painted]).unwrap().color,
+            Color::RED
+        );
+    }
+
+    #[test]
+    fn replacing_cells_reaches_unloaded_chunks() {
+        let mut grid = test_grid(10);
+        let loaded = HexCoord { q: 0, r: 0 };
+        let unloaded = HexCoord { q: 100, r: 100 };
+        grid.shape = GridShape::Unbounded;
+
+        let painted = |pos| Cell {
+            color: Color::RED,
+            ..Cell::new(&grid, pos)
+        };
+        let cells = vec![painted(loaded), painted(unloaded)];
+
+        let mut set = HashMap::new();
+        grid.replace_cells(cells, |e, cell| {
+            set.insert(e, cell);
+        });
+
+        // Every loaded cell is set, blank unless it was given
+        assert_eq!(set.len(), grid.cells.len());
+        assert_eq!(set[&grid.cells[&loaded]].color, Color::RED);
+        assert_eq!(set.values().filter(|c| c.color == Color::RED).count(), 1);
+
+        let stored: Vec<&Cell> = grid.stored_cells().collect();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].pos, unloaded);
+    }
+
+    #[test]
+    fn restoring_cells_leaves_the_rest_alone() {
+        let mut grid = test_grid(10);
+        let loaded = HexCoord { q: 0, r: 0 };
+        let unloaded = HexCoord { q: 100, r: 100 };
+        grid.shape = GridShape::Unbounded;
+
+        let blank_color = grid.blank_color;
+        let painted = |pos| Cell {
+            color: Color::RED,
+            ..Cell::blank(pos, blank_color)
+        };
+        grid.replace_cells(vec![painted(unloaded)], |_, _| {});
+
+        let mut set = HashMap::new();
+        grid.restore_cells(vec![painted(loaded)], |e, cell| {
+            set.insert(e, cell);
+        });
+        assert_eq!(set.len(), 1);
+        assert_eq!(set[&grid.cells[&loaded]].color, Color::RED);
+        assert_eq!(grid.stored_cells().count(), 1);
+
+        // Blank cells aren't stored
+        let blank = Cell::new(&grid, unloaded);
+        grid.restore_cells(vec![blank], |_, _| {});
+        assert_eq!(grid.stored_cells().count(), 0);
+    }
+
+    fn sorted(mut coords: Vec<HexCoord>) -> Vec<(i32, i32)> {
+        coords.sort_by_key(|c| (c.r, c.q));
+        coords.into_iter().map(|c| (c.r, c.q)).collect()
+    }
+
+    #[test]
+    fn rect_is_inclusive_in_every_direction() {
+        let grid = test_grid(10);
+
+        let a = HexCoord { q: -2, r: -2 };
+        let b = HexCoord { q: 2, r: 2 };
+        let c = HexCoord { q: -4, r: 2 };
+        let d = HexCoord { q: 4, r: -2 };
+
+        let expected = sorted(grid.cells_in_rect(&a, &b));
+        assert!(expected.contains(&(a.r, a.q)));
+        assert!(expected.contains(&(b.r, b.q)));
+
+        assert_eq!(expected, sorted(grid.cells_in_rect(&b, &a)));
+
+        // The other diagonal of the same rectangle
+        assert_eq!(expected, sorted(grid.cells_in_rect(&c, &d)));
+        assert_eq!(expected, sorted(grid.cells_in_rect(&d, &c)));
+
+        // Every row between the corners is present, all on screen inside the corners
+        let (pa, pb) = (grid.hex_coord_to_pos(&a), grid.hex_coord_to_pos(&b));
+        for r in a.r..=b.r {
+            assert!(expected.iter().any(|(row, _)| *row == r));
+        }
+        for (r, q) in &expected {
+            let p = grid.hex_coord_to_pos(&HexCoord { q: *q, r: *r });
+            assert!(p.x >= pa.x.min(pb.x) - 0.01 && p.x <= pa.x.max(pb.x) + 0.01);
+        }
+    }
+
+    #[test]
+    fn resize_only_touches_the_edges() {
+        let grid = test_grid(10);
+        let smaller = GridShape::Rectangle {
+            width: 6,
+            height: 10,
         };
 
-        let test_coord = HexCoord { q: 10, r: 10 };
+        let (removed, added) = grid.diff(&smaller);
+        assert!(added.is_empty());
+        assert_eq!(removed.len(), 40);
+
+        let (removed, added) = grid.diff(&GridShape::Rectangle {
+            width: 12,
+            height: 12,
+        });
+        assert!(removed.is_empty());
+        assert_eq!(added.len(), 12 * 12 - 10 * 10);
+
+        let (removed, added) = grid.diff(&grid.shape);
+        assert!(removed.is_empty() && added.is_empty());
+    }
+
+    #[test]
+    fn nearest_free_cell() {
+        let grid = test_grid(10);
+        let centre = HexCoord { q: 0, r: 0 };
+
+        assert_eq!(grid.nearest_free_cell(&centre, |_| false), Some(centre));
+
+        let free = grid.nearest_free_cell(&centre, |c| c == &centre).unwrap();
+        assert_eq!(free.distance(&centre), 1);
+
+        // Outside the grid snaps back to the edge
+        let outside = HexCoord { q: 20, r: 0 };
+        let free = grid.nearest_free_cell(&outside, |_| false).unwrap();
+        assert!(grid.get_cell(&free).is_some());
+        assert!(grid.get_neighbours(&free).len() < 6);
+
+        assert_eq!(grid.nearest_free_cell(&centre, |_| true), None);
+    }
+
+    #[test]
+    fn calibrate_from_three_centres() {
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            let target = GridSettings {
+                hex_size: 50.0,
+                layout,
+                origin: Vec2 { x: 12.0, y: -7.0 },
+                rotation: 0.2,
+                ..Default::default()
+            };
+            let target_grid = Grid::new(GridShape::default(), target);
+
+            let points = [
+                target_grid.hex_coord_to_pos(&HexCoord { q: 0, r: 0 }),
+                target_grid.hex_coord_to_pos(&HexCoord { q: 1, r: 0 }),
+                target_grid.hex_coord_to_pos(&HexCoord { q: 0, r: 1 }),
+            ];
+
+            let start = GridSettings {
+                layout,
+                ..Default::default()
+            };
+            let settings = Grid::new(GridShape::default(), start)
+                .calibrate(&points)
+                .unwrap();
+
+            assert!((settings.hex_size - target.hex_size).abs() < 0.01);
+            assert!(settings.origin.distance(target.origin) < 0.01);
+            assert!((settings.rotation - target.rotation).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn calibrate_rejects_far_points() {
+        let grid = Grid::new(GridShape::default(), GridSettings::default());
+        let points = [
+            Vec2::ZERO,
+            Vec2 { x: 100.0, y: 0.0 },
+            Vec2 { x: 0.0, y: 400.0 },
+        ];
+
+        assert!(grid.calibrate(&points).is_err());
+    }
+
+    #[test]
+    fn rect_single_cell() {
+        let grid = test_grid(10);
+        let a = HexCoord { q: 1, r: 1 };
+
+        assert_eq!(grid.cells_in_rect(&a, &a), vec![a]);
+    }
+
+    #[test]
+    fn parallelogram_is_inclusive_in_every_direction() {
+        let grid = test_grid(10);
+
+        let a = HexCoord { q: -1, r: -2 };
+        let b = HexCoord { q: 2, r: 1 };
+        let c = HexCoord { q: -1, r: 1 };
+        let d = HexCoord { q: 2, r: -2 };
+
+        let expected = sorted(grid.cells_in_parallelogram(&a, &b));
+        assert_eq!(expected.len(), 4 * 4);
+
+        assert_eq!(expected, sorted(grid.cells_in_parallelogram(&b, &a)));
+        assert_eq!(expected, sorted(grid.cells_in_parallelogram(&c, &d)));
+        assert_eq!(expected, sorted(grid.cells_in_parallelogram(&d, &c)));
+
+        for corner in [a, b, c, d] {
+            assert!(expected.contains(&(corner.r, corner.q)));
+        }
+    }
+
+    #[test]
+    fn outline_of_parallelogram() {
+        let grid = test_grid(10);
+
+        let region =
+            grid.cells_in_parallelogram(&HexCoord { q: 0, r: 0 }, &HexCoord { q: 2, r: 2 });
+        let outline = Grid::get_outline(&region);
+
+        assert_eq!(outline.len(), 8);
+        assert!(!outline.contains(&HexCoord { q: 1, r: 1 }));
+    }
+
+    #[test]
+    fn occupants_follow_their_tokens() {
+        let mut grid = Grid::new(GridShape::Hexagon { radius: 3 }, GridSettings::default());
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let (here, there) = (HexCoord { q: 0, r: 0 }, HexCoord { q: 1, r: 0 });
+
+        assert_eq!(grid.occupy(a, here), None);
+        assert_eq!(grid.occupy(b, here), None);
+        assert_eq!(grid.occupants(&here), &[a, b]);
+        // Sharing tokens are spread out, either side of the centre
+        let centre = grid.hex_coord_to_pos(&here);
+        let (pos_a, pos_b) = (grid.occupant_pos(a, &here), grid.occupant_pos(b, &here));
+        assert_ne!(pos_a, centre);
+        assert!((pos_a + pos_b - centre * 2.0).length() < 1e-3);
 
-        let pos = grid.hex_coord_to_pos(&test_coord);
-        let coord = grid.pos_to_hex_coord(&pos);
+        assert_eq!(grid.occupy(a, there), Some(here));
+        assert_eq!(grid.occupants(&here), &[b]);
+        assert_eq!(grid.occupant_pos(b, &here), centre);
 
-        assert_eq!(test_coord, coord);
+        assert_eq!(grid.vacate(a), Some(there));
+        assert!(!grid.is_occupied(&there));
+        assert_eq!(grid.vacate(a), None);
     }
 }
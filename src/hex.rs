@@ -1,8 +1,11 @@
 use std::ops;
 
 use bevy_egui::egui::lerp;
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+use crate::grid::Layout;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub struct HexCoord {
     pub q: i32,
     pub r: i32,
@@ -14,6 +17,15 @@ pub struct FractionalHexCoord {
 }
 
 impl HexCoord {
+    pub const DIRECTIONS: [HexCoord; 6] = [
+        HexCoord { q: 1, r: 0 },
+        HexCoord { q: 1, r: -1 },
+        HexCoord { q: 0, r: -1 },
+        HexCoord { q: -1, r: 0 },
+        HexCoord { q: -1, r: 1 },
+        HexCoord { q: 0, r: 1 },
+    ];
+
     pub fn lerp(a: &HexCoord, b: &HexCoord, t: f32) -> HexCoord {
         Self::round(FractionalHexCoord {
             q: lerp(a.q as f32..=b.q as f32, t),
@@ -21,6 +33,14 @@ impl HexCoord {
         })
     }
 
+    // Lerps with both ends offset by epsilon, keeps the line off the edges between cells
+    pub fn lerp_nudged(a: &HexCoord, b: &HexCoord, t: f32, epsilon: f32) -> HexCoord {
+        Self::round(FractionalHexCoord {
+            q: lerp(a.q as f32 + epsilon..=b.q as f32 + epsilon, t),
+            r: lerp(a.r as f32 + epsilon..=b.r as f32 + epsilon, t),
+        })
+    }
+
     pub fn round(coord: FractionalHexCoord) -> HexCoord {
         let qgrid = coord.q.round() as i32;
         let rgrid = coord.r.round() as i32;
@@ -45,6 +65,29 @@ impl HexCoord {
         let vec = self - other;
         (vec.q.abs() + vec.r.abs() + (vec.q + vec.r).abs()) / 2
     }
+
+    // Column and row counted the way other hex tools do, with rows going down the screen. Odd
+    // rows of pointy topped hexes sit half a hex right, odd columns of flat topped hexes half a
+    // hex up, which keeps the grid's rectangles rectangular
+    pub fn to_offset(&self, layout: Layout) -> (i32, i32) {
+        match layout {
+            Layout::PointyTop => (self.q + self.r.div_euclid(2), -self.r),
+            Layout::FlatTop => (self.q, -(self.r + self.q.div_euclid(2))),
+        }
+    }
+
+    pub fn from_offset(col: i32, row: i32, layout: Layout) -> HexCoord {
+        match layout {
+            Layout::PointyTop => HexCoord {
+                q: col - (-row).div_euclid(2),
+                r: -row,
+            },
+            Layout::FlatTop => HexCoord {
+                q: col,
+                r: -row - col.div_euclid(2),
+            },
+        }
+    }
 }
 
 impl ops::Sub<&HexCoord> for &HexCoord {
@@ -0,0 +1,479 @@
+// Hex maps shared with other hex tools like Hexographer, Worldographer and Hex Kit. In JSON:
+//
+// {
+//   "layout": "PointyTop",
+//   "width": 12,
+//   "height": 8,
+//   "terrains": [{ "name": "Forest", "color": "#338c40" }],
+//   "hexes": [{ "col": 3, "row": 1, "terrain": "Forest", "label": "Oakvale" }]
+// }
+//
+// or in CSV, a hex on each line under a col,row,terrain,label header. Cols and rows count from the
+// top left hex, see HexCoord::to_offset. Everything but the hexes can be left out: the map is then
+// pointy topped, just big enough for its hexes, and terrains missing from the palette get a color
+// of their own
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cell::Cell,
+    grid::{Grid, Layout},
+    hex::HexCoord,
+    shape::{GridShape, MAX_SIZE},
+    terrain::{Palette, Terrain},
+};
+
+const CSV_HEADER: &str = "col,row,terrain,label";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MapTerrain {
+    pub name: String,
+    // #rrggbb
+    pub color: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MapHex {
+    pub col: i32,
+    pub row: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terrain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct HexMap {
+    #[serde(default)]
+    pub layout: Layout,
+    // Size in hexes, 0 to fit the hexes
+    #[serde(default)]
+    pub width: i32,
+    #[serde(default)]
+    pub height: i32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub terrains: Vec<MapTerrain>,
+    pub hexes: Vec<MapHex>,
+}
+
+// The top left offset of a set of hexes. Rounded so the shifted rows or columns stay the odd ones
+fn top_left(coords: &[HexCoord], layout: Layout) -> (i32, i32) {
+    let (col, row) = coords
+        .iter()
+        .map(|c| c.to_offset(layout))
+        .fold((i32::MAX, i32::MAX), |(c0, r0), (c, r)| {
+            (c0.min(c), r0.min(r))
+        });
+
+    match layout {
+        Layout::PointyTop => (col, row.div_euclid(2) * 2),
+        Layout::FlatTop => (col.div_euclid(2) * 2, row),
+    }
+}
+
+fn to_hex_color(color: Color) -> String {
+    let [r, g, b, _] = color.as_rgba_u8();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn from_hex_color(hex: &str) -> Option<Color> {
+    Color::hex(hex.trim_start_matches('#')).ok()
+}
+
+// A muted color picked from the name, so the same terrain always looks the same
+fn name_color(name: &str) -> Color {
+    let hue = name
+        .bytes()
+        .fold(0_u32, |h, b| h.wrapping_mul(31).wrapping_add(b as u32))
+        % 360;
+    Color::hsl(hue as f32, 0.4, 0.55)
+}
+
+// Fields of each record along with the line it starts on, skipping blank lines. Quoted fields
+// can hold commas, quotes and line breaks
+fn csv_records(csv: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut fields = vec![String::new()];
+    let (mut line, mut start) = (1, 1);
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(String::new()),
+            ('\r', false) => {}
+            ('\n', false) => {
+                records.push((start, std::mem::replace(&mut fields, vec![String::new()])));
+                line += 1;
+                start = line;
+            }
+            (c, _) => {
+                if c == '\n' {
+                    line += 1;
+                }
+                fields.last_mut().unwrap().push(c);
+            }
+        }
+    }
+    records.push((start, fields));
+
+    records.retain(|(_, fields)| fields.len() > 1 || !fields[0].trim().is_empty());
+    records
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl HexMap {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Not a hex map: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    // CSV has no room for the layout or size, so the layout is given and the size fits the hexes
+    pub fn from_csv(csv: &str, layout: Layout) -> Result<Self, String> {
+        let mut records = csv_records(csv).into_iter();
+        match records.next() {
+            Some((_, header)) if header.join(",").trim().eq_ignore_ascii_case(CSV_HEADER) => {}
+            _ => return Err(format!("Hex map CSVs start with {}", CSV_HEADER)),
+        }
+
+        let hexes = records
+            .map(|(line, fields)| {
+                let number = |n: usize| {
+                    fields
+                        .get(n)
+                        .and_then(|f| f.trim().parse().ok())
+                        .ok_or_else(|| format!("Line {} needs a col and a row", line))
+                };
+                let text = |n: usize| {
+                    fields
+                        .get(n)
+                        .map(|f| f.trim())
+                        .filter(|f| !f.is_empty())
+                        .map(str::to_string)
+                };
+
+                Ok(MapHex {
+                    col: number(0)?,
+                    row: number(1)?,
+                    terrain: text(2),
+                    label: text(3),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(HexMap {
+            layout,
+            hexes,
+            ..Default::default()
+        })
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = format!("{}\n", CSV_HEADER);
+        for hex in &self.hexes {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                hex.col,
+                hex.row,
+                csv_field(hex.terrain.as_deref().unwrap_or_default()),
+                csv_field(hex.label.as_deref().unwrap_or_default())
+            ));
+        }
+        csv
+    }
+
+    // The grid's hexes that have a terrain or a label, along with the terrains they use
+    pub fn capture(grid: &Grid, cells: &[Cell], palette: &Palette) -> Self {
+        let layout = grid.settings.layout;
+        // Unbounded grids only go as far as they've been drawn on
+        let coords: Vec<HexCoord> = match grid.shape {
+            GridShape::Unbounded => cells.iter().map(|c| c.pos).collect(),
+            _ => grid.shape.edge_coords(layout),
+        };
+        if coords.is_empty() {
+            return HexMap {
+                layout,
+                ..Default::default()
+            };
+        }
+
+        let (left, top) = top_left(&coords, layout);
+        let (right, bottom) = coords
+            .iter()
+            .map(|c| c.to_offset(layout))
+            .fold((left, top), |(c0, r0), (c, r)| (c0.max(c), r0.max(r)));
+
+        let mut cells: Vec<&Cell> = cells
+            .iter()
+            .filter(|c| grid.contains(&c.pos))
+            .filter(|c| c.terrain.is_some() || c.label.is_some())
+            .collect();
+        cells.sort_by_key(|c| {
+            let (col, row) = c.pos.to_offset(layout);
+            (row, col)
+        });
+
+        let used: HashSet<usize> = cells.iter().filter_map(|c| c.terrain).collect();
+        let terrains = palette
+            .terrains
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| used.contains(i))
+            .map(|(_, t)| MapTerrain {
+                name: t.name.clone(),
+                color: to_hex_color(t.color),
+            })
+            .collect();
+
+        let hexes = cells
+            .iter()
+            .map(|cell| {
+                let (col, row) = cell.pos.to_offset(layout);
+                MapHex {
+                    col: col - left,
+                    row: row - top,
+                    terrain: palette.get(cell.terrain).map(|t| t.name.clone()),
+                    label: cell.label.clone(),
+                }
+            })
+            .collect();
+
+        HexMap {
+            layout,
+            width: right - left + 1,
+            height: bottom - top + 1,
+            terrains,
+            hexes,
+        }
+    }
+
+    // The grid shape and cells of the map, adding the terrains the palette is missing to it. The
+    // map lands on one of the grid's rectangles when it can
+    pub fn to_grid(
+        &self,
+        palette: &mut Palette,
+        blank_color: Color,
+    ) -> Result<(GridShape, Vec<Cell>), String> {
+        let layout = self.layout;
+        if let Some(hex) = self.hexes.iter().find(|h| h.col < 0 || h.row < 0) {
+            return Err(format!("Hex {},{} is off the map", hex.col, hex.row));
+        }
+
+        let width = self
+            .width
+            .max(self.hexes.iter().map(|h| h.col + 1).max().unwrap_or(0));
+        let height = self
+            .height
+            .max(self.hexes.iter().map(|h| h.row + 1).max().unwrap_or(0));
+        if width == 0 || height == 0 {
+            return Err("The map has no hexes".to_string());
+        }
+        if width > MAX_SIZE || height > MAX_SIZE {
+            return Err(format!(
+                "The map is {}x{} hexes, maps can be at most {} across",
+                width, height, MAX_SIZE
+            ));
+        }
+
+        // Rows of flat topped hexes are the columns
+        let rectangle = match layout {
+            Layout::PointyTop => GridShape::Rectangle { width, height },
+            Layout::FlatTop => GridShape::Rectangle {
+                width: height,
+                height: width,
+            },
+        };
+        let rectangle_coords = rectangle.coords(layout);
+        let (left, top) = top_left(&rectangle_coords, layout);
+        let to_coord = |col: i32, row: i32| HexCoord::from_offset(col + left, row + top, layout);
+
+        let coords: HashSet<HexCoord> = (0..height)
+            .flat_map(|row| (0..width).map(move |col| to_coord(col, row)))
+            .collect();
+        let shape = if coords == rectangle_coords.into_iter().collect::<HashSet<_>>() {
+            rectangle
+        } else {
+            GridShape::Custom(coords)
+        };
+
+        let colors: HashMap<&str, Color> = self
+            .terrains
+            .iter()
+            .filter_map(|t| Some((t.name.as_str(), from_hex_color(&t.color)?)))
+            .collect();
+
+        let cells = self
+            .hexes
+            .iter()
+            .map(|hex| {
+                let terrain = hex.terrain.as_ref().map(|name| {
+                    palette.find(name).unwrap_or_else(|| {
+                        let color = colors.get(name.as_str()).copied();
+                        palette.terrains.push(Terrain::new(
+                            name,
+                            color.unwrap_or_else(|| name_color(name)),
+                            1.0,
+                        ));
+                        palette.terrains.len() - 1
+                    })
+                });
+                let (color, texture) = palette
+                    .get(terrain)
+                    .map_or((blank_color, Default::default()), |t| (t.color, t.texture));
+
+                Cell {
+                    color,
+                    base_color: color,
+                    texture,
+                    base_texture: texture,
+                    terrain,
+                    label: hex.label.clone(),
+                    ..Cell::blank(to_coord(hex.col, hex.row), blank_color)
+                }
+            })
+            .collect();
+
+        Ok((shape, cells))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::grid::GridSettings;
+
+    #[test]
+    fn offsets_round_trip_and_line_up() {
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            let grid = Grid::new(
+                GridShape::default(),
+                GridSettings {
+                    layout,
+                    ..Default::default()
+                },
+            );
+
+            let coords = GridShape::Hexagon { radius: 6 }.coords(layout);
+            for coord in coords {
+                let (col, row) = coord.to_offset(layout);
+                assert_eq!(HexCoord::from_offset(col, row, layout), coord);
+
+                // Further right with every column and further down with every row
+                let pos = grid.hex_coord_to_pos(&coord);
+                let right = grid.hex_coord_to_pos(&HexCoord::from_offset(col + 1, row, layout));
+                let below = grid.hex_coord_to_pos(&HexCoord::from_offset(col, row + 1, layout));
+                assert!(right.x > pos.x);
+                assert!(below.y < pos.y);
+            }
+        }
+    }
+
+    fn map(layout: Layout) -> HexMap {
+        HexMap {
+            layout,
+            width: 5,
+            height: 3,
+            terrains: vec![MapTerrain {
+                name: "Swamp".to_string(),
+                color: "#406040".to_string(),
+            }],
+            hexes: vec![
+                MapHex {
+                    col: 0,
+                    row: 0,
+                    terrain: Some("Forest".to_string()),
+                    label: Some("Oakvale, the old \"capital\"".to_string()),
+                },
+                MapHex {
+                    col: 4,
+                    row: 1,
+                    terrain: None,
+                    label: Some("Ruins\nof the mill".to_string()),
+                },
+                MapHex {
+                    col: 1,
+                    row: 2,
+                    terrain: Some("Swamp".to_string()),
+                    label: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn maps_round_trip_through_the_grid() {
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            let map = map(layout);
+            let mut palette = Palette::default();
+            let (shape, cells) = map.to_grid(&mut palette, Color::WHITE).unwrap();
+
+            // Missing terrains are added with the map's color
+            let swamp = palette.find("Swamp").unwrap();
+            assert_eq!(
+                palette.terrains[swamp].color,
+                from_hex_color("#406040").unwrap()
+            );
+            assert_eq!(palette.find("Forest"), Some(3));
+            assert_eq!(shape.coords(layout).len(), 15);
+
+            let mut grid = Grid::new(
+                shape,
+                GridSettings {
+                    layout,
+                    ..Default::default()
+                },
+            );
+            grid.blank_color = Color::WHITE;
+            let back = HexMap::capture(&grid, &cells, &palette);
+            assert_eq!(back.hexes, map.hexes);
+            assert_eq!((back.width, back.height), (5, 3));
+
+            // And through the files
+            assert_eq!(HexMap::from_json(&back.to_json().unwrap()).unwrap(), back);
+            let csv = HexMap::from_csv(&back.to_csv(), layout).unwrap();
+            assert_eq!(csv.hexes, map.hexes);
+        }
+    }
+
+    #[test]
+    fn odd_sizes_still_keep_the_odd_rows_shifted() {
+        // Three rows would start the grid's rectangle on a shifted row
+        let map =
+            HexMap::from_csv("col,row,terrain,label\n0,2,Water,\n", Layout::PointyTop).unwrap();
+        let (shape, cells) = map.to_grid(&mut Palette::default(), Color::WHITE).unwrap();
+        assert_eq!(shape.coords(Layout::PointyTop).len(), 3);
+
+        let (_, row) = cells[0].pos.to_offset(Layout::PointyTop);
+        assert_eq!(row.rem_euclid(2), 0);
+
+        // Sizes from the file are checked before any hexes are made
+        let huge = HexMap::from_json(r#"{"width":100000,"height":100000,"hexes":[]}"#).unwrap();
+        assert!(huge.to_grid(&mut Palette::default(), Color::WHITE).is_err());
+        let far = HexMap::from_csv("col,row,terrain,label\n100000,0,,\n", Layout::PointyTop);
+        assert!(far
+            .unwrap()
+            .to_grid(&mut Palette::default(), Color::WHITE)
+            .is_err());
+
+        assert!(HexMap::from_csv("x,y\n", Layout::PointyTop).is_err());
+        assert!(HexMap::from_csv("col,row,terrain,label\n1\n", Layout::PointyTop).is_err());
+    }
+}
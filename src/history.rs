@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+
+use crate::{cell::Cell, grid::Grid};
+
+// How many strokes can be undone
+const MAX_EDITS: usize = 100;
+
+// The cells a stroke changed, as they were and as it left them
+#[derive(Clone, PartialEq, Debug)]
+struct Edit {
+    before: Vec<Cell>,
+    after: Vec<Cell>,
+}
+
+#[derive(Event)]
+pub enum HistoryEvent {
+    Undo,
+    Redo,
+}
+
+// Strokes drawn on the grid, so they can be taken back
+#[derive(Component, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<HistoryEvent>()
+            .add_systems(Startup, setup)
+            .add_systems(Update, on_history_event);
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(History::default());
+}
+
+impl History {
+    // Takes each touched cell as it was before the stroke and as it is now. A new stroke can't
+    // be redone over
+    pub fn record(&mut self, changes: Vec<(Cell, Cell)>) {
+        let (before, after): (Vec<Cell>, Vec<Cell>) =
+            changes.into_iter().filter(|(b, a)| b != a).unzip();
+        if before.is_empty() {
+            return;
+        }
+
+        self.undo.push(Edit { before, after });
+        if self.undo.len() > MAX_EDITS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    // Cells to put back to undo the last stroke
+    pub fn undo(&mut self) -> Option<Vec<Cell>> {
+        let edit = self.undo.pop()?;
+        let cells = edit.before.clone();
+        self.redo.push(edit);
+        Some(cells)
+    }
+
+    // Cells to put back to draw the last undone stroke again
+    pub fn redo(&mut self) -> Option<Vec<Cell>> {
+        let edit = self.redo.pop()?;
+        let cells = edit.after.clone();
+        self.undo.push(edit);
+        Some(cells)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+fn on_history_event(
+    mut events: EventReader<HistoryEvent>,
+    mut history_q: Query<&mut History>,
+    mut grid_q: Query<&mut Grid>,
+    mut cell_q: Query<&mut Cell>,
+) {
+    for e in events.read() {
+        let mut history = history_q.single_mut();
+        let cells = match e {
+            HistoryEvent::Undo => history.undo(),
+            HistoryEvent::Redo => history.redo(),
+        };
+        let Some(cells) = cells else {
+            continue;
+        };
+
+        grid_q.single_mut().restore_cells(cells, |e, cell| {
+            let Ok(mut current) = cell_q.get_mut(e) else {
+                return;
+            };
+            if *current != cell {
+                *current = cell;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex::HexCoord;
+
+    fn cell(q: i32, color: Color) -> Cell {
+        Cell {
+            color,
+            ..Cell::blank(HexCoord { q, r: 0 }, Color::WHITE)
+        }
+    }
+
+    #[test]
+    fn strokes_are_undone_and_redone_in_order() {
+        let mut history = History::default();
+        history.record(vec![(cell(0, Color::WHITE), cell(0, Color::RED))]);
+        history.record(vec![
+            (cell(0, Color::RED), cell(0, Color::BLUE)),
+            (cell(1, Color::WHITE), cell(1, Color::BLUE)),
+        ]);
+        // Nothing changed, so nothing to undo
+        history.record(vec![(cell(2, Color::RED), cell(2, Color::RED))]);
+
+        assert_eq!(
+            history.undo(),
+            Some(vec![cell(0, Color::RED), cell(1, Color::WHITE)])
+        );
+        assert_eq!(history.undo(), Some(vec![cell(0, Color::WHITE)]));
+        assert_eq!(history.undo(), None);
+
+        assert_eq!(history.redo(), Some(vec![cell(0, Color::RED)]));
+        assert!(history.can_redo());
+
+        // Drawing again drops what was undone
+        history.record(vec![(cell(3, Color::WHITE), cell(3, Color::RED))]);
+        assert!(!history.can_redo());
+        assert_eq!(history.undo(), Some(vec![cell(3, Color::WHITE)]));
+        assert_eq!(history.undo(), Some(vec![cell(0, Color::WHITE)]));
+    }
+}
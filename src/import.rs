@@ -0,0 +1,179 @@
+use std::{collections::HashSet, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    background::BackgroundEvent,
+    cell::Cell,
+    fog::FogEvent,
+    grid::{Grid, GridSettings, Layout},
+    hexmap::HexMap,
+    light::{LightLevel, Lighting},
+    terrain::Palette,
+    uvtt::{Uvtt, UvttMap},
+    wall::{WallKind, Walls},
+};
+
+// Folder inside the assets folder that images taken from imported maps are saved to
+const IMPORTED_IMAGES: &str = "imported";
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ImportFormat {
+    // Dungeondraft and DungeonFog's .dd2vtt and .uvtt files
+    Uvtt,
+    // Overland maps from other hex tools, see hexmap
+    HexJson,
+    HexCsv,
+}
+
+impl ImportFormat {
+    pub const ALL: [ImportFormat; 3] = [
+        ImportFormat::Uvtt,
+        ImportFormat::HexJson,
+        ImportFormat::HexCsv,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImportFormat::Uvtt => "Universal VTT",
+            ImportFormat::HexJson => "Hex map JSON",
+            ImportFormat::HexCsv => "Hex map CSV",
+        }
+    }
+}
+
+// Replaces the map with the one in the file at the Import path
+#[derive(Event)]
+pub struct ImportEvent(pub ImportFormat);
+
+#[derive(Component, Default)]
+pub struct Import {
+    pub path: String,
+    // What the last import did
+    pub result: Option<Result<String, String>>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ImportEvent>()
+            .add_systems(Update, on_import_event);
+    }
+}
+
+// Reads the map, saving its image where the background can be loaded from
+fn read_uvtt(path: &str, layout: Layout) -> Result<(UvttMap, Option<String>), String> {
+    let json =
+        std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    let map = Uvtt::parse(&json)?.to_map(layout)?;
+
+    let image = match &map.image {
+        Some((bytes, extension)) => {
+            let stem = Path::new(path)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("map");
+            let asset = format!("{}/{}.{}", IMPORTED_IMAGES, stem, extension);
+            let file = Path::new("assets").join(&asset);
+
+            std::fs::create_dir_all(Path::new("assets").join(IMPORTED_IMAGES))
+                .and_then(|()| std::fs::write(&file, bytes))
+                .map_err(|e| format!("Couldn't save the map's image: {}", e))?;
+            Some(asset)
+        }
+        None => None,
+    };
+
+    Ok((map, image))
+}
+
+fn read_hex_map(path: &str, format: ImportFormat, layout: Layout) -> Result<HexMap, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    match format {
+        ImportFormat::HexCsv => HexMap::from_csv(&text, layout),
+        _ => HexMap::from_json(&text),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn on_import_event(
+    mut events: EventReader<ImportEvent>,
+    mut commands: Commands,
+    mut import_q: Query<&mut Import>,
+    mut grid_q: Query<&mut Grid>,
+    mut palette_q: Query<&mut Palette>,
+    mut walls_q: Query<&mut Walls>,
+    mut lighting_q: Query<&mut Lighting>,
+    mut background_event: EventWriter<BackgroundEvent>,
+    mut fog_event: EventWriter<FogEvent>,
+) {
+    for ImportEvent(format) in events.read() {
+        let mut import = import_q.single_mut();
+        let mut grid = grid_q.single_mut();
+        let mut walls = walls_q.single_mut();
+        let layout = grid.settings.layout;
+
+        // A new map, so nothing drawn on the old one is kept
+        let blank_color = grid.blank_color;
+        let result = match format {
+            ImportFormat::Uvtt => read_uvtt(&import.path, layout).map(|(map, image)| {
+                grid.reshape(
+                    map.shape,
+                    HashSet::new(),
+                    map.settings,
+                    blank_color,
+                    &mut commands,
+                );
+                let cells = map
+                    .lights
+                    .iter()
+                    .map(|(pos, light)| Cell {
+                        light: Some(*light),
+                        ..Cell::new(&grid, *pos)
+                    })
+                    .collect();
+                // Nothing is loaded right after reshaping, the cells wait for their chunks
+                grid.replace_cells(cells, |_, _| {});
+
+                *walls = map.walls;
+                *lighting_q.single_mut() = Lighting {
+                    enabled: !map.lights.is_empty() || map.ambient < LightLevel::Bright,
+                    ambient: map.ambient,
+                };
+                match image {
+                    Some(asset) => background_event.send(BackgroundEvent::Load(asset)),
+                    None => background_event.send(BackgroundEvent::Clear),
+                }
+
+                format!(
+                    "Imported {} walls, {} doors and {} lights",
+                    walls.count(|k| *k == WallKind::Wall),
+                    walls.count(|k| matches!(k, WallKind::Door { .. })),
+                    map.lights.len()
+                )
+            }),
+            ImportFormat::HexJson | ImportFormat::HexCsv => {
+                read_hex_map(&import.path, *format, layout).and_then(|map| {
+                    let mut palette = palette_q.single_mut();
+                    let (shape, cells) = map.to_grid(&mut palette, blank_color)?;
+
+                    let settings = GridSettings {
+                        layout: map.layout,
+                        ..grid.settings
+                    };
+                    grid.reshape(shape, HashSet::new(), settings, blank_color, &mut commands);
+                    grid.replace_cells(cells, |_, _| {});
+                    walls.clear();
+
+                    Ok(format!("Imported {} hexes", map.hexes.len()))
+                })
+            }
+        };
+
+        if result.is_ok() {
+            fog_event.send(FogEvent::Reset);
+        }
+        import.result = Some(result);
+    }
+}
@@ -1,7 +1,7 @@
 mod state;
 
 use bevy::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use crate::ReqTimer;
 use state::State;
@@ -38,7 +38,7 @@ pub enum CreatureType {
     Monster(Monster),
 }
 
-#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Creature {
     pub id: String,
     pub name: String,
@@ -68,7 +68,7 @@ pub enum TrackerEvent {
 pub struct Plugin;
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        // app.add_systems(Update).add_event::<TrackerEvent>();
+        app.add_event::<TrackerEvent>();
     }
 }
 
@@ -91,6 +91,23 @@ impl Tracker {
 
         None
     }
+
+    // Passes the turn to the next creature in order, going back to the top after the last
+    pub fn next_turn(&mut self) -> Option<Creature> {
+        if self.ordered.is_empty() {
+            return None;
+        }
+
+        let next = self
+            .ordered
+            .iter()
+            .position(|c| c.active)
+            .map_or(0, |i| (i + 1) % self.ordered.len());
+        for (i, c) in self.ordered.iter_mut().enumerate() {
+            c.active = i == next;
+        }
+        Some(self.ordered[next].clone())
+    }
 }
 
 // #[derive(Component)]
@@ -138,3 +155,33 @@ impl Tracker {
 //         commands.entity(e).despawn_recursive();
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn creature(id: &str, active: bool) -> Creature {
+        Creature {
+            id: id.to_string(),
+            name: id.to_string(),
+            initiative: 10,
+            player: Some(true),
+            active,
+            number: 0,
+            cr: None,
+            current_ac: 10,
+        }
+    }
+
+    #[test]
+    fn turns_go_round_the_order() {
+        let mut tracker = Tracker::default();
+        assert_eq!(tracker.next_turn(), None);
+
+        tracker.ordered = vec![creature("a", false), creature("b", false)];
+        assert_eq!(tracker.next_turn().map(|c| c.id), Some("a".to_string()));
+        assert_eq!(tracker.next_turn().map(|c| c.id), Some("b".to_string()));
+        assert_eq!(tracker.next_turn().map(|c| c.id), Some("a".to_string()));
+        assert_eq!(tracker.ordered.iter().filter(|c| c.active).count(), 1);
+    }
+}
@@ -0,0 +1,85 @@
+use bevy::prelude::*;
+
+use crate::{
+    cell::{Cell, CellEvent},
+    grid::Grid,
+    hex::HexCoord,
+    token::FEET_PER_HEX,
+};
+
+// What the status bar and the cell window are showing
+#[derive(Component, Default)]
+pub struct Inspector {
+    // Cell under the pointer
+    pub hovered: Option<HexCoord>,
+    // Cell open in the cell window, stays put while the pointer moves
+    pub pinned: Option<HexCoord>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(Update, track_hover);
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(Inspector::default());
+}
+
+// Axial coords with the column and row other hex tools would give the hex
+pub fn coord_label(grid: &Grid, coord: &HexCoord) -> String {
+    let (col, row) = coord.to_offset(grid.settings.layout);
+    format!("q {}, r {} (col {}, row {})", coord.q, coord.r, col, row)
+}
+
+pub fn distance_label(from: &HexCoord, to: &HexCoord) -> String {
+    let hexes = from.distance(to);
+    format!(
+        "{} {} ({} ft)",
+        hexes,
+        if hexes == 1 { "hex" } else { "hexes" },
+        hexes * FEET_PER_HEX
+    )
+}
+
+fn track_hover(
+    mut events: EventReader<CellEvent>,
+    mut inspector_q: Query<&mut Inspector>,
+    cell_q: Query<&Cell>,
+) {
+    let mut inspector = inspector_q.single_mut();
+    for e in events.read() {
+        match e {
+            CellEvent::Over(e) => inspector.hovered = cell_q.get(*e).ok().map(|c| c.pos),
+            CellEvent::Out => inspector.hovered = None,
+            CellEvent::Pressed(..) | CellEvent::Released(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::GridSettings, shape::GridShape};
+
+    #[test]
+    fn labels_give_both_coords_and_feet() {
+        let grid = Grid::new(GridShape::default(), GridSettings::default());
+        assert_eq!(
+            coord_label(&grid, &HexCoord { q: 1, r: -3 }),
+            "q 1, r -3 (col -1, row 3)"
+        );
+
+        let origin = HexCoord { q: 0, r: 0 };
+        assert_eq!(
+            distance_label(&origin, &HexCoord { q: 2, r: 1 }),
+            "3 hexes (15 ft)"
+        );
+        assert_eq!(
+            distance_label(&origin, &HexCoord { q: 0, r: 1 }),
+            "1 hex (5 ft)"
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::CameraEvent,
+    draw::{Draw, DrawMode, DrawTool},
+    fog::Fog,
+    history::HistoryEvent,
+    initiative_tracker::{Tracker, TrackerEvent},
+    inspector::Inspector,
+    preferences::Preferences,
+    selection::{SelectionEvent, NUDGE_DIRECTIONS},
+    terrain::Palette,
+    view::ViewMode,
+};
+
+// What a shortcut does
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Action {
+    TerrainTool,
+    PaintTool,
+    EraseTool,
+    CutTool,
+    CellMode,
+    BoxMode,
+    LineMode,
+    // Picks the palette entry, counting from 1. Its color when painting, the terrain otherwise
+    Swatch(usize),
+    Undo,
+    Redo,
+    NextTurn,
+    ToggleFog,
+    CenterOnActive,
+    ZoomToFit,
+    // Opens the hovered cell in the cell window
+    EditCell,
+    // Moves the selected tokens a hex, see NUDGE_DIRECTIONS
+    Nudge(usize),
+}
+
+impl Action {
+    pub const ALL: [Action; 29] = [
+        Action::TerrainTool,
+        Action::PaintTool,
+        Action::EraseTool,
+        Action::CutTool,
+        Action::CellMode,
+        Action::BoxMode,
+        Action::LineMode,
+        Action::Swatch(1),
+        Action::Swatch(2),
+        Action::Swatch(3),
+        Action::Swatch(4),
+        Action::Swatch(5),
+        Action::Swatch(6),
+        Action::Swatch(7),
+        Action::Swatch(8),
+        Action::Swatch(9),
+        Action::Undo,
+        Action::Redo,
+        Action::NextTurn,
+        Action::ToggleFog,
+        Action::CenterOnActive,
+        Action::ZoomToFit,
+        Action::EditCell,
+        Action::Nudge(0),
+        Action::Nudge(1),
+        Action::Nudge(2),
+        Action::Nudge(3),
+        Action::Nudge(4),
+        Action::Nudge(5),
+    ];
+
+    pub fn name(&self) -> String {
+        match self {
+            Action::TerrainTool => "Terrain tool".to_string(),
+            Action::PaintTool => "Paint tool".to_string(),
+            Action::EraseTool => "Erase tool".to_string(),
+            Action::CutTool => "Cut tool".to_string(),
+            Action::CellMode => "Draw cells".to_string(),
+            Action::BoxMode => "Draw boxes".to_string(),
+            Action::LineMode => "Draw lines".to_string(),
+            Action::Swatch(n) => format!("Swatch {}", n),
+            Action::Undo => "Undo".to_string(),
+            Action::Redo => "Redo".to_string(),
+            Action::NextTurn => "Next turn".to_string(),
+            Action::ToggleFog => "Toggle fog".to_string(),
+            Action::CenterOnActive => "Center on active token".to_string(),
+            Action::ZoomToFit => "Zoom to fit".to_string(),
+            Action::EditCell => "Edit hovered cell".to_string(),
+            Action::Nudge(d) => format!("Move selection {}", NUDGE_DIRECTIONS[*d % 6]),
+        }
+    }
+}
+
+// A key with the modifiers held down with it
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Binding {
+    pub key: KeyCode,
+    #[serde(default)]
+    pub ctrl: bool,
+    #[serde(default)]
+    pub shift: bool,
+    #[serde(default)]
+    pub alt: bool,
+}
+
+impl Binding {
+    pub const fn key(key: KeyCode) -> Self {
+        Self {
+            key,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    pub const fn ctrl(key: KeyCode) -> Self {
+        Self {
+            ctrl: true,
+            ..Self::key(key)
+        }
+    }
+
+    pub const fn shift(key: KeyCode) -> Self {
+        Self {
+            shift: true,
+            ..Self::key(key)
+        }
+    }
+
+    // The key with the modifiers held right now, Cmd counts as Ctrl
+    pub fn pressed(key: KeyCode, keys: &Input<KeyCode>) -> Self {
+        Self {
+            key,
+            ctrl: keys.any_pressed([
+                KeyCode::ControlLeft,
+                KeyCode::ControlRight,
+                KeyCode::SuperLeft,
+                KeyCode::SuperRight,
+            ]),
+            shift: keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+            alt: keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        let key = format!("{:?}", self.key);
+        // Digits are Key1 to Key9
+        let key = match key.strip_prefix("Key") {
+            Some(digit) if !digit.is_empty() => digit.to_string(),
+            _ => key,
+        };
+
+        let mut name = String::new();
+        for (held, modifier) in [
+            (self.ctrl, "Ctrl+"),
+            (self.shift, "Shift+"),
+            (self.alt, "Alt+"),
+        ] {
+            if held {
+                name.push_str(modifier);
+            }
+        }
+        name + &key
+    }
+}
+
+fn is_modifier(key: KeyCode) -> bool {
+    matches!(
+        key,
+        KeyCode::ControlLeft
+            | KeyCode::ControlRight
+            | KeyCode::ShiftLeft
+            | KeyCode::ShiftRight
+            | KeyCode::AltLeft
+            | KeyCode::AltRight
+            | KeyCode::SuperLeft
+            | KeyCode::SuperRight
+    )
+}
+
+// Shortcuts of each action, actions can be left without one. Saved with the preferences
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keymap {
+    bindings: HashMap<Action, Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let digits = [
+            KeyCode::Key1,
+            KeyCode::Key2,
+            KeyCode::Key3,
+            KeyCode::Key4,
+            KeyCode::Key5,
+            KeyCode::Key6,
+            KeyCode::Key7,
+            KeyCode::Key8,
+            KeyCode::Key9,
+        ];
+
+        let mut bindings = HashMap::from([
+            (Action::TerrainTool, Binding::key(KeyCode::T)),
+            (Action::PaintTool, Binding::key(KeyCode::P)),
+            (Action::EraseTool, Binding::key(KeyCode::E)),
+            (Action::CutTool, Binding::key(KeyCode::X)),
+            (Action::CellMode, Binding::key(KeyCode::C)),
+            (Action::BoxMode, Binding::key(KeyCode::B)),
+            (Action::LineMode, Binding::key(KeyCode::L)),
+            (Action::Undo, Binding::ctrl(KeyCode::Z)),
+            (Action::Redo, Binding::ctrl(KeyCode::Y)),
+            (Action::NextTurn, Binding::key(KeyCode::Space)),
+            (Action::ToggleFog, Binding::key(KeyCode::F)),
+            (Action::CenterOnActive, Binding::key(KeyCode::Home)),
+            (Action::ZoomToFit, Binding::key(KeyCode::Key0)),
+            (Action::EditCell, Binding::key(KeyCode::I)),
+            // Up and down are opposites, as are their shifted keys
+            (Action::Nudge(0), Binding::key(KeyCode::Right)),
+            (Action::Nudge(1), Binding::key(KeyCode::Up)),
+            (Action::Nudge(2), Binding::shift(KeyCode::Up)),
+            (Action::Nudge(3), Binding::key(KeyCode::Left)),
+            (Action::Nudge(4), Binding::key(KeyCode::Down)),
+            (Action::Nudge(5), Binding::shift(KeyCode::Down)),
+        ]);
+        for (i, key) in digits.into_iter().enumerate() {
+            bindings.insert(Action::Swatch(i + 1), Binding::key(key));
+        }
+
+        Self { bindings }
+    }
+}
+
+// Written as a list with unbound actions set to null, actions missing from it keep their
+// default shortcut
+impl Serialize for Keymap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bindings: Vec<(Action, Option<Binding>)> =
+            Action::ALL.iter().map(|a| (*a, self.get(a))).collect();
+        bindings.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Keymap {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut keymap = Keymap::default();
+        for (action, binding) in Vec::<(Action, Option<Binding>)>::deserialize(deserializer)? {
+            match binding {
+                Some(binding) => keymap.bindings.insert(action, binding),
+                None => keymap.bindings.remove(&action),
+            };
+        }
+        Ok(keymap)
+    }
+}
+
+impl Keymap {
+    pub fn get(&self, action: &Action) -> Option<Binding> {
+        self.bindings.get(action).copied()
+    }
+
+    pub fn action(&self, binding: &Binding) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|a| self.bindings.get(a) == Some(binding))
+    }
+
+    // Gives the action a new shortcut, unless another action already has it
+    pub fn bind(&mut self, action: Action, binding: Binding) -> Result<(), Action> {
+        match self.action(&binding) {
+            Some(other) if other != action => Err(other),
+            _ => {
+                self.bindings.insert(action, binding);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn unbind(&mut self, action: &Action) {
+        self.bindings.remove(action);
+    }
+
+    // Pairs of actions sharing a shortcut, only possible in a file edited by hand
+    pub fn conflicts(&self) -> Vec<(Action, Action)> {
+        let mut conflicts = Vec::new();
+        for (i, a) in Action::ALL.iter().enumerate() {
+            for b in &Action::ALL[i + 1..] {
+                if self.get(a).is_some() && self.get(a) == self.get(b) {
+                    conflicts.push((*a, *b));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+#[derive(Event)]
+pub struct ActionEvent(pub Action);
+
+// State of the shortcuts window
+#[derive(Component, Default)]
+pub struct Shortcuts {
+    // Waiting for the keys to bind to the action
+    pub rebinding: Option<Action>,
+    // Why the last binding was refused
+    pub error: Option<String>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ActionEvent>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (read_keys, on_action_event)
+                    .chain()
+                    .run_if(in_state(ViewMode::Gm)),
+            );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(Shortcuts::default());
+}
+
+fn read_keys(
+    keys: Res<Input<KeyCode>>,
+    mut contexts: EguiContexts,
+    mut preferences_q: Query<&mut Preferences>,
+    mut shortcuts_q: Query<&mut Shortcuts>,
+    mut action_event: EventWriter<ActionEvent>,
+) {
+    let mut shortcuts = shortcuts_q.single_mut();
+    let pressed = keys
+        .get_just_pressed()
+        .copied()
+        .filter(|k| !is_modifier(*k));
+
+    if let Some(action) = shortcuts.rebinding {
+        let Some(key) = pressed.last() else {
+            return;
+        };
+        shortcuts.rebinding = None;
+        shortcuts.error = None;
+        if key == KeyCode::Escape {
+            return;
+        }
+
+        let binding = Binding::pressed(key, &keys);
+        let mut preferences = preferences_q.single_mut();
+        if preferences.keymap.get(&action) == Some(binding) {
+            return;
+        }
+        if let Err(other) = preferences.keymap.bind(action, binding) {
+            shortcuts.error = Some(format!(
+                "{} is already the shortcut for {}",
+                binding.name(),
+                other.name()
+            ));
+        }
+        return;
+    }
+
+    // Typing in a text field
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let keymap = &preferences_q.single().keymap;
+    for key in pressed {
+        if let Some(action) = keymap.action(&Binding::pressed(key, &keys)) {
+            action_event.send(ActionEvent(action));
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn on_action_event(
+    mut events: EventReader<ActionEvent>,
+    mut draw_q: Query<&mut Draw>,
+    palette_q: Query<&Palette>,
+    mut fog_q: Query<&mut Fog>,
+    mut tracker_q: Query<&mut Tracker>,
+    mut inspector_q: Query<&mut Inspector>,
+    mut history_event: EventWriter<HistoryEvent>,
+    mut tracker_event: EventWriter<TrackerEvent>,
+    mut camera_event: EventWriter<CameraEvent>,
+    mut selection_event: EventWriter<SelectionEvent>,
+) {
+    for ActionEvent(action) in events.read() {
+        let mut draw = draw_q.single_mut();
+
+        match action {
+            Action::TerrainTool => draw.tool = DrawTool::Terrain,
+            Action::PaintTool => draw.tool = DrawTool::Paint,
+            Action::EraseTool => draw.tool = DrawTool::Erase,
+            Action::CutTool => draw.tool = DrawTool::Cut,
+            Action::CellMode => draw.draw_mode = DrawMode::Cell,
+            Action::BoxMode => draw.draw_mode = DrawMode::Box,
+            Action::LineMode => draw.draw_mode = DrawMode::Line,
+            Action::Swatch(n) => {
+                let Some(terrain) = n
+                    .checked_sub(1)
+                    .and_then(|i| palette_q.single().get(Some(i)))
+                else {
+                    continue;
+                };
+                if draw.tool == DrawTool::Paint {
+                    draw.color = terrain.color;
+                    draw.texture = terrain.texture;
+                } else {
+                    draw.tool = DrawTool::Terrain;
+                    draw.terrain = n - 1;
+                }
+            }
+            Action::Undo => history_event.send(HistoryEvent::Undo),
+            Action::Redo => history_event.send(HistoryEvent::Redo),
+            Action::NextTurn => {
+                if let Some(creature) = tracker_q.single_mut().next_turn() {
+                    tracker_event.send(TrackerEvent::TurnUpdate(creature));
+                }
+            }
+            Action::ToggleFog => {
+                let mut fog = fog_q.single_mut();
+                fog.enabled = !fog.enabled;
+            }
+            Action::CenterOnActive => {
+                if let Some(active) = tracker_q.single().ordered.iter().find(|c| c.active) {
+                    camera_event.send(CameraEvent::CenterOnToken(active.id.clone()));
+                }
+            }
+            Action::ZoomToFit => camera_event.send(CameraEvent::ZoomToFit),
+            Action::EditCell => {
+                let mut inspector = inspector_q.single_mut();
+                inspector.pinned = inspector.hovered;
+            }
+            Action::Nudge(d) => selection_event.send(SelectionEvent::Nudge(*d)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_shortcuts_dont_clash() {
+        let keymap = Keymap::default();
+        assert!(keymap.conflicts().is_empty());
+        assert!(Action::ALL.iter().all(|a| keymap.get(a).is_some()));
+        assert_eq!(Binding::ctrl(KeyCode::Z).name(), "Ctrl+Z");
+        assert_eq!(Binding::key(KeyCode::Key3).name(), "3");
+    }
+
+    #[test]
+    fn taken_shortcuts_are_refused() {
+        let mut keymap = Keymap::default();
+        let undo = Binding::ctrl(KeyCode::Z);
+
+        assert_eq!(keymap.bind(Action::Redo, undo), Err(Action::Undo));
+        assert_eq!(keymap.get(&Action::Redo), Some(Binding::ctrl(KeyCode::Y)));
+
+        keymap.unbind(&Action::Undo);
+        assert_eq!(keymap.bind(Action::Redo, undo), Ok(()));
+        assert_eq!(keymap.action(&undo), Some(Action::Redo));
+    }
+
+    #[test]
+    fn saved_keymaps_keep_new_defaults() {
+        let mut keymap = Keymap::default();
+        keymap.unbind(&Action::ToggleFog);
+        keymap
+            .bind(Action::NextTurn, Binding::key(KeyCode::N))
+            .unwrap();
+
+        let json = serde_json::to_string(&keymap).unwrap();
+        assert_eq!(serde_json::from_str::<Keymap>(&json).unwrap(), keymap);
+
+        // Hand edited into a clash, and missing an action
+        let edited: Keymap =
+            serde_json::from_str(r#"[["Undo", {"key": "T"}], ["Redo", null]]"#).unwrap();
+        assert_eq!(edited.get(&Action::Redo), None);
+        assert_eq!(edited.get(&Action::CutTool), Some(Binding::key(KeyCode::X)));
+        assert_eq!(
+            edited.conflicts(),
+            vec![(Action::TerrainTool, Action::Undo)]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    cell::Cell,
+    grid::{Grid, GridSettings, Layout},
+    hex::HexCoord,
+    layer::Layer,
+    preferences::Preferences,
+};
+
+// Text for the labels of the loaded cells
+#[derive(Component, Default)]
+struct Labels {
+    texts: HashMap<HexCoord, (Entity, String)>,
+    // Column and row of each loaded cell, while they're turned on
+    coords: HashMap<HexCoord, Entity>,
+    coords_layout: Option<Layout>,
+    settings: Option<GridSettings>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(PostUpdate, (sync_labels, sync_coordinate_labels));
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(Labels::default());
+}
+
+// Under the centre of the cell, clear of token names. Above it with up set
+fn label_transform(grid: &Grid, coord: &HexCoord, up: bool) -> Transform {
+    let offset = if up { 0.55 } else { -0.55 };
+    let pos = grid.hex_coord_to_pos(coord) + Vec2::Y * grid.settings.hex_size * offset;
+    Transform::from_translation(pos.extend(Layer::Objects.z() + 0.6))
+        .with_scale(Vec3::splat(grid.scale()))
+}
+
+fn sync_labels(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut labels_q: Query<&mut Labels>,
+    grid_q: Query<&Grid>,
+    changed_q: Query<&Cell, Changed<Cell>>,
+    mut removed: RemovedComponents<Cell>,
+) {
+    let (Ok(mut labels), Ok(grid)) = (labels_q.get_single_mut(), grid_q.get_single()) else {
+        return;
+    };
+
+    // Cells of unloaded chunks take their labels with them
+    if removed.read().count() > 0 {
+        labels.texts.retain(|coord, (e, _)| {
+            let loaded = grid.cells.contains_key(coord);
+            if !loaded {
+                commands.entity(*e).despawn_recursive();
+            }
+            loaded
+        });
+    }
+
+    if labels.settings != Some(grid.settings) {
+        labels.settings = Some(grid.settings);
+        for (coord, (e, _)) in &labels.texts {
+            commands
+                .entity(*e)
+                .insert(label_transform(grid, coord, false));
+        }
+    }
+
+    for cell in &changed_q {
+        if cell.label.as_ref() == labels.texts.get(&cell.pos).map(|(_, shown)| shown) {
+            continue;
+        }
+
+        if let Some((e, _)) = labels.texts.remove(&cell.pos) {
+            commands.entity(e).despawn_recursive();
+        }
+        if let Some(label) = &cell.label {
+            let e = commands
+                .spawn((
+                    Text2dBundle {
+                        text: Text::from_section(
+                            label.clone(),
+                            TextStyle {
+                                font: asset_server.load("fonts/Roboto-Regular.ttf"),
+                                font_size: 22.0,
+                                color: Color::BLACK,
+                            },
+                        ),
+                        transform: label_transform(grid, &cell.pos, false),
+                        ..Default::default()
+                    },
+                    Layer::Objects,
+                ))
+                .id();
+            labels.texts.insert(cell.pos, (e, label.clone()));
+        }
+    }
+}
+
+fn sync_coordinate_labels(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut labels_q: Query<&mut Labels>,
+    grid_q: Query<Ref<Grid>>,
+    preferences_q: Query<Ref<Preferences>>,
+) {
+    let (Ok(mut labels), Ok(grid), Ok(preferences)) = (
+        labels_q.get_single_mut(),
+        grid_q.get_single(),
+        preferences_q.get_single(),
+    ) else {
+        return;
+    };
+    if !grid.is_changed() && !preferences.is_changed() {
+        return;
+    }
+
+    // A new layout numbers the columns and rows differently, so they're all written again
+    let relabel = labels.coords_layout != Some(grid.settings.layout);
+    labels.coords_layout = Some(grid.settings.layout);
+    labels.coords.retain(|coord, e| {
+        let keep = preferences.coordinate_labels && !relabel && grid.cells.contains_key(coord);
+        if !keep {
+            commands.entity(*e).despawn_recursive();
+        } else if grid.is_changed() {
+            commands
+                .entity(*e)
+                .insert(label_transform(&grid, coord, true));
+        }
+        keep
+    });
+    if !preferences.coordinate_labels {
+        return;
+    }
+
+    let font = asset_server.load("fonts/Roboto-Regular.ttf");
+    for coord in grid.cells.keys() {
+        if labels.coords.contains_key(coord) {
+            continue;
+        }
+
+        let (col, row) = coord.to_offset(grid.settings.layout);
+        let e = commands
+            .spawn((
+                Text2dBundle {
+                    text: Text::from_section(
+                        format!("{},{}", col, row),
+                        TextStyle {
+                            font: font.clone(),
+                            font_size: 14.0,
+                            color: Color::DARK_GRAY,
+                        },
+                    ),
+                    transform: label_transform(&grid, coord, true),
+                    ..Default::default()
+                },
+                Layer::Objects,
+            ))
+            .id();
+        labels.coords.insert(*coord, e);
+    }
+}
@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::texture::CellTexture;
+
+// Bottom to top
+#[derive(Component, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub enum Layer {
+    Background,
+    #[default]
+    Terrain,
+    Objects,
+    GmNotes,
+    Tokens,
+    // Darkness and the glow of lights
+    Lighting,
+    // Covers what the party hasn't seen
+    Fog,
+    Effects,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 8] = [
+        Layer::Background,
+        Layer::Terrain,
+        Layer::Objects,
+        Layer::GmNotes,
+        Layer::Tokens,
+        Layer::Lighting,
+        Layer::Fog,
+        Layer::Effects,
+    ];
+
+    // Layers painted onto cells, drawn with the cell meshes
+    pub const PAINTED: [Layer; 3] = [Layer::Terrain, Layer::Objects, Layer::GmNotes];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::Background => "Background",
+            Layer::Terrain => "Terrain",
+            Layer::Objects => "Objects",
+            Layer::GmNotes => "GM notes",
+            Layer::Tokens => "Tokens",
+            Layer::Lighting => "Lighting",
+            Layer::Fog => "Fog of war",
+            Layer::Effects => "Effects",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Layer::ALL.iter().position(|l| l == self).unwrap()
+    }
+
+    // Depth of everything on the layer, each layer gets a whole unit for its own children
+    pub fn z(&self) -> f32 {
+        self.index() as f32
+    }
+
+    pub fn is_painted(&self) -> bool {
+        Layer::PAINTED.contains(self)
+    }
+
+    // Layers the drawing tools work on, drawing on the fog reveals and hides cells
+    // and drawing on the lighting places lights
+    pub fn is_drawable(&self) -> bool {
+        self.is_painted() || matches!(self, Layer::Fog | Layer::Lighting)
+    }
+}
+
+// Color and texture painted onto a cell in one of the layers above the terrain
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Paint {
+    pub color: Color,
+    pub texture: CellTexture,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct LayerSettings {
+    pub visible: bool,
+    // Locked layers can't be drawn on or edited
+    pub locked: bool,
+    pub opacity: f32,
+}
+
+impl Default for LayerSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            locked: false,
+            opacity: 1.0,
+        }
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Layers {
+    settings: [LayerSettings; Layer::ALL.len()],
+    // The layer drawing tools work on
+    #[serde(skip)]
+    pub selected: Layer,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        let mut layers = Self {
+            settings: Default::default(),
+            selected: Layer::default(),
+        };
+        // The GM sees through the fog and darkness, players never do
+        layers.get_mut(Layer::Fog).opacity = 0.5;
+        layers.get_mut(Layer::Lighting).opacity = 0.5;
+
+        layers
+    }
+}
+
+impl Layers {
+    // Takes the settings from other, keeping the selected layer
+    pub fn set_settings(&mut self, other: &Layers) {
+        self.settings = other.settings;
+    }
+
+    pub fn same_settings(&self, other: &Layers) -> bool {
+        self.settings == other.settings
+    }
+
+    pub fn get(&self, layer: Layer) -> &LayerSettings {
+        &self.settings[layer.index()]
+    }
+
+    pub fn get_mut(&mut self, layer: Layer) -> &mut LayerSettings {
+        &mut self.settings[layer.index()]
+    }
+
+    pub fn is_locked(&self, layer: Layer) -> bool {
+        self.get(layer).locked
+    }
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, apply_layers);
+    }
+}
+
+type LayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Ref<'static, Layer>,
+        &'static mut Visibility,
+        Option<&'static Handle<ColorMaterial>>,
+        Option<&'static mut Sprite>,
+    ),
+>;
+
+// Shows, hides and fades everything on a layer to match its settings
+fn apply_layers(
+    layers_q: Query<Ref<Layers>>,
+    mut layer_q: LayerQuery,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let Ok(layers) = layers_q.get_single() else {
+        return;
+    };
+
+    for (layer, mut visibility, material, sprite) in &mut layer_q {
+        if !layers.is_changed() && !layer.is_added() {
+            continue;
+        }
+
+        let settings = layers.get(*layer);
+        *visibility = if settings.visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+
+        if let Some(material) = material.and_then(|m| materials.get_mut(m)) {
+            material.color.set_a(settings.opacity);
+        }
+        if let Some(mut sprite) = sprite {
+            sprite.color.set_a(settings.opacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_stack_in_order() {
+        for pair in Layer::ALL.windows(2) {
+            assert!(pair[0].z() < pair[1].z());
+        }
+
+        let mut layers = Layers::default();
+        assert_eq!(layers.selected, Layer::Terrain);
+        assert!(Layer::ALL.iter().all(|l| layers.get(*l).visible));
+
+        layers.get_mut(Layer::Tokens).locked = true;
+        assert!(layers.is_locked(Layer::Tokens));
+        assert!(!layers.is_locked(Layer::Terrain));
+    }
+}
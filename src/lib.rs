@@ -1,27 +1,66 @@
 #[macro_use]
 extern crate lazy_static;
 
-mod cell;
+mod background;
+mod camera;
+pub mod cell;
+pub mod chunk;
 mod draw;
-mod grid;
-mod hex;
+pub mod export;
+mod fog;
+pub mod grid;
+pub mod hex;
+pub mod hexmap;
+mod history;
+pub mod import;
 mod initiative_tracker;
+mod inspector;
+mod keymap;
+mod label;
+pub mod layer;
+mod light;
+pub mod mesh;
+mod minimap;
+mod movement;
+mod pathfinding;
+mod preferences;
+mod selection;
+pub mod session;
+pub mod shape;
+pub mod snapshot;
+mod terrain;
+pub mod texture;
 mod token;
 mod ui;
+pub mod uvtt;
+mod view;
+pub mod wall;
 
 use bevy::{
     audio::AudioPlugin,
     core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping},
     prelude::*,
-    window::WindowPlugin,
+    window::{ExitCondition, WindowPlugin},
 };
 use bevy_egui::EguiPlugin;
 use bevy_mod_picking::prelude::*;
 use bevy_pancam::{PanCam, PanCamPlugin};
 use wasm_bindgen::prelude::*;
 
+use background::Background;
 use draw::Draw;
+use export::Export;
+use fog::Fog;
 use grid::Grid;
+use import::Import;
+use layer::Layers;
+use light::Lighting;
+use preferences::{Preferences, DEFAULT_PREFERENCES_PATH};
+use session::Session;
+use shape::GridShape;
+use terrain::{Palette, DEFAULT_PALETTE_PATH};
+use view::ViewMode;
+use wall::Walls;
 
 use crate::initiative_tracker::Tracker;
 
@@ -46,6 +85,8 @@ pub fn run() {
                     canvas: Some("#hexalon-canvas".into()),
                     ..Default::default()
                 }),
+                // The player window closes with the main one
+                exit_condition: ExitCondition::OnPrimaryClosed,
                 ..Default::default()
             })
             .disable::<AudioPlugin>(),
@@ -57,16 +98,44 @@ pub fn run() {
         EguiPlugin,
         // ReqwestPlugin,
         grid::Plugin,
+        cell::Plugin,
+        chunk::Plugin,
+        mesh::Plugin,
+        layer::Plugin,
+        background::Plugin,
+        terrain::Plugin,
+        texture::Plugin,
         ui::Plugin,
-        //         initiative_tracker::Plugin,
+        initiative_tracker::Plugin,
+        selection::Plugin,
     ))
+    .add_plugins((
+        camera::Plugin,
+        export::Plugin,
+        import::Plugin,
+        inspector::Plugin,
+        fog::Plugin,
+        history::Plugin,
+        keymap::Plugin,
+        label::Plugin,
+        light::Plugin,
+        minimap::Plugin,
+        preferences::Plugin,
+        snapshot::Plugin,
+        session::Plugin,
+        view::Plugin,
+        wall::Plugin,
+    ))
+    .add_plugins(movement::Plugin)
     .add_systems(Startup, setup)
     .add_systems(
         Update,
         (
-            draw::on_draw,
+            draw::on_draw.run_if(in_state(ViewMode::Gm)),
             token::on_token_event,
             token::on_tracker_event,
+            token::on_grid_changed,
+            token::sync_occupancy,
         ),
     );
     //     .add_event::<cell::CellEvent>()
@@ -82,17 +151,45 @@ pub fn run() {
 #[derive(Resource)]
 struct ReqTimer(pub Timer);
 
-fn setup(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
+fn setup(mut commands: Commands) {
     // Setup Grid
     const GRID_SIZE: i32 = 10;
-    Grid::create(GRID_SIZE, &mut commands, &mut meshes, &mut materials);
+    Grid::create(
+        GridShape::Rectangle {
+            width: GRID_SIZE,
+            height: GRID_SIZE,
+        },
+        &mut commands,
+    );
+
+    let palette = match Palette::load(std::path::Path::new(DEFAULT_PALETTE_PATH)) {
+        Ok(palette) => palette,
+        Err(e) => {
+            log::info!("Using default palette: {}", e);
+            Palette::default()
+        }
+    };
+    commands.spawn(palette);
+
+    let preferences = match Preferences::load(std::path::Path::new(DEFAULT_PREFERENCES_PATH)) {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            log::info!("Using default preferences: {}", e);
+            Preferences::default()
+        }
+    };
+    commands.spawn(preferences);
 
     commands.spawn(Draw::default());
+    commands.spawn(Layers::default());
+    commands.spawn(Walls::default());
+    commands.spawn(Fog::default());
+    commands.spawn(Lighting::default());
+    commands.spawn(Background::default());
+    commands.spawn(Export::default());
+    commands.spawn(Import::default());
     commands.spawn(Tracker::default());
+    commands.spawn(Session::default());
 
     // Setup Camera
     commands.spawn((
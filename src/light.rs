@@ -0,0 +1,460 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    prelude::*,
+    render::view::RenderLayers,
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cell::Cell,
+    chunk::ChunkCoord,
+    fog::field_of_view,
+    grid::{Grid, GridSettings},
+    hex::HexCoord,
+    layer::{Layer, Layers},
+    mesh::build_mesh,
+    terrain::Palette,
+    token::Token,
+    view::{GM_ONLY, PLAYER_ONLY},
+    wall::Walls,
+};
+
+lazy_static! {
+    static ref DARK_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.75);
+    static ref DIM_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.4);
+}
+
+// How strongly colored lights tint the cells they light brightly
+const TINT_ALPHA: f32 = 0.2;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default)]
+pub enum LightLevel {
+    #[default]
+    Dark,
+    Dim,
+    Bright,
+}
+
+impl LightLevel {
+    pub const ALL: [LightLevel; 3] = [LightLevel::Bright, LightLevel::Dim, LightLevel::Dark];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightLevel::Dark => "Darkness",
+            LightLevel::Dim => "Dim light",
+            LightLevel::Bright => "Daylight",
+        }
+    }
+}
+
+// A light carried by a token or set on a cell, radii in hexes
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Light {
+    pub bright: i32,
+    // Dim light reaches past the bright light up to here
+    pub dim: i32,
+    pub color: Color,
+}
+
+impl Light {
+    pub const PRESETS: [(&'static str, Light); 4] = [
+        (
+            "Candle",
+            Light {
+                bright: 1,
+                dim: 2,
+                color: Color::rgb(1.0, 0.85, 0.6),
+            },
+        ),
+        (
+            "Torch",
+            Light {
+                bright: 4,
+                dim: 8,
+                color: Color::rgb(1.0, 0.75, 0.45),
+            },
+        ),
+        (
+            "Lantern",
+            Light {
+                bright: 6,
+                dim: 12,
+                color: Color::rgb(1.0, 0.85, 0.6),
+            },
+        ),
+        (
+            "Light spell",
+            Light {
+                bright: 4,
+                dim: 8,
+                color: Color::WHITE,
+            },
+        ),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        Light::PRESETS
+            .iter()
+            .find(|(_, l)| l == self)
+            .map_or("Custom", |(name, _)| name)
+    }
+
+    pub fn level_at(&self, distance: i32) -> LightLevel {
+        if distance <= self.bright {
+            LightLevel::Bright
+        } else if distance <= self.dim {
+            LightLevel::Dim
+        } else {
+            LightLevel::Dark
+        }
+    }
+}
+
+// The map's lighting, off by default so maps start out lit like before
+#[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct Lighting {
+    pub enabled: bool,
+    // Light level wherever no light reaches
+    pub ambient: LightLevel,
+}
+
+// Light level of every cell lit by a light, worked out from the lights on the map
+#[derive(Component, Default)]
+pub struct LightMap {
+    lighting: Lighting,
+    // Level and color of the brightest light on each lit cell
+    lit: HashMap<HexCoord, (LightLevel, Color)>,
+    // Chunks whose lighting changed since they were last drawn
+    dirty: HashSet<ChunkCoord>,
+}
+
+impl LightMap {
+    pub fn level(&self, coord: &HexCoord) -> LightLevel {
+        if !self.lighting.enabled {
+            return LightLevel::Bright;
+        }
+
+        self.lit
+            .get(coord)
+            .map_or(self.lighting.ambient, |(level, _)| {
+                (*level).max(self.lighting.ambient)
+            })
+    }
+
+    // Darkvision sees in the dark as far as it reaches
+    pub fn sees(&self, token: &Token, coord: &HexCoord) -> bool {
+        self.level(coord) > LightLevel::Dark || token.coords.distance(coord) <= token.darkvision
+    }
+
+    // Drawn over the cell, darkening it or tinting it with the light's color
+    fn shade(&self, coord: &HexCoord) -> Option<Color> {
+        match self.level(coord) {
+            LightLevel::Dark => Some(*DARK_COLOR),
+            LightLevel::Dim => Some(*DIM_COLOR),
+            LightLevel::Bright => self
+                .lit
+                .get(coord)
+                .filter(|(_, color)| *color != Color::WHITE)
+                .filter(|_| self.lighting.ambient < LightLevel::Bright)
+                .map(|(_, color)| color.with_a(TINT_ALPHA)),
+        }
+    }
+
+    fn set(&mut self, lighting: Lighting, lit: HashMap<HexCoord, (LightLevel, Color)>) {
+        let changed: Vec<HexCoord> = self
+            .lit
+            .keys()
+            .chain(lit.keys())
+            .filter(|c| self.lit.get(c) != lit.get(c))
+            .copied()
+            .collect();
+        self.dirty.extend(changed.iter().map(ChunkCoord::of));
+
+        self.lighting = lighting;
+        self.lit = lit;
+    }
+}
+
+// The shading meshes, a pair for each chunk with any shade in it
+#[derive(Component, Default)]
+struct LightMeshes {
+    // The GM's see through shade and the players' full one, sharing one mesh
+    meshes: HashMap<ChunkCoord, [Entity; 2]>,
+    // Loaded chunks whose shade has been built, whether or not it needed a mesh
+    drawn: HashSet<ChunkCoord>,
+    settings: Option<GridSettings>,
+    lighting: Lighting,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(Update, update_lighting)
+            .add_systems(PostUpdate, sync_lighting);
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((LightMap::default(), LightMeshes::default()));
+}
+
+// Cells lit by each light, with the level and color of the brightest one
+pub fn light_cells(
+    grid: &Grid,
+    lights: impl IntoIterator<Item = (HexCoord, Light)>,
+    blocks: impl Fn(&HexCoord) -> bool,
+    walls: &Walls,
+) -> HashMap<HexCoord, (LightLevel, Color)> {
+    let mut lit = HashMap::new();
+
+    for (origin, light) in lights {
+        for c in field_of_view(grid, &origin, light.dim.max(light.bright), &blocks, walls) {
+            let level = light.level_at(origin.distance(&c));
+            let entry = lit.entry(c).or_insert((LightLevel::Dark, light.color));
+            if level > entry.0 {
+                *entry = (level, light.color);
+            }
+        }
+    }
+
+    lit
+}
+
+// Works out the light on every cell whenever the lights, the grid, its terrain or walls change
+#[allow(clippy::too_many_arguments)]
+pub fn update_lighting(
+    mut light_map_q: Query<&mut LightMap>,
+    lighting_q: Query<Ref<Lighting>>,
+    grid_q: Query<Ref<Grid>>,
+    walls_q: Query<Ref<Walls>>,
+    palette_q: Query<Ref<Palette>>,
+    token_q: Query<&Token>,
+    moved_q: Query<(), Changed<Token>>,
+    changed_q: Query<(), Changed<Cell>>,
+    mut removed: RemovedComponents<Token>,
+    cell_q: Query<&Cell>,
+) {
+    let (Ok(mut light_map), Ok(lighting), Ok(grid), Ok(walls), Ok(palette)) = (
+        light_map_q.get_single_mut(),
+        lighting_q.get_single(),
+        grid_q.get_single(),
+        walls_q.get_single(),
+        palette_q.get_single(),
+    ) else {
+        return;
+    };
+
+    let removed = removed.read().count() > 0;
+    if !lighting.is_changed()
+        && !grid.is_changed()
+        && !walls.is_changed()
+        && !palette.is_changed()
+        && moved_q.is_empty()
+        && changed_q.is_empty()
+        && !removed
+    {
+        return;
+    }
+
+    if !lighting.enabled {
+        light_map.set(*lighting, HashMap::new());
+        return;
+    }
+
+    let blocks = |c: &HexCoord| {
+        let terrain = grid
+            .lookup_cell(c, |e| cell_q.get(e).ok())
+            .and_then(|c| c.terrain);
+        palette.blocks_sight(terrain)
+    };
+
+    // Lights on cells in unloaded chunks still shine
+    let cell_lights = grid
+        .cells
+        .values()
+        .filter_map(|e| cell_q.get(*e).ok())
+        .chain(grid.stored_cells())
+        .filter_map(|c| Some((c.pos, c.light?)));
+    let token_lights = token_q.iter().filter_map(|t| Some((t.coords, t.light?)));
+
+    let lit = light_cells(&grid, cell_lights.chain(token_lights), blocks, &walls);
+    light_map.set(*lighting, lit);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sync_lighting(
+    mut commands: Commands,
+    mut light_map_q: Query<(&mut LightMap, &mut LightMeshes)>,
+    grid_q: Query<&Grid>,
+    layers_q: Query<&Layers>,
+    handle_q: Query<&Mesh2dHandle>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (Ok((mut light_map, mut light_meshes)), Ok(grid), Ok(layers)) = (
+        light_map_q.get_single_mut(),
+        grid_q.get_single(),
+        layers_q.get_single(),
+    ) else {
+        return;
+    };
+
+    let mut dirty = std::mem::take(&mut light_map.bypass_change_detection().dirty);
+
+    // The ambient light changes every cell
+    if light_meshes.settings != Some(grid.settings) || light_meshes.lighting != light_map.lighting {
+        light_meshes.settings = Some(grid.settings);
+        light_meshes.lighting = light_map.lighting;
+        dirty.extend(grid.loaded.iter().copied());
+    }
+
+    dirty.extend(
+        grid.loaded
+            .iter()
+            .filter(|c| !light_meshes.drawn.contains(c))
+            .copied(),
+    );
+    let unloaded: Vec<ChunkCoord> = light_meshes
+        .drawn
+        .iter()
+        .filter(|c| !grid.loaded.contains(c))
+        .copied()
+        .collect();
+    for chunk in unloaded {
+        light_meshes.drawn.remove(&chunk);
+        for e in light_meshes.meshes.remove(&chunk).into_iter().flatten() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+
+    for chunk in dirty {
+        if !grid.loaded.contains(&chunk) {
+            continue;
+        }
+        light_meshes.drawn.insert(chunk);
+
+        let cells: Vec<(HexCoord, Color)> = chunk
+            .coords()
+            .filter(|c| grid.contains(c))
+            .filter_map(|c| Some((c, light_map.shade(&c)?)))
+            .collect();
+        let existing = light_meshes.meshes.get(&chunk).copied();
+
+        match (cells.is_empty(), existing) {
+            (false, Some([e, _])) => {
+                if let Ok(handle) = handle_q.get(e) {
+                    meshes.insert(handle.0.id(), build_mesh(grid, &cells));
+                }
+            }
+            (false, None) => {
+                let settings = layers.get(Layer::Lighting);
+                let mesh: Mesh2dHandle = meshes.add(build_mesh(grid, &cells)).into();
+                let transform = Transform::from_translation(Vec3::Z * Layer::Lighting.z());
+
+                let gm = commands
+                    .spawn((
+                        MaterialMesh2dBundle {
+                            mesh: mesh.clone(),
+                            material: materials
+                                .add(ColorMaterial::from(Color::WHITE.with_a(settings.opacity))),
+                            transform,
+                            visibility: if settings.visible {
+                                Visibility::Inherited
+                            } else {
+                                Visibility::Hidden
+                            },
+                            ..Default::default()
+                        },
+                        Layer::Lighting,
+                        RenderLayers::layer(GM_ONLY),
+                    ))
+                    .id();
+                // Players always see the darkness in full
+                let player = commands
+                    .spawn((
+                        MaterialMesh2dBundle {
+                            mesh,
+                            material: materials.add(ColorMaterial::from(Color::WHITE)),
+                            transform,
+                            ..Default::default()
+                        },
+                        RenderLayers::layer(PLAYER_ONLY),
+                    ))
+                    .id();
+                light_meshes.meshes.insert(chunk, [gm, player]);
+            }
+            (true, Some(entities)) => {
+                for e in entities {
+                    commands.entity(e).despawn_recursive();
+                }
+                light_meshes.meshes.remove(&chunk);
+            }
+            (true, None) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{grid::tests::test_grid, token::TokenType};
+
+    #[test]
+    fn lights_fade_and_stop_at_walls() {
+        let grid = test_grid(20);
+        let origin = HexCoord { q: 0, r: 0 };
+        let wall = HexCoord { q: 2, r: 0 };
+        let torch = Light::PRESETS[1].1;
+
+        let lit = light_cells(&grid, [(origin, torch)], |c| *c == wall, &Walls::default());
+        assert_eq!(lit[&origin].0, LightLevel::Bright);
+        assert_eq!(lit[&HexCoord { q: 0, r: 6 }].0, LightLevel::Dim);
+        assert!(!lit.contains_key(&HexCoord { q: 0, r: 9 }));
+        // The wall is lit, what's behind it isn't
+        assert!(lit.contains_key(&wall));
+        assert!(!lit.contains_key(&HexCoord { q: 3, r: 0 }));
+
+        // The brighter of two lights wins
+        let candle = Light::PRESETS[0].1;
+        let far = HexCoord { q: 0, r: 7 };
+        let lit = light_cells(
+            &grid,
+            [(origin, torch), (far, candle)],
+            |_| false,
+            &Walls::default(),
+        );
+        assert_eq!(lit[&far], (LightLevel::Bright, candle.color));
+    }
+
+    #[test]
+    fn darkvision_sees_in_the_dark() {
+        let origin = HexCoord { q: 0, r: 0 };
+        let near = HexCoord { q: 2, r: 0 };
+        let far = HexCoord { q: 8, r: 0 };
+        let mut token = Token::new("a", "Elf", TokenType::Party, &origin, &Color::BLUE);
+        let mut light_map = LightMap::default();
+
+        // Everything is lit with lighting off
+        assert_eq!(light_map.level(&far), LightLevel::Bright);
+        assert!(light_map.sees(&token, &far));
+
+        light_map.set(
+            Lighting {
+                enabled: true,
+                ambient: LightLevel::Dark,
+            },
+            [(far, (LightLevel::Dim, Color::WHITE))].into(),
+        );
+        assert_eq!(light_map.shade(&near), Some(*DARK_COLOR));
+        assert!(light_map.sees(&token, &origin));
+        assert!(!light_map.sees(&token, &near));
+        assert!(light_map.sees(&token, &far));
+
+        token.darkvision = 12;
+        assert!(light_map.sees(&token, &near));
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::{HashMap, HashSet},
+    f32::consts::{FRAC_PI_2, FRAC_PI_3},
+};
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+
+use crate::{
+    cell::Cell,
+    chunk::ChunkCoord,
+    grid::{Grid, GridSettings},
+    hex::HexCoord,
+    layer::{Layer, Layers},
+    texture::{world_uv, CellTexture, Textures},
+    view::layer_render_layers,
+};
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(PostUpdate, sync_meshes);
+    }
+}
+
+// The merged meshes the cells are drawn with
+#[derive(Component, Default)]
+pub struct GridMeshes {
+    // One mesh for each layer and texture used in a chunk, textures can't be mixed within a
+    // material
+    meshes: HashMap<(ChunkCoord, Layer, CellTexture), Entity>,
+    // Chunk of every cell that's been drawn, so the chunks of removed cells can be found
+    cells: HashMap<Entity, ChunkCoord>,
+    // Settings the meshes were built with
+    settings: Option<GridSettings>,
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(GridMeshes::default());
+}
+
+// Corners of a hex relative to its centre, counter clockwise
+pub fn hex_corners(grid: &Grid) -> [Vec2; 6] {
+    let rotation = grid.cell_rotation() + FRAC_PI_2;
+    std::array::from_fn(|i| {
+        Vec2::from_angle(rotation + i as f32 * FRAC_PI_3) * grid.settings.hex_size
+    })
+}
+
+// A fan of triangles for every hex, colored through the vertex colors
+pub fn build_mesh(grid: &Grid, cells: &[(HexCoord, Color)]) -> Mesh {
+    let mut positions = Vec::with_capacity(cells.len() * 7);
+    let mut uvs = Vec::with_capacity(cells.len() * 7);
+    let mut colors = Vec::with_capacity(cells.len() * 7);
+    let mut indices = Vec::with_capacity(cells.len() * 18);
+
+    let corners = hex_corners(grid);
+
+    for (pos, color) in cells {
+        let centre = grid.hex_coord_to_pos(pos);
+        let color = color.as_linear_rgba_f32();
+        let start = positions.len() as u32;
+
+        for p in std::iter::once(centre).chain(corners.iter().map(|c| centre + *c)) {
+            positions.push([p.x, p.y, 0.0]);
+            uvs.push(world_uv(p));
+            colors.push(color);
+        }
+
+        // Counter clockwise so the triangles face the camera
+        for i in 0..6 {
+            indices.extend([start, start + 1 + i, start + 1 + (i + 1) % 6]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    mesh
+}
+
+#[allow(clippy::too_many_arguments)]
+fn sync_meshes(
+    mut commands: Commands,
+    mut grid_meshes_q: Query<&mut GridMeshes>,
+    grid_q: Query<&Grid>,
+    textures_q: Query<&Textures>,
+    layers_q: Query<&Layers>,
+    changed_q: Query<(Entity, &Cell), Changed<Cell>>,
+    cell_q: Query<&Cell>,
+    handle_q: Query<&Mesh2dHandle>,
+    mut removed: RemovedComponents<Cell>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (Ok(grid), Ok(textures), Ok(layers), Ok(mut grid_meshes)) = (
+        grid_q.get_single(),
+        textures_q.get_single(),
+        layers_q.get_single(),
+        grid_meshes_q.get_single_mut(),
+    ) else {
+        return;
+    };
+
+    let mut dirty = HashSet::new();
+
+    // Every cell moves when the settings change
+    if grid_meshes.settings != Some(grid.settings) {
+        grid_meshes.settings = Some(grid.settings);
+        dirty.extend(grid_meshes.meshes.keys().map(|(chunk, _, _)| *chunk));
+        dirty.extend(grid.loaded.iter().copied());
+    }
+
+    for (e, cell) in &changed_q {
+        let chunk = ChunkCoord::of(&cell.pos);
+        grid_meshes.cells.insert(e, chunk);
+        dirty.insert(chunk);
+    }
+
+    for e in removed.read() {
+        if let Some(chunk) = grid_meshes.cells.remove(&e) {
+            dirty.insert(chunk);
+        }
+    }
+
+    for chunk in dirty {
+        let mut by_paint: HashMap<(Layer, CellTexture), Vec<(HexCoord, Color)>> = HashMap::new();
+        for coord in chunk.coords() {
+            let Some(cell) = grid.get_cell(&coord).and_then(|e| cell_q.get(*e).ok()) else {
+                continue;
+            };
+
+            for layer in Layer::PAINTED {
+                if let Some(paint) = cell.paint(layer) {
+                    by_paint
+                        .entry((layer, paint.texture))
+                        .or_default()
+                        .push((coord, paint.color));
+                }
+            }
+        }
+
+        for (layer, texture) in Layer::PAINTED
+            .iter()
+            .flat_map(|l| CellTexture::ALL.map(|t| (*l, t)))
+        {
+            let key = (chunk, layer, texture);
+            let existing = grid_meshes.meshes.get(&key).copied();
+
+            match (by_paint.get(&(layer, texture)), existing) {
+                (Some(cells), Some(e)) => {
+                    if let Ok(handle) = handle_q.get(e) {
+                        meshes.insert(handle.0.id(), build_mesh(grid, cells));
+                    }
+                }
+                (Some(cells), None) => {
+                    let settings = layers.get(layer);
+                    let e = commands
+                        .spawn((
+                            MaterialMesh2dBundle {
+                                mesh: meshes.add(build_mesh(grid, cells)).into(),
+                                material: materials.add(ColorMaterial {
+                                    color: Color::WHITE.with_a(settings.opacity),
+                                    texture: textures.get(&texture),
+                                }),
+                                transform: Transform::from_translation(Vec3::Z * layer.z()),
+                                visibility: if settings.visible {
+                                    Visibility::Inherited
+                                } else {
+                                    Visibility::Hidden
+                                },
+                                ..Default::default()
+                            },
+                            layer,
+                            layer_render_layers(layer),
+                        ))
+                        .id();
+                    grid_meshes.meshes.insert(key, e);
+                }
+                (None, Some(e)) => {
+                    commands.entity(e).despawn_recursive();
+                    grid_meshes.meshes.remove(&key);
+                }
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::grid::tests::test_grid;
+
+    #[test]
+    fn mesh_has_a_fan_per_cell() {
+        let grid = test_grid(10);
+        let cells = [
+            (HexCoord { q: 0, r: 0 }, Color::RED),
+            (HexCoord { q: 1, r: 0 }, Color::BLUE),
+        ];
+        let mesh = build_mesh(&grid, &cells);
+
+        assert_eq!(mesh.count_vertices(), 14);
+        assert_eq!(mesh.indices().unwrap().len(), 36);
+
+        // Corners sit a hex radius from the centre
+        let Some(bevy::render::mesh::VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("Mesh has no positions");
+        };
+        let centre = Vec2::from_slice(&positions[0]);
+        for p in &positions[1..7] {
+            let corner = Vec2::from_slice(p);
+            assert!((corner.distance(centre) - grid.settings.hex_size).abs() < 0.001);
+        }
+
+        let Some(bevy::render::mesh::VertexAttributeValues::Float32x4(colors)) =
+            mesh.attribute(Mesh::ATTRIBUTE_COLOR)
+        else {
+            panic!("Mesh has no colors");
+        };
+        assert_eq!(colors[0], Color::RED.as_linear_rgba_f32());
+        assert_eq!(colors[7], Color::BLUE.as_linear_rgba_f32());
+    }
+}
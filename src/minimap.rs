@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::{camera::map_bounds, cell::Cell, grid::Grid, hex::HexCoord};
+
+// Pixels along the longer side of the minimap
+const MINIMAP_SIZE: u32 = 192;
+// Seconds between redraws while the map is being drawn on
+const REDRAW_INTERVAL: f32 = 0.5;
+
+// The whole grid in miniature, redrawn as cells change
+#[derive(Component)]
+pub struct Minimap {
+    pub image: Handle<Image>,
+    // Area of the map the image covers
+    pub bounds: Rect,
+    dirty: bool,
+    timer: Timer,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(PostUpdate, redraw_minimap);
+    }
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let (size, data) = (UVec2::ONE, vec![0; 4]);
+    commands.spawn(Minimap {
+        image: images.add(minimap_image(size, data)),
+        bounds: Rect::default(),
+        dirty: true,
+        timer: Timer::from_seconds(REDRAW_INTERVAL, TimerMode::Repeating),
+    });
+}
+
+fn minimap_image(size: UVec2, data: Vec<u8>) -> Image {
+    Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+// Colors every pixel by the hex under it, top row first. Hexes missing from colors are blank
+fn rasterize(
+    grid: &Grid,
+    colors: &HashMap<HexCoord, Color>,
+    bounds: Rect,
+    max_size: u32,
+) -> (UVec2, Vec<u8>) {
+    let aspect = bounds.width() / bounds.height();
+    let size = if aspect >= 1.0 {
+        UVec2::new(max_size, (max_size as f32 / aspect).round() as u32)
+    } else {
+        UVec2::new((max_size as f32 * aspect).round() as u32, max_size)
+    }
+    .max(UVec2::ONE);
+    let pixel = bounds.size() / size.as_vec2();
+
+    let mut data = Vec::with_capacity((size.x * size.y * 4) as usize);
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let pos = Vec2::new(
+                bounds.min.x + (x as f32 + 0.5) * pixel.x,
+                bounds.max.y - (y as f32 + 0.5) * pixel.y,
+            );
+            let coord = grid.pos_to_hex_coord(&pos);
+            let rgba = match colors.get(&coord) {
+                Some(color) => color.as_rgba_u8(),
+                None if grid.contains(&coord) => grid.blank_color.as_rgba_u8(),
+                None => [0; 4],
+            };
+            data.extend_from_slice(&rgba);
+        }
+    }
+
+    (size, data)
+}
+
+fn redraw_minimap(
+    time: Res<Time>,
+    mut images: ResMut<Assets<Image>>,
+    mut minimap_q: Query<&mut Minimap>,
+    grid_q: Query<Ref<Grid>>,
+    cell_q: Query<&Cell>,
+    changed_q: Query<(), Changed<Cell>>,
+    mut removed: RemovedComponents<Cell>,
+) {
+    let (Ok(mut minimap), Ok(grid)) = (minimap_q.get_single_mut(), grid_q.get_single()) else {
+        return;
+    };
+
+    // Redrawn at most every so often, it's cheap but painting changes cells every frame
+    let removed = removed.read().count() > 0;
+    if grid.is_changed() || removed || !changed_q.is_empty() {
+        minimap.dirty = true;
+    }
+    if !minimap.timer.tick(time.delta()).finished() || !minimap.dirty {
+        return;
+    }
+    minimap.dirty = false;
+
+    let Some(bounds) = map_bounds(&grid, &cell_q) else {
+        minimap.bounds = Rect::default();
+        return;
+    };
+    let colors: HashMap<HexCoord, Color> = grid
+        .cells
+        .values()
+        .filter_map(|e| cell_q.get(*e).ok())
+        .chain(grid.stored_cells())
+        .map(|c| (c.pos, c.color))
+        .collect();
+
+    let (size, data) = rasterize(&grid, &colors, bounds, MINIMAP_SIZE);
+    images.insert(minimap.image.clone(), minimap_image(size, data));
+    minimap.bounds = bounds;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::GridSettings, shape::GridShape};
+
+    #[test]
+    fn the_minimap_shows_painted_cells_where_they_are() {
+        let grid = Grid::new(
+            GridShape::Rectangle {
+                width: 10,
+                height: 4,
+            },
+            GridSettings::default(),
+        );
+        let painted = HexCoord { q: 0, r: 0 };
+        let colors = HashMap::from([(painted, Color::RED)]);
+        let bounds = Rect::from_center_size(Vec2::ZERO, Vec2::new(1000.0, 500.0));
+
+        let (size, data) = rasterize(&grid, &colors, bounds, 64);
+        assert_eq!(size, UVec2::new(64, 32));
+        assert_eq!(data.len(), 64 * 32 * 4);
+
+        let pixel = |x: u32, y: u32| {
+            let i = ((y * size.x + x) * 4) as usize;
+            [data[i], data[i + 1], data[i + 2], data[i + 3]]
+        };
+        // The painted hex is in the middle, the blank ones around it and nothing past the grid
+        assert_eq!(pixel(32, 16), Color::RED.as_rgba_u8());
+        assert_eq!(pixel(32, 12), grid.blank_color.as_rgba_u8());
+        assert_eq!(pixel(0, 16), [0; 4]);
+        assert_eq!(pixel(32, 1), [0; 4]);
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::{prelude::*, render::view::RenderLayers};
+
+use crate::{
+    cell::Cell,
+    grid::Grid,
+    hex::HexCoord,
+    layer::Layer,
+    terrain::Palette,
+    token::{find_token_path, path_feet, Token},
+    view::ViewMode,
+    wall::Walls,
+};
+
+const PATH_COLOR: Color = Color::WHITE;
+const TOO_FAR_COLOR: Color = Color::RED;
+
+// Route the token being dragged would take to the hex under it
+#[derive(Component, Default)]
+pub struct MovePreview {
+    // Set while a token is dragged
+    pub token: Option<Entity>,
+    path: Vec<HexCoord>,
+    // Start and end of the last route looked for, found or not
+    searched: Option<(HexCoord, HexCoord)>,
+    feet: i32,
+    // Further than the token has movement left
+    too_far: bool,
+}
+
+// Cost of the previewed path, written at its end
+#[derive(Component)]
+struct PreviewLabel;
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(Update, update_preview)
+            .add_systems(PostUpdate, draw_preview);
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(MovePreview::default());
+    commands.spawn((
+        PreviewLabel,
+        Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/Roboto-Regular.ttf"),
+                    font_size: 26.0,
+                    color: PATH_COLOR,
+                },
+            ),
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        },
+        ViewMode::default().own_layer(),
+    ));
+}
+
+fn update_preview(
+    mut preview_q: Query<&mut MovePreview>,
+    token_q: Query<(&Token, &Transform)>,
+    grid_q: Query<&Grid>,
+    cell_q: Query<&Cell>,
+    palette_q: Query<&Palette>,
+    walls_q: Query<&Walls>,
+) {
+    let mut preview = preview_q.single_mut();
+    let Some((token, t)) = preview.token.and_then(|e| token_q.get(e).ok()) else {
+        if preview.searched.is_some() {
+            preview.path.clear();
+            preview.searched = None;
+        }
+        return;
+    };
+    let grid = grid_q.single();
+
+    // A route that can't be found is only looked for once, not every frame
+    let to = grid.pos_to_hex_coord(&t.translation.truncate());
+    if preview.searched == Some((token.coords, to)) {
+        return;
+    }
+    preview.searched = Some((token.coords, to));
+
+    match find_token_path(
+        grid,
+        &cell_q,
+        palette_q.single(),
+        walls_q.single(),
+        &token.coords,
+        &to,
+    ) {
+        Some(path) => {
+            preview.feet = path_feet(&path);
+            preview.too_far = preview.feet > token.remaining_movement();
+            preview.path = path.cells;
+        }
+        None => preview.path.clear(),
+    }
+}
+
+fn draw_preview(
+    mut gizmos: Gizmos,
+    preview_q: Query<&MovePreview>,
+    grid_q: Query<&Grid>,
+    mode: Res<State<ViewMode>>,
+    mut label_q: Query<
+        (
+            &mut Text,
+            &mut Transform,
+            &mut Visibility,
+            &mut RenderLayers,
+        ),
+        With<PreviewLabel>,
+    >,
+) {
+    let preview = preview_q.single();
+    let (mut text, mut t, mut visibility, mut layers) = label_q.single_mut();
+    // Shown with the path, to whoever is dragging
+    if *layers != mode.own_layer() {
+        *layers = mode.own_layer();
+    }
+    let Some(end) = preview.path.last().filter(|_| preview.path.len() > 1) else {
+        if *visibility != Visibility::Hidden {
+            *visibility = Visibility::Hidden;
+        }
+        return;
+    };
+    let grid = grid_q.single();
+
+    let color = if preview.too_far {
+        TOO_FAR_COLOR
+    } else {
+        PATH_COLOR
+    };
+    gizmos.linestrip_2d(preview.path.iter().map(|c| grid.hex_coord_to_pos(c)), color);
+
+    let label = format!("{} ft", preview.feet);
+    if text.sections[0].value != label || text.sections[0].style.color != color {
+        text.sections[0].value = label;
+        text.sections[0].style.color = color;
+    }
+    let pos = grid.hex_coord_to_pos(end) + Vec2::Y * grid.settings.hex_size;
+    *t = Transform::from_translation(pos.extend(Layer::Tokens.z() + 0.5))
+        .with_scale(Vec3::splat(grid.scale()));
+    *visibility = Visibility::Visible;
+}
@@ -0,0 +1,202 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::{grid::Grid, hex::HexCoord};
+
+// Hexes a route may wander out of its way, so searches over unbounded grids end
+const MAX_DETOUR: i32 = 64;
+
+pub struct Path {
+    pub cells: Vec<HexCoord>,
+    // Sum of the movement cost of every cell entered, excluding the start
+    pub cost: f32,
+}
+
+struct Node {
+    coord: HexCoord,
+    cost: f32,
+    estimate: f32,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    // Reversed so the BinaryHeap pops the cheapest node first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+// A* search over the grid, cost returns the cost of stepping from a cell into its neighbour or
+// None if that step can't be taken
+pub fn find_path(
+    grid: &Grid,
+    start: &HexCoord,
+    end: &HexCoord,
+    cost: impl Fn(&HexCoord, &HexCoord) -> Option<f32>,
+) -> Option<Path> {
+    if !grid.contains(start) || !grid.contains(end) {
+        return None;
+    }
+    let furthest = start.distance(end) + MAX_DETOUR;
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<HexCoord, HexCoord> = HashMap::new();
+    let mut best: HashMap<HexCoord, f32> = HashMap::new();
+
+    best.insert(*start, 0.0);
+    open.push(Node {
+        coord: *start,
+        cost: 0.0,
+        estimate: start.distance(end) as f32,
+    });
+
+    while let Some(Node {
+        coord,
+        cost: so_far,
+        ..
+    }) = open.pop()
+    {
+        if coord == *end {
+            let mut cells = vec![coord];
+            let mut current = coord;
+            while let Some(prev) = came_from.get(&current) {
+                cells.push(*prev);
+                current = *prev;
+            }
+            cells.reverse();
+
+            return Some(Path {
+                cells,
+                cost: so_far,
+            });
+        }
+
+        if best.get(&coord).is_some_and(|b| *b < so_far) {
+            continue;
+        }
+
+        for n in grid.get_neighbours(&coord) {
+            if start.distance(&n) + n.distance(end) > furthest {
+                continue;
+            }
+            let Some(step) = cost(&coord, &n) else {
+                continue;
+            };
+
+            let next = so_far + step;
+            if !best.get(&n).is_some_and(|b| *b <= next) {
+                best.insert(n, next);
+                came_from.insert(n, coord);
+                open.push(Node {
+                    coord: n,
+                    cost: next,
+                    estimate: next + n.distance(end) as f32,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+// True when no cell strictly between start and end blocks sight
+pub fn line_of_sight(start: &HexCoord, end: &HexCoord, blocks: impl Fn(&HexCoord) -> bool) -> bool {
+    let n = start.distance(end);
+
+    (1..n).all(|i| {
+        let t = i as f32 / n as f32;
+        // Nudge both sides of the line so it never passes exactly between two cells
+        let a = HexCoord::lerp_nudged(start, end, t, 1e-3);
+        let b = HexCoord::lerp_nudged(start, end, t, -1e-3);
+        !blocks(&a) || !blocks(&b)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        grid::{tests::test_grid, GridSettings},
+        shape::GridShape,
+    };
+
+    #[test]
+    fn straight_path() {
+        let grid = test_grid(10);
+        let start = HexCoord { q: 0, r: 0 };
+        let end = HexCoord { q: 3, r: 0 };
+
+        let path = find_path(&grid, &start, &end, |_, _| Some(1.0)).unwrap();
+
+        assert_eq!(path.cells.len(), 4);
+        assert_eq!(path.cells.first(), Some(&start));
+        assert_eq!(path.cells.last(), Some(&end));
+        assert_eq!(path.cost, 3.0);
+    }
+
+    #[test]
+    fn path_avoids_walls_and_expensive_cells() {
+        let grid = test_grid(10);
+        let start = HexCoord { q: 0, r: 0 };
+        let end = HexCoord { q: 2, r: 0 };
+        let wall = HexCoord { q: 1, r: 0 };
+
+        let path = find_path(&grid, &start, &end, |_, c| (c != &wall).then_some(1.0)).unwrap();
+        assert!(!path.cells.contains(&wall));
+        assert_eq!(path.cost, 3.0);
+
+        let path = find_path(&grid, &start, &end, |_, c| {
+            Some(if c == &wall { 5.0 } else { 1.0 })
+        })
+        .unwrap();
+        assert_eq!(path.cost, 3.0);
+    }
+
+    #[test]
+    fn no_path_when_surrounded() {
+        let grid = test_grid(10);
+        let start = HexCoord { q: 0, r: 0 };
+        let end = HexCoord { q: 3, r: 0 };
+
+        let ring = grid.get_neighbours(&start);
+        assert!(find_path(&grid, &start, &end, |_, c| (!ring.contains(c))
+            .then_some(1.0))
+        .is_none());
+
+        // Gives up on unbounded grids too
+        let grid = Grid::new(GridShape::Unbounded, GridSettings::default());
+        let ring = grid.get_neighbours(&start);
+        assert_eq!(ring.len(), 6);
+        assert!(find_path(&grid, &start, &end, |_, c| (!ring.contains(c))
+            .then_some(1.0))
+        .is_none());
+    }
+
+    #[test]
+    fn sight_blocked_by_wall() {
+        let start = HexCoord { q: 0, r: 0 };
+        let end = HexCoord { q: 4, r: 0 };
+        let wall = HexCoord { q: 2, r: 0 };
+
+        assert!(line_of_sight(&start, &end, |_| false));
+        assert!(!line_of_sight(&start, &end, |c| c == &wall));
+        // The end cell itself doesn't block
+        assert!(line_of_sight(&start, &end, |c| c == &end));
+    }
+}
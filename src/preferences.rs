@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{camera::CameraSettings, keymap::Keymap, token::DropPolicy};
+
+pub const DEFAULT_PREFERENCES_PATH: &str = "preferences.json";
+
+// Settings of the app itself rather than of the map, kept between runs
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct Preferences {
+    #[serde(default)]
+    pub keymap: Keymap,
+    #[serde(default)]
+    pub camera: CameraSettings,
+    // Column and row written on every hex
+    #[serde(default)]
+    pub coordinate_labels: bool,
+    // Tokens can't be dropped further than they have movement left
+    #[serde(default)]
+    pub enforce_movement: bool,
+    #[serde(default)]
+    pub drop_policy: DropPolicy,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, save_preferences);
+    }
+}
+
+impl Preferences {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = read(path)?;
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        write(path, &data)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write(path: &Path, data: &str) -> Result<(), String> {
+    std::fs::write(path, data).map_err(|e| e.to_string())
+}
+
+// Browsers have no files to write to, the path is the key in the page's local storage instead
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .ok_or_else(|| "Local storage isn't available".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read(path: &Path) -> Result<String, String> {
+    local_storage()?
+        .get_item(&path.to_string_lossy())
+        .map_err(|e| format!("{:?}", e))?
+        .ok_or_else(|| "Nothing saved yet".to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write(path: &Path, data: &str) -> Result<(), String> {
+    local_storage()?
+        .set_item(&path.to_string_lossy(), data)
+        .map_err(|e| format!("{:?}", e))
+}
+
+// Written out as soon as they're changed, there's no save button
+fn save_preferences(preferences_q: Query<Ref<Preferences>>) {
+    let Ok(preferences) = preferences_q.get_single() else {
+        return;
+    };
+    if preferences.is_changed() && !preferences.is_added() {
+        if let Err(e) = preferences.save(Path::new(DEFAULT_PREFERENCES_PATH)) {
+            log::warn!("Couldn't save preferences: {}", e);
+        }
+    }
+}
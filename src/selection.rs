@@ -0,0 +1,250 @@
+use std::f32::consts::PI;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+
+use crate::{
+    cell::Cell,
+    grid::Grid,
+    hex::HexCoord,
+    layer::{Layer, Layers},
+    preferences::Preferences,
+    terrain::Palette,
+    token::{find_token_path, path_feet, Selected, Token},
+    view::{MainCamera, ViewMode},
+    wall::Walls,
+};
+
+// Names of the directions selected tokens can be nudged in, anticlockwise from the right
+pub const NUDGE_DIRECTIONS: [&str; 6] = [
+    "right",
+    "up right",
+    "up left",
+    "left",
+    "down left",
+    "down right",
+];
+
+const SELECTION_COLOR: Color = Color::YELLOW;
+// Radius of the ring around selected tokens, at the default hex size
+const RING_RADIUS: f32 = 34.0;
+
+#[derive(Event)]
+pub enum SelectionEvent {
+    // Moves the selected tokens a hex in one of the NUDGE_DIRECTIONS
+    Nudge(usize),
+}
+
+// Box being dragged out over the tokens layer to select the tokens inside it
+#[derive(Component, Default)]
+pub struct Marquee {
+    start: Option<Vec2>,
+    end: Vec2,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SelectionEvent>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (marquee_select, on_selection_event).run_if(in_state(ViewMode::Gm)),
+            )
+            .add_systems(PostUpdate, draw_selection.run_if(in_state(ViewMode::Gm)));
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn(Marquee::default());
+}
+
+// Neighbour of a hex closest to the direction on screen. On grids whose hexes have no
+// neighbour straight across the next one anticlockwise is taken
+fn nudge_offset(grid: &Grid, direction: usize) -> HexCoord {
+    let angle = direction as f32 * PI / 3.0 + 0.1;
+    let wanted = Vec2::from_angle(angle);
+    let origin = grid.hex_coord_to_pos(&HexCoord { q: 0, r: 0 });
+
+    HexCoord::DIRECTIONS
+        .into_iter()
+        .min_by(|a, b| {
+            let angle_to = |d: &HexCoord| wanted.angle_between(grid.hex_coord_to_pos(d) - origin);
+            angle_to(a).abs().total_cmp(&angle_to(b).abs())
+        })
+        .unwrap()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn marquee_select(
+    mut commands: Commands,
+    mut contexts: EguiContexts,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    cam_q: Query<(&Camera, &GlobalTransform), MainCamera>,
+    grid_q: Query<&Grid>,
+    layers_q: Query<&Layers>,
+    mut marquee_q: Query<&mut Marquee>,
+    token_q: Query<(Entity, &Token, Has<Selected>)>,
+) {
+    let Ok(grid) = grid_q.get_single() else {
+        return;
+    };
+    let mut marquee = marquee_q.single_mut();
+    let (cam, cam_transform) = cam_q.single();
+    let over_ui = contexts.ctx_mut().wants_pointer_input();
+
+    let pos = window_q
+        .get_single()
+        .ok()
+        .and_then(|w| w.cursor_position())
+        .and_then(|cursor| cam.viewport_to_world_2d(cam_transform, cursor));
+
+    if buttons.just_pressed(MouseButton::Left) {
+        let layers = layers_q.single();
+        let on_token = |p: Vec2| {
+            let coord = grid.pos_to_hex_coord(&p);
+            token_q.iter().any(|(_, t, _)| t.coords == coord)
+        };
+        // Tokens take clicks on their own hex
+        marquee.start = pos.filter(|p| {
+            !over_ui
+                && layers.selected == Layer::Tokens
+                && !layers.is_locked(Layer::Tokens)
+                && !on_token(*p)
+        });
+    }
+    let Some(start) = marquee.start else {
+        return;
+    };
+    if let Some(pos) = pos {
+        marquee.end = pos;
+    }
+    if !buttons.just_released(MouseButton::Left) {
+        return;
+    }
+    marquee.start = None;
+
+    // Shift adds to the selection, otherwise a click on nothing clears it
+    let adding = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let rect = Rect::from_corners(start, marquee.end);
+    for (e, token, selected) in &token_q {
+        let inside = rect.contains(grid.hex_coord_to_pos(&token.coords));
+        if inside && !selected {
+            commands.entity(e).insert(Selected);
+        } else if !inside && selected && !adding {
+            commands.entity(e).remove::<Selected>();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn on_selection_event(
+    mut events: EventReader<SelectionEvent>,
+    mut token_q: Query<(&mut Token, &mut Transform, Has<Selected>)>,
+    grid_q: Query<&Grid>,
+    cell_q: Query<&Cell>,
+    palette_q: Query<&Palette>,
+    walls_q: Query<&Walls>,
+    layers_q: Query<&Layers>,
+    preferences_q: Query<&Preferences>,
+) {
+    let grid = grid_q.single();
+    let palette = palette_q.single();
+    let walls = walls_q.single();
+    let enforce = preferences_q.single().enforce_movement;
+
+    for e in events.read() {
+        let SelectionEvent::Nudge(direction) = e;
+        if layers_q.single().is_locked(Layer::Tokens) {
+            continue;
+        }
+        let offset = nudge_offset(grid, *direction);
+        let ahead =
+            grid.hex_coord_to_pos(&offset) - grid.hex_coord_to_pos(&HexCoord { q: 0, r: 0 });
+
+        let mut taken: Vec<HexCoord> = token_q.iter().map(|(t, _, _)| t.coords).collect();
+        // Those in front go first, making room for the ones behind
+        let mut selected: Vec<_> = token_q.iter_mut().filter(|(_, _, s)| *s).collect();
+        selected.sort_by(|(a, _, _), (b, _, _)| {
+            let along = |t: &Token| grid.hex_coord_to_pos(&t.coords).dot(ahead);
+            along(b).total_cmp(&along(a))
+        });
+
+        for (mut token, mut t, _) in selected {
+            let to = token.coords + offset;
+            if !grid.contains(&to) || taken.contains(&to) {
+                continue;
+            }
+            let Some(feet) = find_token_path(grid, &cell_q, palette, walls, &token.coords, &to)
+                .map(|p| path_feet(&p))
+                .filter(|feet| !enforce || *feet <= token.remaining_movement())
+            else {
+                continue;
+            };
+            taken.retain(|c| *c != token.coords);
+            taken.push(to);
+            token.coords = to;
+            token.moved += feet;
+            t.translation = grid.hex_coord_to_pos(&to).extend(t.translation.z);
+        }
+    }
+}
+
+fn draw_selection(
+    mut gizmos: Gizmos,
+    grid_q: Query<&Grid>,
+    marquee_q: Query<&Marquee>,
+    token_q: Query<&Transform, (With<Token>, With<Selected>)>,
+) {
+    let Ok(grid) = grid_q.get_single() else {
+        return;
+    };
+
+    for t in &token_q {
+        gizmos.circle_2d(
+            t.translation.truncate(),
+            RING_RADIUS * grid.scale(),
+            SELECTION_COLOR,
+        );
+    }
+
+    if let Ok(Marquee {
+        start: Some(start),
+        end,
+    }) = marquee_q.get_single()
+    {
+        let rect = Rect::from_corners(*start, *end);
+        gizmos.rect_2d(rect.center(), 0.0, rect.size(), SELECTION_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{GridSettings, Layout};
+    use crate::shape::GridShape;
+
+    #[test]
+    fn nudges_follow_the_screen() {
+        let mut grid = Grid::new(GridShape::Hexagon { radius: 3 }, GridSettings::default());
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            grid.settings.layout = layout;
+
+            let mut offsets: Vec<HexCoord> = (0..6).map(|d| nudge_offset(&grid, d)).collect();
+            // Opposite directions undo each other
+            for d in 0..3 {
+                assert_eq!(offsets[d] + offsets[d + 3], HexCoord { q: 0, r: 0 });
+            }
+            offsets.sort_by_key(|c| (c.q, c.r));
+            offsets.dedup();
+            assert_eq!(offsets.len(), 6);
+        }
+
+        // Right is right on pointy topped hexes
+        grid.settings.layout = Layout::PointyTop;
+        let right = grid.hex_coord_to_pos(&nudge_offset(&grid, 0));
+        assert!(right.x > 0.0 && right.y.abs() < 1e-3);
+    }
+}
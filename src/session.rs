@@ -0,0 +1,718 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grid::Grid,
+    layer::Layer,
+    snapshot::{Op, Snapshot, SnapshotEvent, SnapshotSource},
+    token::Token,
+    view::{ViewLocked, ViewMode},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::pump;
+#[cfg(not(target_arch = "wasm32"))]
+use native::Transport;
+#[cfg(target_arch = "wasm32")]
+use web::Transport;
+
+pub const DEFAULT_PORT: u16 = 7878;
+// Seconds between checks for changes the GM should send
+const SEND_INTERVAL: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Role {
+    Gm,
+    // Players only move their own tokens, asked for by name or id when joining
+    Player { creature_ids: Vec<String> },
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Gm => "GM",
+            Role::Player { .. } => "Player",
+        }
+    }
+}
+
+// Sent by the apps to the relay
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum ClientMessage {
+    Join { role: Role, key: String },
+    Snapshot(Box<Snapshot>),
+    Op(Op),
+}
+
+// Sent by the relay to the apps
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum ServerMessage {
+    Joined(Role),
+    Rejected(String),
+    Snapshot(Box<Snapshot>),
+    Op(Op),
+}
+
+pub type ClientId = u64;
+
+// What the relay keeps track of, the networking is left to the relay binary
+#[derive(Default)]
+pub struct Relay {
+    // Needed to join as the GM, if set
+    key: Option<String>,
+    roles: HashMap<ClientId, Role>,
+    // What the players see, kept up to date with the ops passed on
+    snapshot: Option<Snapshot>,
+}
+
+impl Relay {
+    pub fn new(key: Option<String>) -> Self {
+        Self {
+            key,
+            ..Default::default()
+        }
+    }
+
+    pub fn leave(&mut self, client: ClientId) {
+        self.roles.remove(&client);
+    }
+
+    // Replies to a message and who they go to
+    pub fn handle(
+        &mut self,
+        client: ClientId,
+        message: ClientMessage,
+    ) -> Vec<(ClientId, ServerMessage)> {
+        let reject = |reason: &str| vec![(client, ServerMessage::Rejected(reason.to_string()))];
+
+        match (self.roles.get(&client).cloned(), message) {
+            (
+                None,
+                ClientMessage::Join {
+                    role: Role::Gm,
+                    key,
+                },
+            ) => {
+                if self.key.as_ref().is_some_and(|k| *k != key) {
+                    return reject("Wrong GM key");
+                }
+                if self.roles.values().any(|r| *r == Role::Gm) {
+                    return reject("The session already has a GM");
+                }
+
+                self.roles.insert(client, Role::Gm);
+                vec![(client, ServerMessage::Joined(Role::Gm))]
+            }
+            (
+                None,
+                ClientMessage::Join {
+                    role: Role::Player { creature_ids },
+                    ..
+                },
+            ) => {
+                let Some(snapshot) = &self.snapshot else {
+                    return reject("Wait for the GM to start the session");
+                };
+
+                let mut ids = Vec::new();
+                for wanted in &creature_ids {
+                    match snapshot.party_token(wanted) {
+                        Some(id) => ids.push(id.to_string()),
+                        None => return reject(&format!("No party token called {}", wanted)),
+                    }
+                }
+
+                let role = Role::Player { creature_ids: ids };
+                self.roles.insert(client, role.clone());
+                vec![
+                    (client, ServerMessage::Joined(role)),
+                    (client, ServerMessage::Snapshot(Box::new(snapshot.clone()))),
+                ]
+            }
+            (Some(_), ClientMessage::Join { .. }) => reject("Already joined"),
+            (None, _) => reject("Join the session first"),
+            (Some(Role::Gm), ClientMessage::Snapshot(snapshot)) => {
+                self.snapshot = Some(*snapshot.clone());
+                self.players_except(client)
+                    .map(|c| (c, ServerMessage::Snapshot(snapshot.clone())))
+                    .collect()
+            }
+            (Some(Role::Gm), ClientMessage::Op(op)) => self.pass_on(client, op),
+            (Some(Role::Player { creature_ids }), ClientMessage::Op(op @ Op::MoveToken { .. })) => {
+                let Op::MoveToken { creature_id, .. } = &op else {
+                    unreachable!()
+                };
+                if !creature_ids.contains(creature_id) {
+                    return reject("That token isn't yours to move");
+                }
+                self.pass_on(client, op)
+            }
+            (Some(Role::Player { .. }), _) => reject("Players can only move their own tokens"),
+        }
+    }
+
+    fn players_except(&self, client: ClientId) -> impl Iterator<Item = ClientId> + '_ {
+        self.roles
+            .iter()
+            .filter(move |(c, r)| **c != client && matches!(r, Role::Player { .. }))
+            .map(|(c, _)| *c)
+    }
+
+    // Applies the op and sends it to everyone else
+    fn pass_on(&mut self, client: ClientId, op: Op) -> Vec<(ClientId, ServerMessage)> {
+        if let Some(snapshot) = &mut self.snapshot {
+            snapshot.apply(&op);
+        }
+
+        self.roles
+            .keys()
+            .filter(|c| **c != client)
+            .map(|c| (*c, ServerMessage::Op(op.clone())))
+            .collect()
+    }
+}
+
+#[derive(Event)]
+pub enum SessionEvent {
+    Join {
+        url: String,
+        role: Role,
+        key: String,
+    },
+    Leave,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum SessionStatus {
+    #[default]
+    Offline,
+    Joining,
+    Joined(Role),
+}
+
+#[derive(Component)]
+pub struct Session {
+    // Filled in from the session window
+    pub url: String,
+    pub as_gm: bool,
+    pub key: String,
+    pub characters: String,
+    pub status: SessionStatus,
+    pub error: Option<String>,
+    // Last state sent by the GM or received by a player, ops are worked out against it
+    last: Option<Snapshot>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            url: format!("ws://localhost:{}", DEFAULT_PORT),
+            as_gm: true,
+            key: String::new(),
+            characters: String::new(),
+            status: SessionStatus::Offline,
+            error: None,
+            last: None,
+        }
+    }
+}
+
+impl Session {
+    // Players may only move the tokens they joined with
+    pub fn may_move(&self, token: &Token) -> bool {
+        match &self.status {
+            SessionStatus::Joined(Role::Player { creature_ids }) => {
+                creature_ids.iter().any(|id| id == token.creature_id())
+            }
+            _ => false,
+        }
+    }
+}
+
+// The connection to the relay, web sockets can't leave the main thread on the web
+#[derive(Default)]
+struct Connection(Option<Transport>);
+
+impl Connection {
+    fn send(&self, message: &ClientMessage) {
+        let Some(transport) = &self.0 else {
+            return;
+        };
+
+        match serde_json::to_string(message) {
+            Ok(text) => transport.send(text),
+            Err(e) => log::error!("Couldn't send to the relay: {}", e),
+        }
+    }
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SessionEvent>()
+            .insert_non_send_resource(Connection::default())
+            .add_systems(Update, (on_session_event, receive, send_updates).chain());
+    }
+}
+
+fn leave(
+    connection: &mut Connection,
+    session: &mut Session,
+    commands: &mut Commands,
+    error: Option<String>,
+) {
+    connection.0 = None;
+    if let SessionStatus::Joined(Role::Player { .. }) = session.status {
+        commands.remove_resource::<ViewLocked>();
+    }
+
+    session.status = SessionStatus::Offline;
+    session.error = error;
+    session.last = None;
+}
+
+fn on_session_event(
+    mut events: EventReader<SessionEvent>,
+    mut connection: NonSendMut<Connection>,
+    mut session_q: Query<&mut Session>,
+    mut commands: Commands,
+) {
+    let mut session = session_q.single_mut();
+
+    for event in events.read() {
+        match event {
+            SessionEvent::Join { url, role, key } => {
+                leave(&mut connection, &mut session, &mut commands, None);
+
+                match Transport::connect(url) {
+                    Ok(transport) => {
+                        connection.0 = Some(transport);
+                        connection.send(&ClientMessage::Join {
+                            role: role.clone(),
+                            key: key.clone(),
+                        });
+                        session.status = SessionStatus::Joining;
+                    }
+                    Err(e) => session.error = Some(e),
+                }
+            }
+            SessionEvent::Leave => leave(&mut connection, &mut session, &mut commands, None),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn receive(
+    mut connection: NonSendMut<Connection>,
+    mut session_q: Query<&mut Session>,
+    mut commands: Commands,
+    mut next_mode: ResMut<NextState<ViewMode>>,
+    mut snapshot_event: EventWriter<SnapshotEvent>,
+    grid_q: Query<&Grid>,
+    mut token_q: Query<(&mut Token, &mut Transform)>,
+) {
+    let Some(transport) = &connection.0 else {
+        return;
+    };
+    let received = transport.receive();
+    let mut session = session_q.single_mut();
+
+    for message in received {
+        let message = match message.and_then(|text| {
+            serde_json::from_str::<ServerMessage>(&text)
+                .map_err(|e| format!("Bad message from the relay: {}", e))
+        }) {
+            Ok(message) => message,
+            Err(e) => {
+                leave(&mut connection, &mut session, &mut commands, Some(e));
+                return;
+            }
+        };
+
+        match message {
+            ServerMessage::Joined(role) => {
+                // Players get the player view and nothing else
+                if let Role::Player { .. } = role {
+                    commands.insert_resource(ViewLocked);
+                    next_mode.set(ViewMode::Player);
+                }
+                session.status = SessionStatus::Joined(role);
+            }
+            ServerMessage::Rejected(reason) => {
+                if session.status == SessionStatus::Joining {
+                    leave(&mut connection, &mut session, &mut commands, Some(reason));
+                    return;
+                }
+                session.error = Some(reason);
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                session.last = Some(*snapshot.clone());
+                snapshot_event.send(SnapshotEvent(*snapshot));
+            }
+            // A player moved one of their tokens
+            ServerMessage::Op(Op::MoveToken {
+                creature_id,
+                coords,
+                moved,
+            }) if session.status == SessionStatus::Joined(Role::Gm) => {
+                let grid = grid_q.single();
+                for (mut token, mut t) in &mut token_q {
+                    if token.creature_id() != creature_id {
+                        continue;
+                    }
+                    if token.coords != coords {
+                        token.coords = coords;
+                        t.translation = grid.hex_coord_to_pos(&coords).extend(Layer::Tokens.z());
+                    }
+                    if token.moved != moved {
+                        token.moved = moved;
+                    }
+                }
+            }
+            ServerMessage::Op(op) => {
+                if let Some(last) = &mut session.last {
+                    last.apply(&op);
+                    snapshot_event.send(SnapshotEvent(last.clone()));
+                }
+            }
+        }
+    }
+}
+
+// The GM sends what changed on the map, players the moves of their tokens
+fn send_updates(
+    connection: NonSend<Connection>,
+    mut session_q: Query<&mut Session>,
+    time: Res<Time>,
+    mut timer: Local<f32>,
+    source: SnapshotSource,
+    token_q: Query<&Token, Changed<Token>>,
+) {
+    let mut session = session_q.single_mut();
+
+    match session.status.clone() {
+        SessionStatus::Joined(Role::Gm) => {
+            *timer += time.delta_seconds();
+            if *timer < SEND_INTERVAL {
+                return;
+            }
+            *timer = 0.0;
+
+            let Some(snapshot) = source.capture().map(Snapshot::for_players) else {
+                return;
+            };
+            match session
+                .last
+                .as_ref()
+                .and_then(|last| snapshot.ops_since(last))
+            {
+                Some(ops) => {
+                    for op in ops {
+                        connection.send(&ClientMessage::Op(op));
+                    }
+                }
+                None => connection.send(&ClientMessage::Snapshot(Box::new(snapshot.clone()))),
+            }
+            session.last = Some(snapshot);
+        }
+        SessionStatus::Joined(Role::Player { .. }) => {
+            let moves: Vec<Op> = token_q
+                .iter()
+                .filter(|t| session.may_move(t))
+                .map(|t| Op::MoveToken {
+                    creature_id: t.creature_id().to_string(),
+                    coords: t.coords,
+                    moved: t.moved,
+                })
+                .collect();
+            let Some(last) = &mut session.last else {
+                return;
+            };
+
+            for op in moves {
+                let before = last.clone();
+                last.apply(&op);
+                if *last != before {
+                    connection.send(&ClientMessage::Op(op));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        sync::mpsc::{self, Receiver, Sender, TryRecvError},
+        thread,
+        time::Duration,
+    };
+
+    use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+    // How long to wait when there's nothing to read
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    // Talks to the relay from its own thread
+    pub struct Transport {
+        outgoing: Sender<String>,
+        incoming: Receiver<Result<String, String>>,
+    }
+
+    impl Transport {
+        pub fn connect(url: &str) -> Result<Self, String> {
+            let url = url.to_string();
+            let (outgoing, outgoing_rx) = mpsc::channel();
+            let (incoming_tx, incoming) = mpsc::channel();
+
+            thread::spawn(move || {
+                let result = tungstenite::connect(url.as_str())
+                    .map_err(|e| e.to_string())
+                    .and_then(|(mut socket, _)| {
+                        if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+                            stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+                        }
+                        pump(&mut socket, &outgoing_rx, |text| {
+                            let _ = incoming_tx.send(Ok(text));
+                        })
+                    });
+
+                let error = result
+                    .err()
+                    .unwrap_or("The relay closed the session".to_string());
+                let _ = incoming_tx.send(Err(error));
+            });
+
+            Ok(Self { outgoing, incoming })
+        }
+
+        pub fn send(&self, text: String) {
+            let _ = self.outgoing.send(text);
+        }
+
+        pub fn receive(&self) -> Vec<Result<String, String>> {
+            self.incoming.try_iter().collect()
+        }
+    }
+
+    fn ignore_would_block(result: tungstenite::Result<()>) -> Result<(), String> {
+        match result {
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            result => result.map_err(|e| e.to_string()),
+        }
+    }
+
+    // Passes text messages both ways on a non-blocking socket until either side hangs up
+    pub fn pump<S: Read + Write>(
+        socket: &mut WebSocket<S>,
+        outgoing: &Receiver<String>,
+        mut on_text: impl FnMut(String),
+    ) -> Result<(), String> {
+        loop {
+            loop {
+                match outgoing.try_recv() {
+                    Ok(text) => ignore_would_block(socket.send(Message::Text(text)))?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        let _ = socket.close(None);
+                        let _ = socket.flush();
+                        return Ok(());
+                    }
+                }
+            }
+            ignore_would_block(socket.flush())?;
+
+            match socket.read() {
+                Ok(Message::Text(text)) => on_text(text),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL)
+                }
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, rc::Rc};
+
+    use wasm_bindgen::{prelude::*, JsCast};
+    use web_sys::{Event, MessageEvent, WebSocket};
+
+    pub struct Transport {
+        socket: WebSocket,
+        // Sent once the socket opens
+        pending: Rc<RefCell<Vec<String>>>,
+        incoming: Rc<RefCell<Vec<Result<String, String>>>>,
+        _on_open: Closure<dyn FnMut(Event)>,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+        _on_close: Closure<dyn FnMut(Event)>,
+    }
+
+    impl Transport {
+        pub fn connect(url: &str) -> Result<Self, String> {
+            let socket = WebSocket::new(url).map_err(|e| format!("{:?}", e))?;
+            let pending = Rc::new(RefCell::new(Vec::<String>::new()));
+            let incoming = Rc::new(RefCell::new(Vec::new()));
+
+            let (open_socket, queue) = (socket.clone(), pending.clone());
+            let on_open = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                for text in queue.borrow_mut().drain(..) {
+                    let _ = open_socket.send_with_str(&text);
+                }
+            });
+            socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+
+            let queue = incoming.clone();
+            let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |e: MessageEvent| {
+                if let Some(text) = e.data().as_string() {
+                    queue.borrow_mut().push(Ok(text));
+                }
+            });
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+            let queue = incoming.clone();
+            let on_close = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                queue
+                    .borrow_mut()
+                    .push(Err("Lost the connection to the relay".to_string()));
+            });
+            socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+            Ok(Self {
+                socket,
+                pending,
+                incoming,
+                _on_open: on_open,
+                _on_message: on_message,
+                _on_close: on_close,
+            })
+        }
+
+        pub fn send(&self, text: String) {
+            if self.socket.ready_state() == WebSocket::OPEN {
+                let _ = self.socket.send_with_str(&text);
+            } else {
+                self.pending.borrow_mut().push(text);
+            }
+        }
+
+        pub fn receive(&self) -> Vec<Result<String, String>> {
+            self.incoming.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl Drop for Transport {
+        fn drop(&mut self) {
+            // The closures go with the transport
+            self.socket.set_onopen(None);
+            self.socket.set_onmessage(None);
+            self.socket.set_onclose(None);
+            let _ = self.socket.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        grid::tests::test_grid, hex::HexCoord, snapshot::tests::snapshot, token::TokenType,
+    };
+
+    const GM: ClientId = 1;
+    const ALICE: ClientId = 2;
+    const BOB: ClientId = 3;
+
+    fn join(role: Role, key: &str) -> ClientMessage {
+        ClientMessage::Join {
+            role,
+            key: key.to_string(),
+        }
+    }
+
+    fn player(names: &[&str]) -> Role {
+        Role::Player {
+            creature_ids: names.iter().map(|n| n.to_string()).collect(),
+        }
+    }
+
+    fn rejected(replies: &[(ClientId, ServerMessage)]) -> bool {
+        matches!(replies, [(_, ServerMessage::Rejected(_))])
+    }
+
+    fn move_token(id: &str) -> ClientMessage {
+        ClientMessage::Op(Op::MoveToken {
+            creature_id: id.to_string(),
+            coords: HexCoord { q: 2, r: 2 },
+            moved: 10,
+        })
+    }
+
+    #[test]
+    fn players_only_move_their_own_tokens() {
+        let grid = test_grid(10);
+        let coords = HexCoord { q: 0, r: 0 };
+        let tokens = vec![
+            Token::new("f1", "Fighter", TokenType::Party, &coords, &Color::BLUE),
+            Token::new("w1", "Wizard", TokenType::Party, &coords, &Color::BLUE),
+            Token::new("g1", "Goblin", TokenType::Enemy, &coords, &Color::RED),
+        ];
+        let mut relay = Relay::new(Some("secret".to_string()));
+
+        assert!(rejected(
+            &relay.handle(ALICE, join(player(&["Fighter"]), ""))
+        ));
+        assert!(rejected(&relay.handle(GM, join(Role::Gm, "wrong"))));
+        assert_eq!(
+            relay.handle(GM, join(Role::Gm, "secret")),
+            [(GM, ServerMessage::Joined(Role::Gm))]
+        );
+        assert!(rejected(&relay.handle(BOB, join(Role::Gm, "secret"))));
+        assert!(rejected(&relay.handle(ALICE, move_token("f1"))));
+
+        // Nobody to send it to yet
+        let snapshot = snapshot(&grid, Vec::new(), tokens);
+        assert!(relay
+            .handle(GM, ClientMessage::Snapshot(Box::new(snapshot.clone())))
+            .is_empty());
+
+        assert!(rejected(
+            &relay.handle(ALICE, join(player(&["Goblin"]), ""))
+        ));
+        assert_eq!(
+            relay.handle(ALICE, join(player(&["fighter"]), "")),
+            [
+                (ALICE, ServerMessage::Joined(player(&["f1"]))),
+                (ALICE, ServerMessage::Snapshot(Box::new(snapshot)))
+            ]
+        );
+        relay.handle(BOB, join(player(&["w1"]), ""));
+
+        assert!(rejected(&relay.handle(ALICE, move_token("w1"))));
+        assert!(rejected(&relay.handle(ALICE, move_token("g1"))));
+        let mut sent_to: Vec<ClientId> = relay
+            .handle(ALICE, move_token("f1"))
+            .into_iter()
+            .map(|(c, _)| c)
+            .collect();
+        sent_to.sort();
+        assert_eq!(sent_to, [GM, BOB]);
+        assert_eq!(
+            relay.snapshot.as_ref().unwrap().tokens()[0].coords,
+            HexCoord { q: 2, r: 2 }
+        );
+
+        // The GM's changes only go to the players
+        relay.leave(BOB);
+        assert_eq!(relay.handle(GM, move_token("g1")).len(), 1);
+    }
+}
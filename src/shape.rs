@@ -0,0 +1,335 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{grid::Layout, hex::HexCoord};
+
+// Most hexes a side of a shape can have. Only the chunks near the camera are loaded, so this is
+// bounded by memory for drawn cells
+pub const MAX_SIZE: i32 = 10_000;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum GridShape {
+    // Rectangular on screen, width counts hexes along a row
+    Rectangle { width: i32, height: i32 },
+    // Every hex within radius of the centre
+    Hexagon { radius: i32 },
+    Triangle { size: i32 },
+    // Straight along the q and r axes
+    Parallelogram { width: i32, height: i32 },
+    // Any set of hexes
+    Custom(HashSet<HexCoord>),
+    // Every hex there is, only the chunks near the camera are ever loaded
+    Unbounded,
+}
+
+impl Default for GridShape {
+    fn default() -> Self {
+        GridShape::Rectangle {
+            width: 10,
+            height: 10,
+        }
+    }
+}
+
+impl GridShape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GridShape::Rectangle { .. } => "Rectangle",
+            GridShape::Hexagon { .. } => "Hexagon",
+            GridShape::Triangle { .. } => "Triangle",
+            GridShape::Parallelogram { .. } => "Parallelogram",
+            GridShape::Custom(_) => "Custom",
+            GridShape::Unbounded => "Unbounded",
+        }
+    }
+
+    pub fn contains(&self, coord: &HexCoord, layout: Layout) -> bool {
+        match self {
+            GridShape::Rectangle { width, height } => {
+                let (row, col) = match layout {
+                    Layout::PointyTop => (coord.r, coord.q),
+                    Layout::FlatTop => (coord.q, coord.r),
+                };
+                let (left, top) = (-width / 2, -height / 2);
+                let offset = row.div_euclid(2);
+
+                (top..top + height).contains(&row)
+                    && (left - offset..left - offset + width).contains(&col)
+            }
+            GridShape::Hexagon { radius } => coord.distance(&HexCoord { q: 0, r: 0 }) <= *radius,
+            GridShape::Triangle { size } => {
+                let offset = size / 3;
+                let (q, r) = (coord.q + offset, coord.r + offset);
+
+                q >= 0 && r >= 0 && q + r < *size
+            }
+            GridShape::Parallelogram { width, height } => {
+                let (left, top) = (-width / 2, -height / 2);
+
+                (left..left + width).contains(&coord.q) && (top..top + height).contains(&coord.r)
+            }
+            GridShape::Custom(mask) => mask.contains(coord),
+            GridShape::Unbounded => true,
+        }
+    }
+
+    // Every coord in the shape, roughly centred on 0, 0. Unbounded shapes have too many to list
+    pub fn coords(&self, layout: Layout) -> Vec<HexCoord> {
+        let mut coords = Vec::new();
+
+        match self {
+            GridShape::Rectangle { width, height } => {
+                let (left, top) = (-width / 2, -height / 2);
+
+                for row in top..top + height {
+                    // Shift every other row back so the rows line up on screen
+                    let offset = row.div_euclid(2);
+
+                    for col in (left - offset)..(left - offset + width) {
+                        coords.push(match layout {
+                            Layout::PointyTop => HexCoord { q: col, r: row },
+                            Layout::FlatTop => HexCoord { q: row, r: col },
+                        });
+                    }
+                }
+            }
+            GridShape::Hexagon { radius } => {
+                for q in -radius..=*radius {
+                    for r in (-radius).max(-q - radius)..=(*radius).min(-q + radius) {
+                        coords.push(HexCoord { q, r });
+                    }
+                }
+            }
+            GridShape::Triangle { size } => {
+                let offset = size / 3;
+
+                for q in 0..*size {
+                    for r in 0..size - q {
+                        coords.push(HexCoord {
+                            q: q - offset,
+                            r: r - offset,
+                        });
+                    }
+                }
+            }
+            GridShape::Parallelogram { width, height } => {
+                let (left, top) = (-width / 2, -height / 2);
+
+                for r in top..top + height {
+                    for q in left..left + width {
+                        coords.push(HexCoord { q, r });
+                    }
+                }
+            }
+            GridShape::Custom(mask) => coords.extend(mask.iter()),
+            GridShape::Unbounded => {}
+        }
+
+        coords
+    }
+
+    // First and last hex of every straight row of the shape, every other hex lies between them.
+    // Enough to find how far the shape reaches without going through all of its hexes
+    pub fn edge_coords(&self, layout: Layout) -> Vec<HexCoord> {
+        let rows: Vec<(HexCoord, HexCoord)> = match self {
+            GridShape::Rectangle { width, height } => {
+                let (left, top) = (-width / 2, -height / 2);
+
+                (top..top + height)
+                    .filter(|_| *width > 0)
+                    .map(|row| {
+                        let first = left - row.div_euclid(2);
+                        let last = first + width - 1;
+                        match layout {
+                            Layout::PointyTop => {
+                                (HexCoord { q: first, r: row }, HexCoord { q: last, r: row })
+                            }
+                            Layout::FlatTop => {
+                                (HexCoord { q: row, r: first }, HexCoord { q: row, r: last })
+                            }
+                        }
+                    })
+                    .collect()
+            }
+            GridShape::Hexagon { radius } => (-radius..=*radius)
+                .map(|q| {
+                    let first = (-radius).max(-q - radius);
+                    let last = (*radius).min(-q + radius);
+                    (HexCoord { q, r: first }, HexCoord { q, r: last })
+                })
+                .collect(),
+            GridShape::Triangle { size } => {
+                let offset = size / 3;
+
+                (0..*size)
+                    .map(|q| {
+                        let q0 = q - offset;
+                        (
+                            HexCoord { q: q0, r: -offset },
+                            HexCoord {
+                                q: q0,
+                                r: size - q - 1 - offset,
+                            },
+                        )
+                    })
+                    .collect()
+            }
+            GridShape::Parallelogram { width, height } => {
+                let (left, top) = (-width / 2, -height / 2);
+
+                (top..top + height)
+                    .filter(|_| *width > 0)
+                    .map(|r| {
+                        (
+                            HexCoord { q: left, r },
+                            HexCoord {
+                                q: left + width - 1,
+                                r,
+                            },
+                        )
+                    })
+                    .collect()
+            }
+            GridShape::Custom(mask) => return mask.iter().copied().collect(),
+            GridShape::Unbounded => return Vec::new(),
+        };
+
+        rows.into_iter().flat_map(|(a, b)| [a, b]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(shape: GridShape) -> usize {
+        let coords = shape.coords(Layout::PointyTop);
+        let unique: HashSet<&HexCoord> = coords.iter().collect();
+        assert_eq!(coords.len(), unique.len());
+
+        coords.len()
+    }
+
+    #[test]
+    fn rectangle_has_independent_sides() {
+        let shape = GridShape::Rectangle {
+            width: 7,
+            height: 3,
+        };
+        assert_eq!(count(shape.clone()), 21);
+
+        for layout in [Layout::PointyTop, Layout::FlatTop] {
+            let coords = shape.coords(layout);
+            let rows: HashSet<i32> = coords
+                .iter()
+                .map(|c| match layout {
+                    Layout::PointyTop => c.r,
+                    Layout::FlatTop => c.q,
+                })
+                .collect();
+            assert_eq!(rows.len(), 3);
+        }
+    }
+
+    #[test]
+    fn hexagon() {
+        assert_eq!(count(GridShape::Hexagon { radius: 0 }), 1);
+        assert_eq!(count(GridShape::Hexagon { radius: 2 }), 19);
+
+        let centre = HexCoord { q: 0, r: 0 };
+        let coords = GridShape::Hexagon { radius: 3 }.coords(Layout::PointyTop);
+        assert!(coords.iter().all(|c| c.distance(&centre) <= 3));
+    }
+
+    #[test]
+    fn triangle_and_parallelogram() {
+        assert_eq!(count(GridShape::Triangle { size: 4 }), 10);
+        assert_eq!(
+            count(GridShape::Parallelogram {
+                width: 4,
+                height: 5
+            }),
+            20
+        );
+    }
+
+    #[test]
+    fn contains_matches_coords() {
+        let mask: HashSet<HexCoord> = [HexCoord { q: 3, r: -1 }].into();
+        for shape in [
+            GridShape::Rectangle {
+                width: 7,
+                height: 4,
+            },
+            GridShape::Hexagon { radius: 3 },
+            GridShape::Triangle { size: 5 },
+            GridShape::Parallelogram {
+                width: 3,
+                height: 6,
+            },
+            GridShape::Custom(mask),
+        ] {
+            for layout in [Layout::PointyTop, Layout::FlatTop] {
+                let coords: HashSet<HexCoord> = shape.coords(layout).into_iter().collect();
+                for q in -10..10 {
+                    for r in -10..10 {
+                        let c = HexCoord { q, r };
+                        assert_eq!(shape.contains(&c, layout), coords.contains(&c));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edges_reach_as_far_as_the_shape() {
+        // Along both axes and both diagonals, which covers either layout on screen
+        let extent = |coords: &[HexCoord]| {
+            let axes = |c: &HexCoord| [c.q, c.r, 2 * c.q + c.r, c.q + 2 * c.r];
+            coords
+                .iter()
+                .map(axes)
+                .fold(([i32::MAX; 4], [i32::MIN; 4]), |(min, max), a| {
+                    (
+                        std::array::from_fn(|i| min[i].min(a[i])),
+                        std::array::from_fn(|i| max[i].max(a[i])),
+                    )
+                })
+        };
+
+        for shape in [
+            GridShape::Rectangle {
+                width: 7,
+                height: 4,
+            },
+            GridShape::Rectangle {
+                width: 1,
+                height: 5,
+            },
+            GridShape::Hexagon { radius: 3 },
+            GridShape::Triangle { size: 5 },
+            GridShape::Parallelogram {
+                width: 3,
+                height: 6,
+            },
+        ] {
+            for layout in [Layout::PointyTop, Layout::FlatTop] {
+                let coords = shape.coords(layout);
+                let edges = shape.edge_coords(layout);
+                assert!(edges.iter().all(|c| shape.contains(c, layout)));
+                assert_eq!(extent(&edges), extent(&coords));
+            }
+        }
+
+        assert!(GridShape::Unbounded
+            .edge_coords(Layout::PointyTop)
+            .is_empty());
+    }
+
+    #[test]
+    fn custom_mask() {
+        let mask: HashSet<HexCoord> = [HexCoord { q: 3, r: -1 }, HexCoord { q: 0, r: 0 }].into();
+        assert_eq!(count(GridShape::Custom(mask)), 2);
+    }
+}
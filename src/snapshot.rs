@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::{Background, BackgroundEvent},
+    cell::Cell,
+    fog::Fog,
+    grid::{Grid, GridSettings},
+    hex::HexCoord,
+    initiative_tracker::{Creature, Tracker},
+    layer::{Layer, Layers},
+    light::Lighting,
+    shape::GridShape,
+    token::Token,
+    view::visible_to_players,
+    wall::Walls,
+};
+
+// Everything needed to show the map in another app
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Snapshot {
+    shape: GridShape,
+    holes: HashSet<HexCoord>,
+    settings: GridSettings,
+    blank_color: Color,
+    // Only the cells that have been drawn on, loaded or not
+    cells: Vec<Cell>,
+    tokens: Vec<Token>,
+    walls: Walls,
+    fog: Fog,
+    lighting: Lighting,
+    layers: Layers,
+    background: Option<String>,
+    tracker: Vec<Creature>,
+}
+
+// A change to a snapshot, small enough to send on every move
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Op {
+    MoveToken {
+        creature_id: String,
+        coords: HexCoord,
+        // Feet the token has moved this turn, so movement left is the same on every screen
+        moved: i32,
+    },
+    // Blank cells stand for cells that have been cleared
+    SetCells(Vec<Cell>),
+    SetFog(Fog),
+}
+
+impl Snapshot {
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    // Id of the party token with the given id or name
+    pub fn party_token(&self, id_or_name: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .filter(|t| t.is_party())
+            .find(|t| t.creature_id() == id_or_name || t.name().eq_ignore_ascii_case(id_or_name))
+            .map(|t| t.creature_id())
+    }
+
+    // Ops turning old into this snapshot, or None when only the whole snapshot will do
+    pub fn ops_since(&self, old: &Snapshot) -> Option<Vec<Op>> {
+        let same_tokens = self.tokens.len() == old.tokens.len()
+            && self.tokens.iter().zip(&old.tokens).all(|(new, old)| {
+                let mut moved = old.clone();
+                moved.coords = new.coords;
+                moved.moved = new.moved;
+                moved == *new
+            });
+        if !same_tokens
+            || self.shape != old.shape
+            || self.holes != old.holes
+            || self.settings != old.settings
+            || self.blank_color != old.blank_color
+            || self.walls != old.walls
+            || self.lighting != old.lighting
+            || self.layers != old.layers
+            || self.background != old.background
+            || self.tracker != old.tracker
+        {
+            return None;
+        }
+
+        let mut ops: Vec<Op> = self
+            .tokens
+            .iter()
+            .zip(&old.tokens)
+            .filter(|(new, old)| new.coords != old.coords || new.moved != old.moved)
+            .map(|(new, _)| Op::MoveToken {
+                creature_id: new.creature_id().to_string(),
+                coords: new.coords,
+                moved: new.moved,
+            })
+            .collect();
+
+        let old_cells: HashMap<HexCoord, &Cell> = old.cells.iter().map(|c| (c.pos, c)).collect();
+        let new_cells: HashSet<HexCoord> = self.cells.iter().map(|c| c.pos).collect();
+        let cells: Vec<Cell> = self
+            .cells
+            .iter()
+            .filter(|c| old_cells.get(&c.pos) != Some(c))
+            .cloned()
+            .chain(
+                old.cells
+                    .iter()
+                    .filter(|c| !new_cells.contains(&c.pos))
+                    .map(|c| Cell::blank(c.pos, self.blank_color)),
+            )
+            .collect();
+        if !cells.is_empty() {
+            ops.push(Op::SetCells(cells));
+        }
+
+        if self.fog != old.fog {
+            ops.push(Op::SetFog(self.fog.clone()));
+        }
+
+        Some(ops)
+    }
+
+    pub fn apply(&mut self, op: &Op) {
+        match op {
+            Op::MoveToken {
+                creature_id,
+                coords,
+                moved,
+            } => {
+                if let Some(token) = self
+                    .tokens
+                    .iter_mut()
+                    .find(|t| t.creature_id() == creature_id)
+                {
+                    token.coords = *coords;
+                    token.moved = *moved;
+                }
+            }
+            Op::SetCells(cells) => {
+                let changed: HashSet<HexCoord> = cells.iter().map(|c| c.pos).collect();
+                self.cells.retain(|c| !changed.contains(&c.pos));
+                let blank_color = self.blank_color;
+                self.cells.extend(
+                    cells
+                        .iter()
+                        .filter(|c| **c != Cell::blank(c.pos, blank_color))
+                        .cloned(),
+                );
+                self.cells.sort_by_key(|c| (c.pos.r, c.pos.q));
+            }
+            Op::SetFog(fog) => self.fog = fog.clone(),
+        }
+    }
+
+    // Leaves out everything only the GM should see
+    pub fn for_players(mut self) -> Self {
+        for cell in &mut self.cells {
+            cell.overlays.remove(&Layer::GmNotes);
+            cell.note = None;
+        }
+
+        let fog = &self.fog;
+        let hidden: HashSet<String> = self
+            .tokens
+            .iter()
+            .filter(|t| !visible_to_players(t, fog))
+            .map(|t| t.creature_id().to_string())
+            .collect();
+        self.tokens.retain(|t| !hidden.contains(t.creature_id()));
+
+        // Monsters the players can't see yet stay out of the turn order
+        self.tracker.retain(|c| !hidden.contains(&c.id));
+        for creature in self.tracker.iter_mut().filter(|c| c.player.is_none()) {
+            creature.cr = None;
+            creature.current_ac = 0;
+        }
+
+        self
+    }
+}
+
+// Applies a snapshot taken in another app
+#[derive(Event)]
+pub struct SnapshotEvent(pub Snapshot);
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SnapshotEvent>()
+            .add_systems(Update, on_snapshot);
+    }
+}
+
+#[derive(SystemParam)]
+pub struct SnapshotSource<'w, 's> {
+    grid_q: Query<'w, 's, &'static Grid>,
+    cell_q: Query<'w, 's, &'static Cell>,
+    token_q: Query<'w, 's, &'static Token>,
+    walls_q: Query<'w, 's, &'static Walls>,
+    fog_q: Query<'w, 's, &'static Fog>,
+    lighting_q: Query<'w, 's, &'static Lighting>,
+    layers_q: Query<'w, 's, &'static Layers>,
+    background_q: Query<'w, 's, &'static Background>,
+    tracker_q: Query<'w, 's, &'static Tracker>,
+}
+
+impl SnapshotSource<'_, '_> {
+    pub fn capture(&self) -> Option<Snapshot> {
+        let (Ok(grid), Ok(walls), Ok(fog), Ok(lighting), Ok(layers), Ok(background), Ok(tracker)) = (
+            self.grid_q.get_single(),
+            self.walls_q.get_single(),
+            self.fog_q.get_single(),
+            self.lighting_q.get_single(),
+            self.layers_q.get_single(),
+            self.background_q.get_single(),
+            self.tracker_q.get_single(),
+        ) else {
+            return None;
+        };
+
+        // Sorted so the same map always gives the same snapshot
+        let mut cells: Vec<Cell> = grid
+            .cells
+            .values()
+            .filter_map(|e| self.cell_q.get(*e).ok())
+            .chain(grid.stored_cells())
+            .filter(|c| !c.is_blank(grid))
+            .cloned()
+            .collect();
+        cells.sort_by_key(|c| (c.pos.r, c.pos.q));
+
+        let mut tokens: Vec<Token> = self.token_q.iter().cloned().collect();
+        tokens.sort_by(|a, b| a.creature_id().cmp(b.creature_id()));
+
+        Some(Snapshot {
+            shape: grid.shape.clone(),
+            holes: grid.holes.clone(),
+            settings: grid.settings,
+            blank_color: grid.blank_color,
+            cells,
+            tokens,
+            walls: walls.clone(),
+            fog: fog.clone(),
+            lighting: *lighting,
+            layers: layers.clone(),
+            background: background.loaded().map(str::to_string),
+            tracker: tracker.ordered.clone(),
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn on_snapshot(
+    mut events: EventReader<SnapshotEvent>,
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut grid_q: Query<&mut Grid>,
+    mut cell_q: Query<&mut Cell>,
+    mut token_q: Query<(Entity, &mut Token, &mut Transform)>,
+    mut walls_q: Query<&mut Walls>,
+    mut fog_q: Query<&mut Fog>,
+    mut lighting_q: Query<&mut Lighting>,
+    mut layers_q: Query<&mut Layers>,
+    background_q: Query<&Background>,
+    mut background_event: EventWriter<BackgroundEvent>,
+    mut tracker_q: Query<&mut Tracker>,
+) {
+    // Only the newest one matters
+    let Some(SnapshotEvent(snapshot)) = events.read().last() else {
+        return;
+    };
+    let mut grid = grid_q.single_mut();
+
+    if grid.shape != snapshot.shape
+        || grid.holes != snapshot.holes
+        || grid.settings != snapshot.settings
+        || grid.blank_color != snapshot.blank_color
+    {
+        grid.reshape(
+            snapshot.shape.clone(),
+            snapshot.holes.clone(),
+            snapshot.settings,
+            snapshot.blank_color,
+            &mut commands,
+        );
+    }
+
+    grid.replace_cells(snapshot.cells.clone(), |e, cell| {
+        let Ok(mut current) = cell_q.get_mut(e) else {
+            return;
+        };
+        if *current != cell {
+            *current = cell;
+        }
+    });
+
+    let mut wanted: HashMap<&str, &Token> = snapshot
+        .tokens
+        .iter()
+        .map(|t| (t.creature_id(), t))
+        .collect();
+    for (e, mut token, mut t) in &mut token_q {
+        match wanted.remove(token.creature_id()) {
+            Some(new) => {
+                if *token != *new {
+                    *token = new.clone();
+                    t.translation = grid.hex_coord_to_pos(&token.coords).extend(t.translation.z);
+                }
+            }
+            None => commands.entity(e).despawn_recursive(),
+        }
+    }
+    for token in wanted.into_values() {
+        Token::create(&mut commands, &asset_server, &grid, token.clone());
+    }
+
+    let mut walls = walls_q.single_mut();
+    if *walls != snapshot.walls {
+        *walls = snapshot.walls.clone();
+    }
+
+    let mut fog = fog_q.single_mut();
+    if *fog != snapshot.fog {
+        fog.replace(&snapshot.fog);
+    }
+
+    let mut lighting = lighting_q.single_mut();
+    if *lighting != snapshot.lighting {
+        *lighting = snapshot.lighting;
+    }
+
+    let mut layers = layers_q.single_mut();
+    if !layers.same_settings(&snapshot.layers) {
+        layers.set_settings(&snapshot.layers);
+    }
+
+    if background_q.single().loaded() != snapshot.background.as_deref() {
+        background_event.send(match &snapshot.background {
+            Some(path) => BackgroundEvent::Load(path.clone()),
+            None => BackgroundEvent::Clear,
+        });
+    }
+
+    let mut tracker = tracker_q.single_mut();
+    if tracker.ordered != snapshot.tracker {
+        tracker.ordered = snapshot.tracker.clone();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::{grid::tests::test_grid, layer::Paint, texture::CellTexture, token::TokenType};
+
+    pub fn snapshot(grid: &Grid, cells: Vec<Cell>, tokens: Vec<Token>) -> Snapshot {
+        Snapshot {
+            shape: grid.shape.clone(),
+            holes: HashSet::new(),
+            settings: grid.settings,
+            blank_color: grid.blank_color,
+            cells,
+            tokens,
+            walls: Walls::default(),
+            fog: Fog::default(),
+            lighting: Lighting::default(),
+            layers: Layers::default(),
+            background: None,
+            tracker: Vec::new(),
+        }
+    }
+
+    fn creature(id: &str, player: Option<bool>) -> Creature {
+        Creature {
+            id: id.to_string(),
+            name: id.to_string(),
+            initiative: 10,
+            player,
+            active: false,
+            number: 0,
+            cr: Some("1/4".to_string()),
+            current_ac: 13,
+        }
+    }
+
+    #[test]
+    fn players_get_no_gm_details() {
+        let grid = test_grid(10);
+        let coords = HexCoord { q: 1, r: 1 };
+
+        let mut cell = Cell::new(&grid, coords);
+        cell.note = Some("Trapdoor".to_string());
+        cell.overlays.insert(
+            Layer::GmNotes,
+            Paint {
+                color: Color::RED,
+                texture: CellTexture::Solid,
+            },
+        );
+        cell.overlays.insert(
+            Layer::Objects,
+            Paint {
+                color: Color::BLUE,
+                texture: CellTexture::Solid,
+            },
+        );
+
+        let mut hidden = Token::new("goblin", "Goblin", TokenType::Enemy, &coords, &Color::RED);
+        hidden.hidden = true;
+        let fighter = Token::new(
+            "fighter",
+            "Fighter",
+            TokenType::Party,
+            &coords,
+            &Color::BLUE,
+        );
+        let mut snapshot = snapshot(&grid, vec![cell], vec![fighter, hidden]);
+        snapshot.background = Some("maps/cave.png".to_string());
+        snapshot.tracker = vec![
+            creature("fighter", Some(true)),
+            creature("goblin", None),
+            creature("orc", None),
+        ];
+
+        // Survives the trip to the other app
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), snapshot);
+
+        let players = snapshot.for_players();
+        assert!(!players.cells[0].overlays.contains_key(&Layer::GmNotes));
+        assert!(players.cells[0].overlays.contains_key(&Layer::Objects));
+        assert_eq!(players.cells[0].note, None);
+        assert_eq!(players.tokens.len(), 1);
+
+        let ids: Vec<&str> = players.tracker.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["fighter", "orc"]);
+        assert_eq!(players.tracker[0].current_ac, 13);
+        assert_eq!(players.tracker[1].cr, None);
+    }
+
+    #[test]
+    fn ops_bring_old_snapshots_up_to_date() {
+        let grid = test_grid(10);
+        let a = HexCoord { q: 0, r: 0 };
+        let b = HexCoord { q: 1, r: 0 };
+        let fighter = Token::new("fighter", "Fighter", TokenType::Party, &a, &Color::BLUE);
+
+        let mut cleared = Cell::new(&grid, a);
+        cleared.color = Color::GREEN;
+        let old = snapshot(&grid, vec![cleared], vec![fighter.clone()]);
+
+        let mut moved = fighter.clone();
+        moved.coords = b;
+        moved.moved = 5;
+        let mut painted = Cell::new(&grid, b);
+        painted.color = Color::RED;
+        let mut new = snapshot(&grid, vec![painted], vec![moved]);
+        new.fog.reveal(&[b]);
+
+        let ops = new.ops_since(&old).unwrap();
+        assert_eq!(ops.len(), 3);
+        let mut patched = old.clone();
+        for op in &ops {
+            patched.apply(op);
+        }
+        assert_eq!(patched, new);
+        assert_eq!(patched.tokens[0].moved, 5);
+        assert_eq!(new.ops_since(&new), Some(Vec::new()));
+
+        // A new turn only resets the movement
+        let mut next_turn = new.clone();
+        next_turn.tokens[0].moved = 0;
+        let ops = next_turn.ops_since(&new).unwrap();
+        assert_eq!(ops.len(), 1);
+        patched.apply(&ops[0]);
+        assert_eq!(patched, next_turn);
+
+        // New tokens need the whole snapshot
+        let goblin = Token::new("goblin", "Goblin", TokenType::Enemy, &a, &Color::RED);
+        new.tokens.push(goblin);
+        assert_eq!(new.ops_since(&old), None);
+
+        assert_eq!(new.party_token("FIGHTER"), Some("fighter"));
+        assert_eq!(new.party_token("goblin"), None);
+    }
+}
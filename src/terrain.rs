@@ -0,0 +1,256 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cell::{Cell, HEX_COLOR},
+    grid::Grid,
+    texture::CellTexture,
+};
+
+pub const DEFAULT_PALETTE_PATH: &str = "palette.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Terrain {
+    pub name: String,
+    pub color: Color,
+    #[serde(default)]
+    pub texture: CellTexture,
+    // Multiplier applied to the cost of entering a cell
+    pub movement_cost: f32,
+    pub blocks_sight: bool,
+    pub blocks_movement: bool,
+}
+
+impl Terrain {
+    pub fn new(name: &str, color: Color, movement_cost: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            color,
+            texture: CellTexture::Solid,
+            movement_cost,
+            blocks_sight: false,
+            blocks_movement: false,
+        }
+    }
+}
+
+#[derive(Event)]
+pub enum PaletteEvent {
+    Removed(usize),
+    // A whole new palette was loaded, giving the new index of each old terrain
+    Replaced(Vec<Option<usize>>),
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PaletteEvent>()
+            .add_systems(Update, (on_palette_event, on_palette_changed).chain());
+    }
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub terrains: Vec<Terrain>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut wall = Terrain::new("Wall", Color::rgb(0.3, 0.3, 0.3), 1.0);
+        wall.blocks_sight = true;
+        wall.blocks_movement = true;
+        wall.texture = CellTexture::Stone;
+
+        let mut forest = Terrain::new("Forest", Color::rgb(0.2, 0.55, 0.25), 2.0);
+        forest.texture = CellTexture::Grass;
+        forest.blocks_sight = true;
+
+        let mut difficult = Terrain::new("Difficult", Color::rgb(0.65, 0.5, 0.3), 2.0);
+        difficult.texture = CellTexture::Hatch;
+
+        let mut water = Terrain::new("Water", Color::rgb(0.25, 0.45, 0.85), 2.0);
+        water.texture = CellTexture::Water;
+
+        Self {
+            terrains: vec![
+                Terrain::new("Open", *HEX_COLOR, 1.0),
+                difficult,
+                water,
+                forest,
+                wall,
+            ],
+        }
+    }
+}
+
+impl Palette {
+    pub fn get(&self, terrain: Option<usize>) -> Option<&Terrain> {
+        terrain.and_then(|t| self.terrains.get(t))
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.terrains.iter().position(|t| t.name == name)
+    }
+
+    // Where each of the terrains is in the other palette, matched by name
+    pub fn remap_to(&self, other: &Palette) -> Vec<Option<usize>> {
+        self.terrains.iter().map(|t| other.find(&t.name)).collect()
+    }
+
+    // Cost of entering a cell with this terrain, None if it can't be entered. Never less than
+    // open ground, pathfinding relies on it
+    pub fn movement_cost(&self, terrain: Option<usize>) -> Option<f32> {
+        match self.get(terrain) {
+            Some(t) if t.blocks_movement => None,
+            Some(t) => Some(t.movement_cost.max(1.0)),
+            None => Some(1.0),
+        }
+    }
+
+    pub fn blocks_sight(&self, terrain: Option<usize>) -> bool {
+        self.get(terrain).is_some_and(|t| t.blocks_sight)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, data).map_err(|e| e.to_string())
+    }
+}
+
+// Terrain index of a cell once the terrain at removed is gone
+fn shift_terrain(terrain: Option<usize>, removed: usize) -> Option<usize> {
+    match terrain {
+        Some(t) if t == removed => None,
+        Some(t) if t > removed => Some(t - 1),
+        t => t,
+    }
+}
+
+fn on_palette_event(
+    mut events: EventReader<PaletteEvent>,
+    mut grid_q: Query<&mut Grid>,
+    mut cell_q: Query<&mut Cell>,
+) {
+    let mut grid = grid_q.single_mut();
+
+    for e in events.read() {
+        match e {
+            PaletteEvent::Removed(removed) => {
+                for mut cell in &mut cell_q {
+                    let terrain = shift_terrain(cell.terrain, *removed);
+                    if terrain != cell.terrain {
+                        cell.terrain = terrain;
+                    }
+                }
+
+                for cell in grid.stored_cells_mut() {
+                    cell.terrain = shift_terrain(cell.terrain, *removed);
+                }
+            }
+            PaletteEvent::Replaced(remap) => {
+                let remap_terrain = |t: Option<usize>| t.and_then(|t| *remap.get(t)?);
+                for mut cell in &mut cell_q {
+                    let terrain = remap_terrain(cell.terrain);
+                    if terrain != cell.terrain {
+                        cell.terrain = terrain;
+                    }
+                }
+
+                for cell in grid.stored_cells_mut() {
+                    cell.terrain = remap_terrain(cell.terrain);
+                }
+            }
+        }
+    }
+}
+
+fn restyle(cell: &mut Cell, base_color: Color, base_texture: CellTexture) {
+    // Only restyle cells that haven't been painted over
+    if cell.color == cell.base_color && cell.texture == cell.base_texture {
+        cell.color = base_color;
+        cell.texture = base_texture;
+    }
+    cell.base_color = base_color;
+    cell.base_texture = base_texture;
+}
+
+// Keeps the cells in sync with edits made to the palette
+fn on_palette_changed(
+    palette_q: Query<&Palette, Changed<Palette>>,
+    mut grid_q: Query<&mut Grid>,
+    mut cell_q: Query<&mut Cell>,
+) {
+    let Ok(palette) = palette_q.get_single() else {
+        return;
+    };
+    let mut grid = grid_q.single_mut();
+    let blank_color = grid.blank_color;
+
+    let base = |cell: &Cell| {
+        palette
+            .get(cell.terrain)
+            .map_or((blank_color, CellTexture::Solid), |t| (t.color, t.texture))
+    };
+    let outdated = |cell: &Cell| base(cell) != (cell.base_color, cell.base_texture);
+
+    for mut cell in cell_q.iter_mut().filter(|c| outdated(c)) {
+        let (color, texture) = base(&cell);
+        restyle(&mut cell, color, texture);
+    }
+
+    for cell in grid.stored_cells_mut().filter(|c| outdated(c)) {
+        let (color, texture) = base(cell);
+        restyle(cell, color, texture);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_round_trip() {
+        let palette = Palette::default();
+
+        let data = serde_json::to_string(&palette).unwrap();
+        let loaded: Palette = serde_json::from_str(&data).unwrap();
+
+        assert_eq!(palette, loaded);
+    }
+
+    #[test]
+    fn movement_cost() {
+        let palette = Palette::default();
+
+        assert_eq!(palette.movement_cost(None), Some(1.0));
+        assert_eq!(palette.movement_cost(palette.find("Difficult")), Some(2.0));
+        assert_eq!(palette.movement_cost(palette.find("Wall")), None);
+        assert!(palette.blocks_sight(palette.find("Wall")));
+        assert!(!palette.blocks_sight(palette.find("Open")));
+
+        // Free movement would break pathfinding
+        let mut free = palette.clone();
+        free.terrains[0].movement_cost = 0.0;
+        assert_eq!(free.movement_cost(Some(0)), Some(1.0));
+    }
+
+    #[test]
+    fn loaded_palettes_keep_terrains_by_name() {
+        let old = Palette::default();
+        let mut new = Palette::default();
+        new.terrains.reverse();
+        new.terrains.remove(0);
+
+        let remap = old.remap_to(&new);
+        assert_eq!(remap[old.find("Open").unwrap()], new.find("Open"));
+        assert_eq!(remap[old.find("Forest").unwrap()], new.find("Forest"));
+        assert_eq!(remap[old.find("Wall").unwrap()], None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::HashMap, f32::consts::TAU};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+    },
+};
+use serde::{Deserialize, Serialize};
+
+// Pixel size of the generated tiles, every pattern repeats evenly within it
+const TILE_SIZE: u32 = 64;
+// World units covered by one repeat of a tile
+const TILE_WORLD_SIZE: f32 = 96.0;
+
+// Greyscale surfaces tinted by the cell color
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum CellTexture {
+    #[default]
+    Solid,
+    Grass,
+    Stone,
+    Water,
+    Hatch,
+    CrossHatch,
+    Dots,
+}
+
+impl CellTexture {
+    pub const ALL: [CellTexture; 7] = [
+        CellTexture::Solid,
+        CellTexture::Grass,
+        CellTexture::Stone,
+        CellTexture::Water,
+        CellTexture::Hatch,
+        CellTexture::CrossHatch,
+        CellTexture::Dots,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CellTexture::Solid => "Solid",
+            CellTexture::Grass => "Grass",
+            CellTexture::Stone => "Stone",
+            CellTexture::Water => "Water",
+            CellTexture::Hatch => "Hatching",
+            CellTexture::CrossHatch => "Cross hatching",
+            CellTexture::Dots => "Dots",
+        }
+    }
+
+    // Brightness of a pixel in the tile, between 0 and 1
+    pub fn sample(&self, x: u32, y: u32) -> f32 {
+        let (x, y) = (x % TILE_SIZE, y % TILE_SIZE);
+        let (fx, fy) = (x as f32 / TILE_SIZE as f32, y as f32 / TILE_SIZE as f32);
+
+        match self {
+            CellTexture::Solid => 1.0,
+            CellTexture::Grass => {
+                let blades = value_noise(fx * 16.0, fy * 4.0, 16, 4);
+                0.7 + 0.2 * value_noise(fx * 4.0, fy * 4.0, 4, 4) + 0.1 * blades
+            }
+            CellTexture::Stone => {
+                // Offset rows of bricks separated by mortar
+                const ROWS: u32 = 4;
+                const COLUMNS: u32 = 2;
+                let row_height = TILE_SIZE / ROWS;
+                let column_width = TILE_SIZE / COLUMNS;
+                let offset = if (y / row_height) % 2 == 1 {
+                    column_width / 2
+                } else {
+                    0
+                };
+
+                let mortar = y % row_height < 2 || (x + offset) % column_width < 2;
+                let grain = 0.1 * value_noise(fx * 8.0, fy * 8.0, 8, 8);
+                if mortar {
+                    0.5 + grain
+                } else {
+                    0.8 + grain
+                }
+            }
+            CellTexture::Water => {
+                let wave = (TAU * (2.0 * fx + 0.25 * (TAU * fy).sin())).sin();
+                0.8 + 0.15 * wave
+            }
+            CellTexture::Hatch => hatch(x + y),
+            CellTexture::CrossHatch => hatch(x + y).min(hatch(x + TILE_SIZE - y)),
+            CellTexture::Dots => {
+                const SPACING: u32 = 16;
+                let dx = (x % SPACING) as f32 - SPACING as f32 / 2.0;
+                let dy = (y % SPACING) as f32 - SPACING as f32 / 2.0;
+                if dx * dx + dy * dy < 6.0 {
+                    0.4
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+
+    fn image(&self) -> Image {
+        let data = (0..TILE_SIZE)
+            .flat_map(|y| (0..TILE_SIZE).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let v = (self.sample(x, y).clamp(0.0, 1.0) * 255.0) as u8;
+                [v, v, v, 255]
+            })
+            .collect();
+
+        let mut image = Image::new(
+            Extent3d {
+                width: TILE_SIZE,
+                height: TILE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8UnormSrgb,
+        );
+        image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: ImageAddressMode::Repeat,
+            address_mode_v: ImageAddressMode::Repeat,
+            ..Default::default()
+        });
+
+        image
+    }
+}
+
+fn hatch(v: u32) -> f32 {
+    const SPACING: u32 = 16;
+    if v % SPACING < 3 {
+        0.35
+    } else {
+        1.0
+    }
+}
+
+fn hash(x: i32, y: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(374761393) ^ (y as u32).wrapping_mul(668265263);
+    h = (h ^ (h >> 13)).wrapping_mul(1274126177);
+    (h ^ (h >> 16)) as f32 / u32::MAX as f32
+}
+
+// Smooth noise that wraps every period_x by period_y units
+fn value_noise(x: f32, y: f32, period_x: i32, period_y: i32) -> f32 {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+    let (tx, ty) = (tx * tx * (3.0 - 2.0 * tx), ty * ty * (3.0 - 2.0 * ty));
+
+    let corner = |dx: i32, dy: i32| {
+        hash(
+            (x0 + dx).rem_euclid(period_x),
+            (y0 + dy).rem_euclid(period_y),
+        )
+    };
+
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+// Texture coordinates in world space so tiles continue seamlessly across neighbouring cells
+pub fn world_uv(pos: Vec2) -> [f32; 2] {
+    [pos.x / TILE_WORLD_SIZE, -pos.y / TILE_WORLD_SIZE]
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup);
+    }
+}
+
+#[derive(Component)]
+pub struct Textures {
+    handles: HashMap<CellTexture, Handle<Image>>,
+}
+
+impl Textures {
+    pub fn get(&self, texture: &CellTexture) -> Option<Handle<Image>> {
+        self.handles.get(texture).cloned()
+    }
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let handles = CellTexture::ALL
+        .iter()
+        .filter(|t| **t != CellTexture::Solid)
+        .map(|t| (*t, images.add(t.image())))
+        .collect();
+
+    commands.spawn(Textures { handles });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn textures_tile_seamlessly() {
+        for texture in CellTexture::ALL {
+            // Stepping across the edge of a tile shouldn't jump more than stepping inside it
+            let mut inside: f32 = 0.0;
+            let mut across: f32 = 0.0;
+            for i in 0..TILE_SIZE {
+                for j in 0..TILE_SIZE - 1 {
+                    inside = inside
+                        .max((texture.sample(j, i) - texture.sample(j + 1, i)).abs())
+                        .max((texture.sample(i, j) - texture.sample(i, j + 1)).abs());
+                }
+
+                across = across
+                    .max((texture.sample(TILE_SIZE - 1, i) - texture.sample(0, i)).abs())
+                    .max((texture.sample(i, TILE_SIZE - 1) - texture.sample(i, 0)).abs());
+            }
+
+            assert!(across <= inside, "{} has a seam", texture.name());
+        }
+    }
+}
@@ -1,14 +1,65 @@
+use std::collections::{HashSet, VecDeque};
+
 use bevy::{math::vec4, prelude::*};
 use bevy_mod_picking::prelude::*;
+use serde::{Deserialize, Serialize};
 
-use crate::{grid::Grid, hex::HexCoord, initiative_tracker::TrackerEvent};
+use crate::{
+    cell::Cell,
+    grid::Grid,
+    hex::HexCoord,
+    initiative_tracker::TrackerEvent,
+    layer::{Layer, Layers},
+    light::Light,
+    movement::MovePreview,
+    pathfinding::{find_path, Path},
+    preferences::Preferences,
+    session::Session,
+    terrain::Palette,
+    view::{MainCamera, ViewMode},
+    wall::Walls,
+};
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum TokenType {
     Party,
     Enemy,
 }
 
+pub const FEET_PER_HEX: i32 = 5;
+// Hexes a token can see, 60ft at 5ft a hex
+pub const DEFAULT_VISION: i32 = 12;
+// Feet a token can move in a turn
+pub const DEFAULT_SPEED: i32 = 30;
+
+fn default_speed() -> i32 {
+    DEFAULT_SPEED
+}
+
+// What happens to a token dropped on a hex that's already taken
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DropPolicy {
+    // Moves to the nearest free hex
+    #[default]
+    Bump,
+    // Trades places with the token there
+    Swap,
+    // Shares the hex with allies, moving on from enemies
+    Stack,
+}
+
+impl DropPolicy {
+    pub const ALL: [DropPolicy; 3] = [DropPolicy::Bump, DropPolicy::Swap, DropPolicy::Stack];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropPolicy::Bump => "Move to a free hex",
+            DropPolicy::Swap => "Swap places",
+            DropPolicy::Stack => "Share with allies",
+        }
+    }
+}
+
 #[derive(Event)]
 pub struct TurnEvent;
 
@@ -17,13 +68,30 @@ pub enum TokenEvent {
     BatchSpawn(Vec<(Token, Vec2)>),
 }
 
-#[derive(Component, Clone, Debug)]
+// Marks the tokens the GM has selected, they move together
+#[derive(Component)]
+pub struct Selected;
+
+#[derive(Component, Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct Token {
     name: String,
     creature_id: String,
     token_type: TokenType,
-    coords: HexCoord,
+    pub coords: HexCoord,
     color: Color,
+    // Hexes the token can see, party tokens reveal the fog around them
+    pub vision: i32,
+    // Only shown to the GM
+    pub hidden: bool,
+    // Hexes the token can see in the dark
+    pub darkvision: i32,
+    pub light: Option<Light>,
+    // Feet of movement a turn
+    #[serde(default = "default_speed")]
+    pub speed: i32,
+    // Feet moved since the creature's turn started
+    #[serde(default)]
+    pub moved: i32,
 }
 
 impl Token {
@@ -40,15 +108,49 @@ impl Token {
             token_type,
             coords: *coords,
             color: *color,
+            vision: DEFAULT_VISION,
+            hidden: false,
+            darkvision: 0,
+            light: None,
+            speed: DEFAULT_SPEED,
+            moved: 0,
         }
     }
 
-    fn create(
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    pub fn creature_id(&self) -> &str {
+        &self.creature_id
+    }
+
+    pub fn is_party(&self) -> bool {
+        matches!(self.token_type, TokenType::Party)
+    }
+
+    // Tokens on the same side, who may share a hex
+    pub fn is_ally(&self, other: &Token) -> bool {
+        self.token_type == other.token_type
+    }
+
+    // Feet of movement left this turn
+    pub fn remaining_movement(&self) -> i32 {
+        (self.speed - self.moved).max(0)
+    }
+
+    pub fn create(
         commands: &mut Commands,
         asset_server: &Res<AssetServer>,
+        grid: &Grid,
         token: Token,
-        pos: &Vec2,
     ) -> Entity {
+        let pos = grid.hex_coord_to_pos(&token.coords);
+
         let texture = match token.token_type {
             TokenType::Party => asset_server.load("sprites/shield-sword.png"),
             TokenType::Enemy => asset_server.load("sprites/skull.png"),
@@ -59,7 +161,8 @@ impl Token {
                 token.clone(),
                 SpriteBundle {
                     texture,
-                    transform: Transform::from_translation(pos.extend(0.1)),
+                    transform: Transform::from_translation(pos.extend(Layer::Tokens.z()))
+                        .with_scale(Vec3::splat(grid.scale())),
                     sprite: Sprite {
                         custom_size: Some(Vec2 { x: 55.0, y: 55.0 }),
                         color: token.color,
@@ -67,8 +170,10 @@ impl Token {
                     },
                     ..Default::default()
                 },
+                On::<Pointer<Click>>::run(on_token_clicked),
                 On::<Pointer<Drag>>::run(on_token_drag),
                 On::<Pointer<DragEnd>>::run(on_token_dropped),
+                Layer::Tokens,
             ))
             .id();
 
@@ -97,57 +202,231 @@ impl Token {
     }
 }
 
+// Shift-click adds the token to the selection or takes it out, a plain click selects only it
+fn on_token_clicked(
+    event: Listener<Pointer<Click>>,
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    selected_q: Query<Entity, With<Selected>>,
+) {
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        if selected_q.contains(event.target) {
+            commands.entity(event.target).remove::<Selected>();
+        } else {
+            commands.entity(event.target).insert(Selected);
+        }
+        return;
+    }
+
+    for e in &selected_q {
+        commands.entity(e).remove::<Selected>();
+    }
+    commands.entity(event.target).insert(Selected);
+}
+
+// Dragging a selected token takes the rest of the selection with it
 fn on_token_drag(
     event: Listener<Pointer<Drag>>,
-    cam_q: Query<&OrthographicProjection, With<Camera>>,
-    mut token_q: Query<&mut Transform, With<Token>>,
+    cam_q: Query<&OrthographicProjection, MainCamera>,
+    mut token_q: Query<(Entity, &Token, &mut Transform, Has<Selected>)>,
+    layers_q: Query<&Layers>,
+    mode: Res<State<ViewMode>>,
+    session_q: Query<&Session>,
+    mut preview_q: Query<&mut MovePreview>,
 ) {
+    if layers_q.single().is_locked(Layer::Tokens) {
+        return;
+    }
+    let Ok((_, _, _, group)) = token_q.get(event.target) else {
+        return;
+    };
+    preview_q.single_mut().token = Some(event.target);
+
+    // The GM moves any token, players in a session only their own
+    let session = session_q.single();
+    let may_move = |token: &Token| *mode.get() == ViewMode::Gm || session.may_move(token);
+
     let cam_proj = cam_q.single();
-    let mut t = token_q.get_mut(event.target).unwrap();
-    t.translation += Vec3 {
+    let delta = Vec3 {
         x: event.delta.x * cam_proj.scale,
         y: -event.delta.y * cam_proj.scale,
         z: 0.0,
+    };
+    for (e, token, mut t, selected) in &mut token_q {
+        if (e == event.target || (group && selected)) && may_move(token) {
+            t.translation += delta;
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn on_token_dropped(
     event: Listener<Pointer<DragEnd>>,
-    mut token_q: Query<(&mut Token, &mut Transform)>,
+    mut token_q: Query<(Entity, &mut Token, &mut Transform, Has<Selected>)>,
     grid_q: Query<&Grid>,
+    cell_q: Query<&Cell>,
+    palette_q: Query<&Palette>,
+    walls_q: Query<&Walls>,
+    mode: Res<State<ViewMode>>,
+    session_q: Query<&Session>,
+    preferences_q: Query<&Preferences>,
+    mut preview_q: Query<&mut MovePreview>,
 ) {
     let grid = grid_q.single();
+    let palette = palette_q.single();
+    let walls = walls_q.single();
+    let session = session_q.single();
+    let preferences = preferences_q.single();
+    let enforce = preferences.enforce_movement;
+    preview_q.single_mut().token = None;
 
-    let (mut token, mut t) = token_q.get_mut(event.target).unwrap();
+    let Ok((_, dragged, t, group)) = token_q.get(event.target) else {
+        return;
+    };
+    let dropped = grid.pos_to_hex_coord(&t.translation.truncate());
+    let offset = &dropped - &dragged.coords;
+    let may_move = |token: &Token| *mode.get() == ViewMode::Gm || session.may_move(token);
 
-    let hex_coords = grid.pos_to_hex_coord(&Vec2 {
-        x: t.translation.x,
-        y: t.translation.y,
-    });
-    let rounded_pos = grid.hex_coord_to_pos(&hex_coords);
+    // The dragged token goes first so it lands where it was dropped
+    let mut members = vec![(event.target, dragged.coords, dragged.remaining_movement())];
+    let mut taken = Vec::new();
+    for (e, token, _, selected) in &token_q {
+        if e == event.target {
+            continue;
+        }
+        if group && selected && may_move(token) {
+            members.push((e, token.coords, token.remaining_movement()));
+        } else {
+            taken.push(token.coords);
+        }
+    }
 
-    t.translation = rounded_pos.extend(0.1);
-    token.coords = hex_coords;
-}
+    let path =
+        |from: &HexCoord, to: &HexCoord| find_token_path(grid, &cell_q, palette, walls, from, to);
+    let can_move = |i: usize, to: &HexCoord| {
+        let (_, from, remaining) = members[i];
+        path(&from, to).is_some_and(|p| !enforce || path_feet(&p) <= remaining)
+    };
 
-fn find_empty_cells(
-    taken_coords: &Vec<HexCoord>,
-    grid: &Grid,
-    start: HexCoord,
-) -> Option<HexCoord> {
-    if !taken_coords.iter().any(|t| t == &start) {
-        return Some(start);
+    // A lone token dropped on another trades places with it
+    let swap = match grid.occupants(&dropped) {
+        [other] if preferences.drop_policy == DropPolicy::Swap && members.len() == 1 => token_q
+            .get(*other)
+            .ok()
+            .filter(|(e, other, _, _)| *e != event.target && may_move(other))
+            .filter(|_| can_move(0, &dropped))
+            .map(|(e, ..)| e),
+        _ => None,
+    };
+    // Each token with where it was, where it goes and whether that uses up its movement
+    let placed: Vec<(Entity, HexCoord, HexCoord, bool)> = match swap {
+        Some(other) => {
+            let (_, start, _) = members[0];
+            let other_coords = token_q.get(other).unwrap().1.coords;
+            vec![
+                (event.target, start, dropped, true),
+                (other, other_coords, start, false),
+            ]
+        }
+        None => {
+            // Allies can share a hex when stacking is allowed, never with an enemy
+            let may_share = |i: usize, c: &HexCoord| {
+                let (_, member, ..) = token_q.get(members[i].0).unwrap();
+                let others: Vec<&Token> = grid
+                    .occupants(c)
+                    .iter()
+                    .filter(|o| !members.iter().any(|(m, ..)| m == *o))
+                    .filter_map(|o| token_q.get(*o).ok().map(|(_, t, ..)| t))
+                    .collect();
+                preferences.drop_policy == DropPolicy::Stack
+                    && !others.is_empty()
+                    && others.iter().all(|o| o.is_ally(member))
+            };
+            let coords: Vec<HexCoord> = members.iter().map(|(_, c, _)| *c).collect();
+            let placed = place_group(grid, &coords, &offset, taken, may_share, can_move);
+            members
+                .iter()
+                .zip(placed)
+                .map(|((e, from, _), to)| (*e, *from, to, true))
+                .collect()
+        }
+    };
+
+    for (e, from, coords, moving) in placed {
+        let (_, mut token, mut t, _) = token_q.get_mut(e).unwrap();
+        t.translation = grid.occupant_pos(e, &coords).extend(t.translation.z);
+        if from != coords {
+            if moving {
+                token.moved += path(&from, &coords).map_or(0, |p| path_feet(&p));
+            }
+            token.coords = coords;
+        }
     }
+}
 
-    for n in grid.get_neighbours(&start).iter() {
-        if !taken_coords.iter().any(|t| t == n) {
-            return Some(*n);
+// Cheapest way a token could walk between the hexes, around walls and over passable terrain
+pub fn find_token_path(
+    grid: &Grid,
+    cell_q: &Query<&Cell>,
+    palette: &Palette,
+    walls: &Walls,
+    from: &HexCoord,
+    to: &HexCoord,
+) -> Option<Path> {
+    find_path(grid, from, to, |from, c| {
+        if walls.blocks(from, c) {
+            return None;
         }
+        let terrain = grid
+            .lookup_cell(c, |e| cell_q.get(e).ok())
+            .and_then(|c| c.terrain);
+        palette.movement_cost(terrain)
+    })
+}
+
+// Movement the path takes up, difficult terrain counting extra
+pub fn path_feet(path: &Path) -> i32 {
+    (path.cost * FEET_PER_HEX as f32).round() as i32
+}
+
+// Where each of a group of tokens ends up when moved by the offset, keeping their formation.
+// Each snaps to the nearest hex it's free to take, or stays near where it was when it can't
+// get there
+fn place_group(
+    grid: &Grid,
+    members: &[HexCoord],
+    offset: &HexCoord,
+    mut taken: Vec<HexCoord>,
+    may_share: impl Fn(usize, &HexCoord) -> bool,
+    can_move: impl Fn(usize, &HexCoord) -> bool,
+) -> Vec<HexCoord> {
+    let mut placed = Vec::with_capacity(members.len());
+    for (i, from) in members.iter().enumerate() {
+        let blocked: Vec<HexCoord> = taken.iter().filter(|c| !may_share(i, c)).copied().collect();
+        let coords = find_empty_cells(&blocked, grid, from + offset)
+            .filter(|to| grid.contains(to) && can_move(i, to))
+            .or_else(|| find_empty_cells(&blocked, grid, *from))
+            .unwrap_or(*from);
+        taken.push(coords);
+        placed.push(coords);
     }
+    placed
+}
+
+// Nearest hex to start that isn't taken, searching outwards over the grid
+fn find_empty_cells(taken_coords: &[HexCoord], grid: &Grid, start: HexCoord) -> Option<HexCoord> {
+    let mut seen = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
 
-    for n in grid.get_neighbours(&start).iter() {
-        if let Some(n) = find_empty_cells(taken_coords, grid, n.to_owned()) {
-            return Some(n);
+    while let Some(coords) = queue.pop_front() {
+        if !taken_coords.contains(&coords) {
+            return Some(coords);
+        }
+        for d in HexCoord::DIRECTIONS.iter() {
+            let n = &coords + d;
+            if grid.contains(&n) && seen.insert(n) {
+                queue.push_back(n);
+            }
         }
     }
 
@@ -156,21 +435,99 @@ fn find_empty_cells(
 
 pub fn on_tracker_event(
     mut event_reader: EventReader<TrackerEvent>,
-    mut tokens_q: Query<(&Token, &mut Sprite)>,
+    mut tokens_q: Query<(&mut Token, &mut Sprite)>,
 ) {
     for e in event_reader.iter() {
         if let TrackerEvent::TurnUpdate(c) = e {
-            for (tok, mut sprite) in &mut tokens_q {
+            for (mut tok, mut sprite) in &mut tokens_q {
                 if c.id == tok.creature_id {
-                    sprite.color = tok.color + vec4(1.0, 1.0, 1.0, 0.0);
+                    // A new turn, a full move
+                    if tok.moved != 0 {
+                        tok.moved = 0;
+                    }
+                    sprite.color = (tok.color + vec4(1.0, 1.0, 1.0, 0.0)).with_a(sprite.color.a());
                 } else {
-                    sprite.color = tok.color;
+                    sprite.color = tok.color.with_a(sprite.color.a());
                 }
             }
         }
     }
 }
 
+// Keeps tokens on their hex when the grid is recalibrated, moving any left off the grid
+pub fn on_grid_changed(
+    grid_q: Query<&Grid, Changed<Grid>>,
+    mut token_q: Query<(Entity, &mut Token, &mut Transform)>,
+) {
+    let Ok(grid) = grid_q.get_single() else {
+        return;
+    };
+
+    let mut taken: Vec<HexCoord> = token_q
+        .iter()
+        .map(|(_, t, _)| t.coords)
+        .filter(|c| grid.contains(c))
+        .collect();
+
+    for (e, mut token, mut t) in &mut token_q {
+        if !grid.contains(&token.coords) {
+            match grid.nearest_free_cell(&token.coords, |c| taken.contains(c)) {
+                Some(coords) => {
+                    log::warn!(
+                        "{} was outside the grid, moved from {:?} to {:?}",
+                        token.name,
+                        token.coords,
+                        coords
+                    );
+                    token.coords = coords;
+                    taken.push(coords);
+                }
+                None => log::warn!("{} is outside the grid with nowhere to go", token.name),
+            }
+        }
+
+        t.translation = grid.occupant_pos(e, &token.coords).extend(t.translation.z);
+        t.scale = Vec3::splat(grid.scale());
+    }
+}
+
+// Keeps the grid's record of which token is on which hex, spreading out tokens that share one
+pub fn sync_occupancy(
+    mut grid_q: Query<&mut Grid>,
+    token_q: Query<(Entity, &Token), Changed<Token>>,
+    mut removed: RemovedComponents<Token>,
+    mut transform_q: Query<&mut Transform, With<Token>>,
+) {
+    let Ok(mut grid) = grid_q.get_single_mut() else {
+        return;
+    };
+    // Nothing drawn from the grid depends on where the tokens are
+    let grid = grid.bypass_change_detection();
+
+    let mut changed = HashSet::new();
+    for e in removed.read() {
+        changed.extend(grid.vacate(e));
+    }
+    for (e, token) in &token_q {
+        if let Some(left) = grid.occupy(e, token.coords) {
+            changed.insert(left);
+        }
+        changed.insert(token.coords);
+    }
+
+    for coords in changed {
+        for e in grid.occupants(&coords) {
+            let Ok(mut t) = transform_q.get_mut(*e) else {
+                continue;
+            };
+            let pos = grid.occupant_pos(*e, &coords);
+            if t.translation.truncate() != pos {
+                t.translation = pos.extend(t.translation.z);
+            }
+        }
+    }
+}
+
 pub fn on_token_event(
     mut event_reader: EventReader<TokenEvent>,
     mut commands: Commands,
@@ -183,14 +540,17 @@ pub fn on_token_event(
     for e in event_reader.iter() {
         match e {
             TokenEvent::BatchSpawn(toks) => {
-                let mut taken_coords = token_q.iter().map(|t| t.coords).collect();
+                let mut taken_coords: Vec<HexCoord> = token_q.iter().map(|t| t.coords).collect();
                 for (tok, pos) in toks {
                     let coords = find_empty_cells(&taken_coords, grid, grid.pos_to_hex_coord(pos));
 
                     match coords {
                         Some(coords) => {
-                            let pos = grid.hex_coord_to_pos(&coords);
-                            Token::create(&mut commands, &asset_server, tok.clone(), &pos);
+                            let tok = Token {
+                                coords,
+                                ..tok.clone()
+                            };
+                            Token::create(&mut commands, &asset_server, grid, tok);
                             taken_coords.push(coords);
                         }
                         None => log::error!("Token exists in that location"),
@@ -200,3 +560,75 @@ pub fn on_token_event(
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::GridSettings, shape::GridShape};
+
+    #[test]
+    fn groups_keep_their_formation() {
+        let grid = Grid::new(
+            GridShape::Rectangle {
+                width: 10,
+                height: 10,
+            },
+            GridSettings::default(),
+        );
+        let members = [HexCoord { q: 0, r: 0 }, HexCoord { q: 1, r: 0 }];
+        let offset = HexCoord { q: 0, r: 2 };
+        let no_sharing = |_: usize, _: &HexCoord| false;
+
+        let placed = place_group(&grid, &members, &offset, vec![], no_sharing, |_, _| true);
+        assert_eq!(
+            placed,
+            vec![HexCoord { q: 0, r: 2 }, HexCoord { q: 1, r: 2 }]
+        );
+
+        // A member landing on someone else's hex snaps next to it instead
+        let other = HexCoord { q: 1, r: 2 };
+        let placed = place_group(&grid, &members, &offset, vec![other], no_sharing, |_, _| {
+            true
+        });
+        assert_eq!(placed[0], HexCoord { q: 0, r: 2 });
+        assert_eq!(placed[1].distance(&other), 1);
+        assert_ne!(placed[1], placed[0]);
+
+        // Unless it's allowed to share it
+        let placed = place_group(
+            &grid,
+            &members,
+            &offset,
+            vec![other],
+            |_, _| true,
+            |_, _| true,
+        );
+        assert_eq!(placed[1], other);
+
+        // Those that can't get there stay put
+        let placed = place_group(&grid, &members, &offset, vec![], no_sharing, |i, _| i == 0);
+        assert_eq!(
+            placed,
+            vec![HexCoord { q: 0, r: 2 }, HexCoord { q: 1, r: 0 }]
+        );
+    }
+
+    #[test]
+    fn movement_is_counted_in_feet() {
+        let coords = HexCoord { q: 0, r: 0 };
+        let mut token = Token::new("a", "Fighter", TokenType::Party, &coords, &Color::BLUE);
+        assert_eq!(token.remaining_movement(), DEFAULT_SPEED);
+
+        // Two hexes, the second difficult
+        let path = Path {
+            cells: vec![coords, HexCoord { q: 1, r: 0 }, HexCoord { q: 2, r: 0 }],
+            cost: 3.0,
+        };
+        token.moved += path_feet(&path);
+        assert_eq!(token.moved, 15);
+        assert_eq!(token.remaining_movement(), 15);
+
+        token.moved += 20;
+        assert_eq!(token.remaining_movement(), 0);
+    }
+}
@@ -1,18 +1,243 @@
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
 
-use crate::draw::{Draw, DrawMode};
-use crate::grid::{Grid, GridEvent};
+use crate::background::{Background, BackgroundEvent, TRANSPARENT_CELL_ALPHA};
+use crate::camera::{view_centre, CameraControl, CameraEvent};
+use crate::cell::{Cell, HEX_COLOR};
+use crate::draw::{BoxShape, Draw, DrawMode, DrawTool};
+use crate::export::{Export, ExportEvent, ExportFormat};
+use crate::fog::{Fog, FogEvent};
+use crate::grid::{Grid, GridEvent, Layout};
+use crate::history::{History, HistoryEvent};
+use crate::import::{Import, ImportEvent, ImportFormat};
 use crate::initiative_tracker::Tracker;
-use crate::token::{Token, TokenEvent, TokenType};
+use crate::inspector::{coord_label, distance_label, Inspector};
+use crate::keymap::{Action, Keymap, Shortcuts};
+use crate::layer::{Layer, Layers};
+use crate::light::{Light, LightLevel, Lighting};
+use crate::minimap::Minimap;
+use crate::preferences::Preferences;
+use crate::session::{Role, Session, SessionEvent, SessionStatus};
+use crate::shape::{GridShape, MAX_SIZE};
+use crate::terrain::{Palette, PaletteEvent, Terrain, DEFAULT_PALETTE_PATH};
+use crate::texture::CellTexture;
+use crate::token::{DropPolicy, Selected, Token, TokenEvent, TokenType, FEET_PER_HEX};
+use crate::view::{visible_to_players, MainCamera, ViewEvent, ViewLocked, ViewMode};
+use crate::wall::{DoorTool, WallKind, Walls};
 
 pub struct Plugin;
 impl bevy::prelude::Plugin for Plugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (toolbox, sync));
+        app.add_systems(
+            Update,
+            (
+                menu,
+                initiative,
+                session,
+                (
+                    toolbox, sync, palette, background, layers, export, import, walls, shortcuts,
+                    camera, minimap, status_bar, cell,
+                )
+                    .run_if(in_state(ViewMode::Gm)),
+            ),
+        );
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+fn menu(
+    mut contexts: EguiContexts,
+    mode: Res<State<ViewMode>>,
+    mut next_mode: ResMut<NextState<ViewMode>>,
+    locked: Option<Res<ViewLocked>>,
+    mut view_event: EventWriter<ViewEvent>,
+    history_q: Query<&History>,
+    mut history_event: EventWriter<HistoryEvent>,
+    mut preferences_q: Query<&mut Preferences>,
+) {
+    // Players can't switch to the GM's view
+    if locked.is_some() {
+        return;
+    }
+
+    let ctx = contexts.ctx_mut();
+
+    egui::TopBottomPanel::top("top").show(ctx, |ui| {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button(
+                "File",
+                |ui| {
+                    if ui.button("Open Obsidian project").clicked() {}
+                },
+            );
+            if *mode.get() == ViewMode::Gm {
+                ui.menu_button("Edit", |ui| {
+                    let history = history_q.single();
+                    let keymap = &preferences_q.single().keymap;
+                    for (action, enabled, event) in [
+                        (Action::Undo, history.can_undo(), HistoryEvent::Undo),
+                        (Action::Redo, history.can_redo(), HistoryEvent::Redo),
+                    ] {
+                        let button = egui::Button::new(action.name())
+                            .shortcut_text(keymap.get(&action).map_or(String::new(), |b| b.name()));
+                        if ui.add_enabled(enabled, button).clicked() {
+                            history_event.send(event);
+                            ui.close_menu();
+                        }
+                    }
+                });
+            }
+            ui.menu_button("View", |ui| {
+                for m in [ViewMode::Gm, ViewMode::Player] {
+                    if ui.radio(*mode.get() == m, m.name()).clicked() {
+                        next_mode.set(m);
+                    }
+                }
+
+                let mut labels = preferences_q.single().coordinate_labels;
+                if ui.checkbox(&mut labels, "Coordinate labels").changed() {
+                    preferences_q.single_mut().coordinate_labels = labels;
+                }
+
+                ui.separator();
+                if ui
+                    .button("Open player view")
+                    .on_hover_text("A second window for the players' screen")
+                    .clicked()
+                {
+                    view_event.send(ViewEvent::OpenPlayerView);
+                    ui.close_menu();
+                }
+            });
+        });
+    });
+}
+
+// Turn order from the tracker, players only get the names of what they can see
+fn initiative(
+    mut contexts: EguiContexts,
+    tracker_q: Query<&Tracker>,
+    token_q: Query<&Token>,
+    fog_q: Query<&Fog>,
+    mode: Res<State<ViewMode>>,
+) {
+    let tracker = tracker_q.single();
+    if tracker.ordered.is_empty() {
+        return;
+    }
+
+    let ctx = contexts.ctx_mut();
+    let fog = fog_q.single();
+    let gm = *mode.get() == ViewMode::Gm;
+
+    egui::Window::new("Initiative").show(ctx, |ui| {
+        egui::Grid::new("initiative")
+            .num_columns(if gm { 4 } else { 2 })
+            .striped(true)
+            .show(ui, |ui| {
+                for creature in &tracker.ordered {
+                    let hidden = token_q
+                        .iter()
+                        .any(|t| t.creature_id() == creature.id && !visible_to_players(t, fog));
+                    if hidden && !gm {
+                        continue;
+                    }
+
+                    let name = egui::RichText::new(&creature.name);
+                    ui.label(if creature.active { name.strong() } else { name });
+                    ui.label(creature.initiative.to_string());
+                    if gm {
+                        ui.label(format!("AC {}", creature.current_ac));
+                        ui.label(
+                            creature
+                                .cr
+                                .as_ref()
+                                .map_or(String::new(), |cr| format!("CR {}", cr)),
+                        );
+                    }
+                    ui.end_row();
+                }
+            });
+    });
+}
+
+// Joining a session on the relay, as the GM or as a player with their characters
+fn session(
+    mut contexts: EguiContexts,
+    mut session_q: Query<&mut Session>,
+    mut session_event: EventWriter<SessionEvent>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut session = session_q.single_mut();
+
+    egui::Window::new("Session")
+        .default_open(false)
+        .show(ctx, |ui| {
+            match session.status.clone() {
+                SessionStatus::Offline => {
+                    ui.horizontal(|ui| {
+                        ui.label("Relay");
+                        ui.text_edit_singleline(&mut session.url);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut session.as_gm, true, "GM");
+                        ui.radio_value(&mut session.as_gm, false, "Player");
+                    });
+
+                    if session.as_gm {
+                        ui.horizontal(|ui| {
+                            ui.label("GM key");
+                            ui.add(egui::TextEdit::singleline(&mut session.key).password(true));
+                        });
+                    } else {
+                        ui.horizontal(|ui| {
+                            ui.label("Characters");
+                            ui.text_edit_singleline(&mut session.characters)
+                                .on_hover_text("Names of your party tokens, separated by commas");
+                        });
+                    }
+
+                    if ui.button("Join").clicked() {
+                        let role = if session.as_gm {
+                            Role::Gm
+                        } else {
+                            Role::Player {
+                                creature_ids: session
+                                    .characters
+                                    .split(',')
+                                    .map(str::trim)
+                                    .filter(|s| !s.is_empty())
+                                    .map(str::to_string)
+                                    .collect(),
+                            }
+                        };
+                        session_event.send(SessionEvent::Join {
+                            url: session.url.clone(),
+                            role,
+                            key: session.key.clone(),
+                        });
+                    }
+                }
+                SessionStatus::Joining => {
+                    ui.label(format!("Joining {}...", session.url));
+                    if ui.button("Cancel").clicked() {
+                        session_event.send(SessionEvent::Leave);
+                    }
+                }
+                SessionStatus::Joined(role) => {
+                    ui.label(format!("Joined {} as {}", session.url, role.name()));
+                    if ui.button("Leave").clicked() {
+                        session_event.send(SessionEvent::Leave);
+                    }
+                }
+            }
+
+            if let Some(e) = &session.error {
+                ui.colored_label(egui::Color32::RED, e);
+            }
+        });
+}
+
 fn sync(mut contexts: EguiContexts, tracker_q: Query<&Tracker>) {
     let ctx = contexts.ctx_mut();
     let tracker = tracker_q.single();
@@ -23,6 +248,919 @@ fn sync(mut contexts: EguiContexts, tracker_q: Query<&Tracker>) {
     });
 }
 
+// Shape picker and its dimensions as grid rows, returns true if the shape was changed
+// The edited shape, only built when it's changed so a custom shape isn't copied every frame
+fn shape_edit(ui: &mut egui::Ui, shape: &GridShape) -> Option<GridShape> {
+    let mut edited = None;
+
+    ui.label("Shape");
+    let size = match shape {
+        GridShape::Rectangle { width, .. } | GridShape::Parallelogram { width, .. } => *width,
+        GridShape::Hexagon { radius } => *radius * 2 + 1,
+        GridShape::Triangle { size } => *size,
+        GridShape::Custom(_) | GridShape::Unbounded => 10,
+    };
+    egui::ComboBox::from_id_source("grid_shape")
+        .selected_text(shape.name())
+        .show_ui(ui, |ui| {
+            for option in [
+                GridShape::Rectangle {
+                    width: size,
+                    height: size,
+                },
+                GridShape::Hexagon { radius: size / 2 },
+                GridShape::Triangle { size },
+                GridShape::Parallelogram {
+                    width: size,
+                    height: size,
+                },
+                GridShape::Unbounded,
+            ] {
+                let name = option.name();
+                if ui.selectable_label(shape.name() == name, name).clicked() && shape.name() != name
+                {
+                    edited = Some(option);
+                }
+            }
+        });
+    ui.end_row();
+
+    let drag = |ui: &mut egui::Ui, label: &str, value: &mut i32| {
+        ui.label(label);
+        let changed = ui
+            .add(
+                egui::DragValue::new(value)
+                    .speed(1.0)
+                    .clamp_range(1..=MAX_SIZE),
+            )
+            .changed();
+        ui.end_row();
+        changed
+    };
+
+    let resized = match *shape {
+        GridShape::Rectangle {
+            mut width,
+            mut height,
+        } => (drag(ui, "Width", &mut width) | drag(ui, "Height", &mut height))
+            .then_some(GridShape::Rectangle { width, height }),
+        GridShape::Parallelogram {
+            mut width,
+            mut height,
+        } => (drag(ui, "Width", &mut width) | drag(ui, "Height", &mut height))
+            .then_some(GridShape::Parallelogram { width, height }),
+        GridShape::Hexagon { mut radius } => {
+            drag(ui, "Radius", &mut radius).then_some(GridShape::Hexagon { radius })
+        }
+        GridShape::Triangle { mut size } => {
+            drag(ui, "Size", &mut size).then_some(GridShape::Triangle { size })
+        }
+        GridShape::Custom(ref mask) => {
+            ui.label("Cells");
+            ui.label(mask.len().to_string());
+            ui.end_row();
+            None
+        }
+        GridShape::Unbounded => None,
+    };
+
+    edited.or(resized)
+}
+
+// Returns true if the color was changed
+fn color_edit(ui: &mut egui::Ui, color: &mut Color) -> bool {
+    let mut rgba = color.as_rgba_u8();
+    let changed = ui.color_edit_button_srgba_unmultiplied(&mut rgba).changed();
+    if changed {
+        *color = Color::from(rgba.map(|c| c as f32 / 255.0));
+    }
+
+    changed
+}
+
+// Returns true if a different texture was picked
+fn texture_picker(ui: &mut egui::Ui, id: impl std::hash::Hash, texture: &mut CellTexture) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_id_source(id)
+        .selected_text(texture.name())
+        .show_ui(ui, |ui| {
+            for t in CellTexture::ALL {
+                changed |= ui.selectable_value(texture, t, t.name()).changed();
+            }
+        });
+
+    changed
+}
+
+// One of the preset lights or none
+fn light_picker(ui: &mut egui::Ui, id: impl std::hash::Hash, light: &mut Option<Light>) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_id_source(id)
+        .selected_text(light.map_or("No light", |l| l.name()))
+        .show_ui(ui, |ui| {
+            changed |= ui.selectable_value(light, None, "No light").changed();
+            for (name, l) in Light::PRESETS {
+                changed |= ui.selectable_value(light, Some(l), name).changed();
+            }
+        });
+
+    changed
+}
+
+fn layers(
+    mut contexts: EguiContexts,
+    mut layers_q: Query<&mut Layers>,
+    mut fog_q: Query<&mut Fog>,
+    mut fog_event: EventWriter<FogEvent>,
+    mut lighting_q: Query<&mut Lighting>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut layers = layers_q.single_mut();
+    let mut fog = fog_q.single_mut();
+    let mut lighting = lighting_q.single_mut();
+
+    egui::Window::new("Layers").show(ctx, |ui| {
+        egui::Grid::new("layers")
+            .num_columns(4)
+            .striped(true)
+            .show(ui, |ui| {
+                // Top of the stack first
+                for layer in Layer::ALL.iter().rev() {
+                    let selected = layers.selected == *layer;
+                    let label = ui.selectable_label(selected, layer.name()).on_hover_text(
+                        if layer.is_painted() {
+                            "Draw on this layer"
+                        } else if *layer == Layer::Fog {
+                            "Left click reveals cells, right click hides them"
+                        } else if *layer == Layer::Lighting {
+                            "Left click places the light from the toolbox, right click removes lights"
+                        } else {
+                            "Nothing can be drawn on this layer"
+                        },
+                    );
+                    if label.clicked() && !selected {
+                        layers.selected = *layer;
+                    }
+
+                    // Only mark the layers changed when a setting does
+                    let mut settings = *layers.get(*layer);
+                    ui.checkbox(&mut settings.visible, "Visible");
+                    ui.checkbox(&mut settings.locked, "Locked");
+                    ui.add(egui::Slider::new(&mut settings.opacity, 0.0..=1.0).show_value(false));
+                    ui.end_row();
+
+                    if settings != *layers.get(*layer) {
+                        *layers.get_mut(*layer) = settings;
+                    }
+                }
+            });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            let mut enabled = fog.enabled;
+            ui.checkbox(&mut enabled, "Fog of war")
+                .on_hover_text("Party tokens reveal what they can see");
+            if enabled != fog.enabled {
+                fog.enabled = enabled;
+            }
+            if ui.button("Cover everything").clicked() {
+                fog_event.send(FogEvent::Reset);
+            }
+        });
+        ui.horizontal(|ui| {
+            let mut settings = *lighting;
+            ui.checkbox(&mut settings.enabled, "Lighting")
+                .on_hover_text("Only lit cells can be seen, unless a token has darkvision");
+            egui::ComboBox::from_id_source("ambient")
+                .selected_text(settings.ambient.name())
+                .show_ui(ui, |ui| {
+                    for level in LightLevel::ALL {
+                        ui.selectable_value(&mut settings.ambient, level, level.name());
+                    }
+                });
+            if settings != *lighting {
+                *lighting = settings;
+            }
+        });
+    });
+}
+
+// Saves the map as a picture for printing or notes
+fn export(
+    mut contexts: EguiContexts,
+    mut export_q: Query<&mut Export>,
+    mut export_event: EventWriter<ExportEvent>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut export = export_q.single_mut();
+
+    egui::Window::new("Export")
+        .default_open(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("export")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Path");
+                    ui.text_edit_singleline(&mut export.path)
+                        .on_hover_text("The extension is added for you");
+                    ui.end_row();
+
+                    let options = &mut export.options;
+                    ui.label("Scale");
+                    ui.add(
+                        egui::DragValue::new(&mut options.scale)
+                            .speed(0.1)
+                            .clamp_range(0.1..=10.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Margin");
+                    ui.add(
+                        egui::DragValue::new(&mut options.margin)
+                            .speed(1.0)
+                            .clamp_range(0.0..=500.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Show");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut options.tokens, "Tokens");
+                        ui.checkbox(&mut options.coordinates, "Coordinates");
+                        ui.checkbox(&mut options.gm_only, "GM only")
+                            .on_hover_text("GM notes and hidden tokens");
+                    });
+                    ui.end_row();
+                });
+
+            ui.horizontal(|ui| {
+                for format in [ExportFormat::Svg, ExportFormat::Png] {
+                    if ui
+                        .button(format!("Save {}", format.extension().to_uppercase()))
+                        .clicked()
+                    {
+                        export_event.send(ExportEvent(format));
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Hex map");
+                for format in [ExportFormat::HexJson, ExportFormat::HexCsv] {
+                    if ui
+                        .button(format!("Save {}", format.extension().to_uppercase()))
+                        .on_hover_text("Terrains and labels for other hex tools")
+                        .clicked()
+                    {
+                        export_event.send(ExportEvent(format));
+                    }
+                }
+            });
+
+            match &export.result {
+                Some(Ok(path)) => {
+                    ui.label(format!("Saved {}", path));
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+                None => {}
+            }
+        });
+}
+
+// Replaces the map with one made in another app
+fn import(
+    mut contexts: EguiContexts,
+    mut import_q: Query<&mut Import>,
+    mut import_event: EventWriter<ImportEvent>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut import = import_q.single_mut();
+
+    egui::Window::new("Import")
+        .default_open(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Path");
+                ui.text_edit_singleline(&mut import.path).on_hover_text(
+                    "A .dd2vtt or .uvtt file from Dungeondraft or DungeonFog, or a hex map",
+                );
+            });
+
+            ui.horizontal(|ui| {
+                for format in ImportFormat::ALL {
+                    let hover = match format {
+                        ImportFormat::Uvtt => "Replaces the grid, walls, lights and background",
+                        _ => "Replaces the grid with one from another hex tool",
+                    };
+                    if ui.button(format.name()).on_hover_text(hover).clicked() {
+                        import_event.send(ImportEvent(format));
+                    }
+                }
+            });
+
+            match &import.result {
+                Some(Ok(done)) => {
+                    ui.label(done);
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(egui::Color32::RED, e);
+                }
+                None => {}
+            }
+        });
+}
+
+fn walls(
+    mut contexts: EguiContexts,
+    mut walls_q: Query<&mut Walls>,
+    mut door_tool_q: Query<&mut DoorTool>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut door_tool = door_tool_q.single_mut();
+
+    egui::Window::new("Walls")
+        .default_open(false)
+        .show(ctx, |ui| {
+            // Only touched through the buttons, so change detection isn't set off every frame
+            let walls = walls_q.single();
+            ui.label(format!(
+                "{} walls, {} doors",
+                walls.count(|k| *k == WallKind::Wall),
+                walls.count(|k| matches!(k, WallKind::Door { .. }))
+            ));
+
+            ui.checkbox(&mut door_tool.active, "Click doors to open or close them");
+
+            ui.horizontal(|ui| {
+                if ui.button("Open all doors").clicked() {
+                    walls_q.single_mut().set_doors(true);
+                }
+                if ui.button("Close all doors").clicked() {
+                    walls_q.single_mut().set_doors(false);
+                }
+                if ui.button("Clear").clicked() {
+                    walls_q.single_mut().clear();
+                }
+            });
+        });
+}
+
+fn camera(
+    mut contexts: EguiContexts,
+    mut control_q: Query<&mut CameraControl>,
+    mut preferences_q: Query<&mut Preferences>,
+    token_q: Query<&Token>,
+    mut camera_event: EventWriter<CameraEvent>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut control = control_q.single_mut();
+
+    egui::Window::new("Camera")
+        .default_open(false)
+        .show(ctx, |ui| {
+            // Only marked changed when a setting does, so the preferences aren't saved every frame
+            let mut settings = preferences_q.single().camera.clone();
+
+            ui.horizontal(|ui| {
+                if ui.button("Zoom to fit").clicked() {
+                    camera_event.send(CameraEvent::ZoomToFit);
+                }
+                egui::ComboBox::from_id_source("center_on")
+                    .selected_text("Center on token")
+                    .show_ui(ui, |ui| {
+                        for token in &token_q {
+                            if ui.selectable_label(false, token.name()).clicked() {
+                                camera_event.send(CameraEvent::CenterOnToken(
+                                    token.creature_id().to_string(),
+                                ));
+                            }
+                        }
+                    });
+            });
+            ui.checkbox(&mut settings.follow_turns, "Follow the active creature")
+                .on_hover_text("Moves to the token whose turn it is");
+
+            ui.horizontal(|ui| {
+                ui.label("Zoom limits");
+                let (min, max) = (settings.min_scale, settings.max_scale);
+                ui.add(
+                    egui::DragValue::new(&mut settings.min_scale)
+                        .speed(0.01)
+                        .clamp_range(0.01..=max),
+                )
+                .on_hover_text("Closest in");
+                ui.add(
+                    egui::DragValue::new(&mut settings.max_scale)
+                        .speed(0.1)
+                        .clamp_range(min..=100.0),
+                )
+                .on_hover_text("Furthest out");
+            });
+
+            ui.separator();
+            let mut removed = None;
+            for (i, viewpoint) in settings.viewpoints.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.button(&viewpoint.name).clicked() {
+                        camera_event.send(CameraEvent::GoTo(i));
+                    }
+                    if ui.button("Delete").clicked() {
+                        removed = Some(i);
+                    }
+                });
+            }
+            if let Some(i) = removed {
+                settings.viewpoints.remove(i);
+            }
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut control.viewpoint_name);
+                let name = control.viewpoint_name.trim().to_string();
+                if ui
+                    .add_enabled(!name.is_empty(), egui::Button::new("Save view"))
+                    .clicked()
+                {
+                    camera_event.send(CameraEvent::SaveViewpoint(name));
+                    control.viewpoint_name.clear();
+                }
+            });
+
+            if settings != preferences_q.single().camera {
+                preferences_q.single_mut().camera = settings;
+            }
+        });
+}
+
+fn egui_color(color: Color) -> egui::Color32 {
+    let [r, g, b, a] = color.as_rgba_u8();
+    egui::Color32::from_rgba_unmultiplied(r, g, b, a)
+}
+
+// The whole map with the tokens and what the camera shows, click or drag to move the camera
+fn minimap(
+    mut contexts: EguiContexts,
+    minimap_q: Query<&Minimap>,
+    grid_q: Query<&Grid>,
+    token_q: Query<&Token>,
+    cam_q: Query<(&Transform, &OrthographicProjection), MainCamera>,
+    mut camera_event: EventWriter<CameraEvent>,
+) {
+    let minimap = minimap_q.single();
+    let bounds = minimap.bounds;
+    if bounds.is_empty() {
+        return;
+    }
+    let texture = contexts.add_image(minimap.image.clone());
+    let ctx = contexts.ctx_mut();
+    let grid = grid_q.single();
+
+    egui::Window::new("Minimap").show(ctx, |ui| {
+        let size = egui::vec2(200.0, 200.0 * bounds.height() / bounds.width());
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::click_and_drag());
+        let rect = response.rect;
+
+        // Image rows go down the screen, the map goes up
+        let to_screen = |pos: Vec2| {
+            let uv = (pos - bounds.min) / bounds.size();
+            rect.left_bottom() + egui::vec2(uv.x * rect.width(), -uv.y * rect.height())
+        };
+        let to_world = |pos: egui::Pos2| {
+            let uv = Vec2::new(
+                (pos.x - rect.left()) / rect.width(),
+                (rect.bottom() - pos.y) / rect.height(),
+            );
+            bounds.min + uv * bounds.size()
+        };
+
+        painter.image(
+            texture,
+            rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE,
+        );
+        for token in &token_q {
+            let pos = to_screen(grid.hex_coord_to_pos(&token.coords));
+            painter.circle_filled(pos, 3.0, egui_color(token.color()));
+        }
+        if let Ok((t, projection)) = cam_q.get_single() {
+            let view = Rect::from_center_size(t.translation.truncate(), projection.area.size());
+            painter.rect_stroke(
+                egui::Rect::from_two_pos(to_screen(view.min), to_screen(view.max)),
+                0.0,
+                egui::Stroke::new(1.5, egui::Color32::WHITE),
+            );
+        }
+
+        if response.clicked() || response.dragged() {
+            if let Some(pos) = response.interact_pointer_pos() {
+                camera_event.send(CameraEvent::CenterOn(to_world(pos)));
+            }
+        }
+    });
+}
+
+// What's under the pointer, along the bottom of the window
+fn status_bar(
+    mut contexts: EguiContexts,
+    inspector_q: Query<&Inspector>,
+    grid_q: Query<&Grid>,
+    cell_q: Query<&Cell>,
+    palette_q: Query<&Palette>,
+    token_q: Query<(&Token, Option<&Selected>)>,
+) {
+    let ctx = contexts.ctx_mut();
+    let grid = grid_q.single();
+    let hovered = inspector_q.single().hovered;
+
+    egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            let Some(pos) = hovered else {
+                ui.label("Point at a hex to inspect it");
+                return;
+            };
+            ui.label(coord_label(grid, &pos));
+
+            let cell = grid.get_cell(&pos).and_then(|e| cell_q.get(*e).ok());
+            if let Some(terrain) = cell.and_then(|c| palette_q.single().get(c.terrain)) {
+                ui.separator();
+                ui.label(&terrain.name);
+            }
+            if let Some(elevation) = cell.map(|c| c.elevation).filter(|e| *e != 0) {
+                ui.separator();
+                ui.label(format!("Elevation {} ft", elevation));
+            }
+
+            let names: Vec<&str> = token_q
+                .iter()
+                .filter(|(t, _)| t.coords == pos)
+                .map(|(t, _)| t.name())
+                .collect();
+            if !names.is_empty() {
+                ui.separator();
+                ui.label(names.join(", "));
+            }
+            if let Some((selected, _)) = token_q.iter().find(|(_, s)| s.is_some()) {
+                ui.separator();
+                ui.label(format!(
+                    "{} from {}",
+                    distance_label(&selected.coords, &pos),
+                    selected.name()
+                ));
+            }
+
+            if let Some(note) = cell.and_then(|c| c.note.as_ref()) {
+                ui.separator();
+                ui.label(egui::RichText::new(note).italics());
+            }
+        });
+    });
+}
+
+// Name, note and elevation of the cell picked with the edit cell shortcut
+fn cell(
+    mut contexts: EguiContexts,
+    mut inspector_q: Query<&mut Inspector>,
+    mut grid_q: Query<&mut Grid>,
+    mut cell_q: Query<&mut Cell>,
+    preferences_q: Query<&Preferences>,
+) {
+    let mut inspector = inspector_q.single_mut();
+    let Some(pos) = inspector.pinned else {
+        return;
+    };
+    let mut grid = grid_q.single_mut();
+    if !grid.contains(&pos) {
+        inspector.pinned = None;
+        return;
+    }
+
+    // The cell may have been panned out of its chunk
+    let current = match grid.get_cell(&pos).and_then(|e| cell_q.get(*e).ok()) {
+        Some(c) => c.clone(),
+        None => grid
+            .stored_cells()
+            .find(|c| c.pos == pos)
+            .cloned()
+            .unwrap_or_else(|| Cell::new(&grid, pos)),
+    };
+    let mut edited = current.clone();
+    let mut open = true;
+
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Cell").open(&mut open).show(ctx, |ui| {
+        ui.label(coord_label(&grid, &pos));
+        egui::Grid::new("cell")
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                ui.label("Label");
+                let mut label = edited.label.clone().unwrap_or_default();
+                if ui.text_edit_singleline(&mut label).changed() {
+                    edited.label = Some(label).filter(|l| !l.is_empty());
+                }
+                ui.end_row();
+
+                ui.label("Elevation");
+                ui.add(
+                    egui::DragValue::new(&mut edited.elevation)
+                        .speed(FEET_PER_HEX)
+                        .suffix(" ft"),
+                );
+                ui.end_row();
+
+                ui.label("GM note");
+                let mut note = edited.note.clone().unwrap_or_default();
+                if ui.text_edit_multiline(&mut note).changed() {
+                    edited.note = Some(note).filter(|n| !n.is_empty());
+                }
+                ui.end_row();
+            });
+
+        let key = preferences_q
+            .single()
+            .keymap
+            .get(&Action::EditCell)
+            .map_or("the edit cell shortcut".to_string(), |b| b.name());
+        ui.weak(format!("Press {} over another hex to edit it", key));
+    });
+
+    if edited != current {
+        grid.restore_cells(vec![edited], |e, cell| {
+            if let Ok(mut c) = cell_q.get_mut(e) {
+                *c = cell;
+            }
+        });
+    }
+    if !open {
+        inspector.pinned = None;
+    }
+}
+
+// Click a shortcut, then press the keys to bind to it
+fn shortcuts(
+    mut contexts: EguiContexts,
+    mut preferences_q: Query<&mut Preferences>,
+    mut shortcuts_q: Query<&mut Shortcuts>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut shortcuts = shortcuts_q.single_mut();
+
+    egui::Window::new("Shortcuts")
+        .default_open(false)
+        .show(ctx, |ui| {
+            // Only touched through the buttons, so the preferences aren't saved every frame
+            let keymap = &preferences_q.single().keymap;
+            let mut unbind = None;
+
+            egui::Grid::new("shortcuts")
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    for action in Action::ALL {
+                        ui.label(action.name());
+                        let text = if shortcuts.rebinding == Some(action) {
+                            "Press a key...".to_string()
+                        } else {
+                            keymap.get(&action).map_or("None".to_string(), |b| b.name())
+                        };
+                        if ui.button(text).on_hover_text("Escape cancels").clicked() {
+                            shortcuts.rebinding = Some(action);
+                            shortcuts.error = None;
+                        }
+                        if ui.button("Clear").clicked() {
+                            unbind = Some(action);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            for (a, b) in keymap.conflicts() {
+                ui.colored_label(
+                    egui::Color32::YELLOW,
+                    format!("{} and {} share a shortcut", a.name(), b.name()),
+                );
+            }
+            if let Some(e) = &shortcuts.error {
+                ui.colored_label(egui::Color32::RED, e);
+            }
+
+            let reset = ui.button("Reset to defaults").clicked();
+            if let Some(action) = unbind {
+                preferences_q.single_mut().keymap.unbind(&action);
+            }
+            if reset {
+                preferences_q.single_mut().keymap = Keymap::default();
+            }
+        });
+}
+
+fn background(
+    mut contexts: EguiContexts,
+    mut background_q: Query<&mut Background>,
+    grid_q: Query<&Grid>,
+    mut background_event: EventWriter<BackgroundEvent>,
+    mut grid_event: EventWriter<GridEvent>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut background = background_q.single_mut();
+    let grid = grid_q.single();
+
+    egui::Window::new("Background")
+        .default_open(false)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut background.path)
+                    .on_hover_text("Image path inside the assets folder");
+                if ui.button("Load").clicked() {
+                    background_event.send(BackgroundEvent::Load(background.path.clone()));
+                }
+                if ui.button("Clear").clicked() {
+                    background_event.send(BackgroundEvent::Clear);
+                }
+            });
+
+            if ui
+                .checkbox(&mut background.transparent_cells, "Only show painted cells")
+                .changed()
+            {
+                let alpha = if background.transparent_cells {
+                    TRANSPARENT_CELL_ALPHA
+                } else {
+                    1.0
+                };
+                grid_event.send(GridEvent::BlankColor(HEX_COLOR.with_a(alpha)));
+            }
+
+            ui.heading("Calibration");
+            match background.calibration.as_ref().map(|p| p.len()) {
+                Some(clicked) => {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Click the centre of hex {}/3", clicked + 1));
+                        if ui.button("Cancel").clicked() {
+                            background.calibration = None;
+                        }
+                    });
+                }
+                None => {
+                    if ui
+                        .button("Pick hex centres")
+                        .on_hover_text("Click the centres of three hexes that all touch")
+                        .clicked()
+                    {
+                        background.calibration = Some(Vec::new());
+                    }
+                }
+            }
+
+            if let Some(e) = &background.error {
+                ui.label(e);
+            }
+
+            let mut settings = grid.settings;
+            let mut rotation = settings.rotation.to_degrees();
+
+            let mut changed = false;
+            egui::Grid::new("calibration")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Hex size");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut settings.hex_size)
+                                .speed(0.1)
+                                .clamp_range(1.0..=500.0),
+                        )
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Offset");
+                    ui.horizontal(|ui| {
+                        let origin = &mut settings.origin;
+                        changed |= ui.add(egui::DragValue::new(&mut origin.x)).changed();
+                        changed |= ui.add(egui::DragValue::new(&mut origin.y)).changed();
+                    });
+                    ui.end_row();
+
+                    ui.label("Rotation");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut rotation)
+                                .speed(0.1)
+                                .suffix("°")
+                                .clamp_range(-180.0..=180.0),
+                        )
+                        .changed();
+                    ui.end_row();
+                });
+
+            if changed {
+                settings.rotation = rotation.to_radians();
+                grid_event.send(GridEvent::Settings(settings));
+            }
+        });
+}
+
+fn palette(
+    mut contexts: EguiContexts,
+    mut palette_q: Query<&mut Palette>,
+    mut draw_q: Query<&mut Draw>,
+    mut palette_event: EventWriter<PaletteEvent>,
+    mut path: Local<Option<String>>,
+) {
+    let ctx = contexts.ctx_mut();
+    let mut palette = palette_q.single_mut();
+    let mut draw = draw_q.single_mut();
+    let path = path.get_or_insert_with(|| DEFAULT_PALETTE_PATH.to_string());
+
+    let mut changed = false;
+    let mut removed = None;
+    let mut loaded = None;
+
+    egui::Window::new("Palette")
+        .default_open(false)
+        .show(ctx, |ui| {
+            // Only flag the palette as changed on an actual edit, so cells aren't resynced every frame
+            let terrains = &mut palette.bypass_change_detection().terrains;
+
+            egui::Grid::new("palette")
+                .num_columns(7)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Name");
+                    ui.label("Color");
+                    ui.label("Texture");
+                    ui.label("Cost");
+                    ui.label("Blocks sight");
+                    ui.label("Blocks movement");
+                    ui.end_row();
+
+                    for (i, terrain) in terrains.iter_mut().enumerate() {
+                        changed |= ui
+                            .add(egui::TextEdit::singleline(&mut terrain.name).desired_width(80.0))
+                            .changed();
+                        changed |= color_edit(ui, &mut terrain.color);
+                        changed |= texture_picker(ui, ("terrain_texture", i), &mut terrain.texture);
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut terrain.movement_cost)
+                                    .speed(0.1)
+                                    .clamp_range(1.0..=10.0),
+                            )
+                            .changed();
+                        changed |= ui.checkbox(&mut terrain.blocks_sight, "").changed();
+                        changed |= ui.checkbox(&mut terrain.blocks_movement, "").changed();
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            if ui.button("Add").clicked() {
+                terrains.push(Terrain::new("New", Color::GRAY, 1.0));
+                changed = true;
+            }
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(path);
+                if ui.button("Save").clicked() {
+                    let p = Palette {
+                        terrains: terrains.clone(),
+                    };
+                    if let Err(e) = p.save(std::path::Path::new(path)) {
+                        log::error!("Failed to save palette: {}", e);
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    match Palette::load(std::path::Path::new(path)) {
+                        Ok(p) => loaded = Some(p),
+                        Err(e) => log::error!("Failed to load palette: {}", e),
+                    }
+                }
+            });
+        });
+
+    if let Some(i) = removed {
+        palette.terrains.remove(i);
+        palette_event.send(PaletteEvent::Removed(i));
+    }
+
+    if let Some(p) = loaded {
+        palette_event.send(PaletteEvent::Replaced(palette.remap_to(&p)));
+        *palette = p;
+    }
+
+    if changed {
+        palette.set_changed();
+    }
+
+    if draw.terrain >= palette.terrains.len() {
+        draw.terrain = palette.terrains.len().saturating_sub(1);
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 fn toolbox(
     mut commands: Commands,
@@ -31,43 +1169,81 @@ fn toolbox(
     mut token_event: EventWriter<TokenEvent>,
     mut grid_event: EventWriter<GridEvent>,
     grid_q: Query<&Grid>,
-    cam_q: Query<&Transform, With<Camera2d>>,
-    token_q: Query<Entity, With<Token>>,
+    cam_q: Query<(&Camera, &GlobalTransform), MainCamera>,
+    mut token_q: Query<(Entity, &mut Token)>,
     tracker_q: Query<&Tracker>,
+    palette_q: Query<&Palette>,
+    layers_q: Query<&Layers>,
+    mut preferences_q: Query<&mut Preferences>,
 ) {
     let mut draw = draw_q.single_mut();
     let tracker = tracker_q.single();
     let grid = grid_q.single();
+    let palette = palette_q.single();
 
     let ctx = contexts.ctx_mut();
 
-    egui::TopBottomPanel::top("top").show(ctx, |ui| {
-        egui::menu::bar(ui, |ui| {
-            ui.menu_button(
-                "File",
-                |ui| {
-                    if ui.button("Open Obsidian project").clicked() {}
-                },
-            );
-        });
-    });
-
     egui::Window::new("Toolbox").show(ctx, |ui| {
         ui.vertical(|ui| {
             ui.heading("Grid");
-            ui.horizontal(|ui| {
-                let mut size = grid.size;
-                if ui
-                    .add(
-                        egui::DragValue::new(&mut size)
-                            .speed(1.0)
-                            .clamp_range(0..=250),
-                    )
-                    .changed()
-                {
-                    grid_event.send(GridEvent::Resize(size, size));
-                }
-            });
+            egui::Grid::new("grid_settings")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    if let Some(shape) = shape_edit(ui, &grid.shape) {
+                        grid_event.send(GridEvent::Resize(shape));
+                    }
+
+                    if !grid.holes.is_empty() {
+                        ui.label("Cut cells");
+                        ui.horizontal(|ui| {
+                            ui.label(grid.holes.len().to_string());
+                            if ui.button("Restore").clicked() {
+                                grid_event.send(GridEvent::RestoreCells);
+                            }
+                        });
+                        ui.end_row();
+                    }
+
+                    let mut settings = grid.settings;
+                    let mut changed = false;
+
+                    ui.label("Hex radius");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut settings.hex_size)
+                                .speed(0.5)
+                                .clamp_range(5.0..=200.0),
+                        )
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Gap");
+                    changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut settings.spacing)
+                                .speed(0.1)
+                                .clamp_range(0.0..=20.0),
+                        )
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Layout");
+                    ui.horizontal(|ui| {
+                        changed |= ui
+                            .radio_value(&mut settings.layout, Layout::PointyTop, "Pointy top")
+                            .changed();
+                        changed |= ui
+                            .radio_value(&mut settings.layout, Layout::FlatTop, "Flat top")
+                            .changed();
+                    });
+                    ui.end_row();
+
+                    if changed {
+                        grid_event.send(GridEvent::Settings(settings));
+                    }
+                });
 
             ui.heading("Drawing");
             egui::Grid::new("draw_settings")
@@ -75,35 +1251,81 @@ fn toolbox(
                 .spacing([40.0, 4.0])
                 .striped(true)
                 .show(ui, |ui| {
+                    ui.label("Tool");
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut draw.tool, DrawTool::Terrain, "Terrain");
+                        ui.radio_value(&mut draw.tool, DrawTool::Paint, "Paint");
+                        ui.radio_value(&mut draw.tool, DrawTool::Erase, "Erase")
+                            .on_hover_text("Right click erases in any mode");
+                        ui.radio_value(&mut draw.tool, DrawTool::Cut, "Cut")
+                            .on_hover_text("Removes cells from the grid");
+                    });
+                    ui.end_row();
+
                     ui.label("Mode");
                     ui.horizontal(|ui| {
                         ui.radio_value(&mut draw.draw_mode, DrawMode::Cell, "Cell");
                         ui.radio_value(&mut draw.draw_mode, DrawMode::Box, "Box");
-                        if draw.draw_mode == DrawMode::Box {
-                            ui.checkbox(&mut draw.fill, "Fill Box");
-                        }
-
                         ui.radio_value(&mut draw.draw_mode, DrawMode::Line, "Line");
                     });
 
                     ui.end_row();
 
-                    ui.label("Color");
-                    let mut color = draw.color.as_rgba_u8();
-                    ui.color_edit_button_srgba_unmultiplied(&mut color);
-                    draw.color = Color::from(color.map(|c| c as f32 / 255.0));
-                    ui.end_row();
+                    if draw.draw_mode == DrawMode::Box {
+                        ui.label("Box");
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut draw.box_shape, BoxShape::Rect, "Rectangle");
+                            ui.radio_value(
+                                &mut draw.box_shape,
+                                BoxShape::Parallelogram,
+                                "Parallelogram",
+                            );
+                            ui.checkbox(&mut draw.fill, "Fill Box");
+                        });
+
+                        ui.end_row();
+                    }
+
+                    match draw.tool {
+                        DrawTool::Terrain => {
+                            ui.label("Terrain");
+                            let selected = palette
+                                .get(Some(draw.terrain))
+                                .map_or("", |t| t.name.as_str());
+                            egui::ComboBox::from_id_source("terrain")
+                                .selected_text(selected)
+                                .show_ui(ui, |ui| {
+                                    for (i, t) in palette.terrains.iter().enumerate() {
+                                        ui.selectable_value(&mut draw.terrain, i, &t.name);
+                                    }
+                                });
+                            ui.end_row();
+                        }
+                        DrawTool::Paint => {
+                            ui.label("Color");
+                            color_edit(ui, &mut draw.color);
+                            ui.end_row();
+
+                            ui.label("Texture");
+                            texture_picker(ui, "texture", &mut draw.texture);
+                            ui.end_row();
+                        }
+                        DrawTool::Erase | DrawTool::Cut => {}
+                    }
+
+                    if layers_q.single().selected == Layer::Lighting {
+                        ui.label("Light");
+                        light_picker(ui, "draw_light", &mut draw.light);
+                        ui.end_row();
+                    }
                 });
 
             ui.heading("Tokens");
 
             if token_q.is_empty() {
                 if !tracker.ordered.is_empty() && ui.button("Load State").clicked() {
-                    let cam = cam_q.single();
-                    let pos = Vec2 {
-                        x: cam.translation.x,
-                        y: cam.translation.z,
-                    };
+                    let (cam, t) = cam_q.single();
+                    let pos = view_centre(cam, t).unwrap_or_default();
                     let coords = grid.pos_to_hex_coord(&pos);
 
                     let batches = tracker
@@ -144,7 +1366,72 @@ fn toolbox(
             } else if ui.button("Clear state").clicked() {
                 token_q
                     .iter()
-                    .for_each(|e| commands.entity(e).despawn_recursive())
+                    .for_each(|(e, _)| commands.entity(e).despawn_recursive())
+            }
+
+            if !token_q.is_empty() {
+                let mut enforce = preferences_q.single().enforce_movement;
+                if ui
+                    .checkbox(&mut enforce, "Enforce movement")
+                    .on_hover_text("Tokens can't be dropped further than their speed allows")
+                    .changed()
+                {
+                    preferences_q.single_mut().enforce_movement = enforce;
+                }
+
+                let mut policy = preferences_q.single().drop_policy;
+                egui::ComboBox::from_label("Dropped on a token")
+                    .selected_text(policy.name())
+                    .show_ui(ui, |ui| {
+                        for p in DropPolicy::ALL {
+                            ui.selectable_value(&mut policy, p, p.name());
+                        }
+                    });
+                if policy != preferences_q.single().drop_policy {
+                    preferences_q.single_mut().drop_policy = policy;
+                }
+            }
+
+            for (e, mut token) in &mut token_q {
+                ui.horizontal(|ui| {
+                    let mut hidden = token.hidden;
+                    ui.checkbox(&mut hidden, format!("Hide {}", token.name()))
+                        .on_hover_text("Hidden tokens are only shown to the GM");
+                    if hidden != token.hidden {
+                        token.hidden = hidden;
+                    }
+
+                    let mut light = token.light;
+                    if light_picker(ui, e, &mut light) {
+                        token.light = light;
+                    }
+
+                    let mut feet = token.darkvision * FEET_PER_HEX;
+                    ui.add(
+                        egui::DragValue::new(&mut feet)
+                            .speed(FEET_PER_HEX)
+                            .clamp_range(0..=120)
+                            .prefix("Darkvision ")
+                            .suffix(" ft"),
+                    );
+                    if feet / FEET_PER_HEX != token.darkvision {
+                        token.darkvision = feet / FEET_PER_HEX;
+                    }
+
+                    let mut speed = token.speed;
+                    ui.add(
+                        egui::DragValue::new(&mut speed)
+                            .speed(FEET_PER_HEX)
+                            .clamp_range(0..=120)
+                            .prefix("Speed ")
+                            .suffix(" ft"),
+                    );
+                    if speed != token.speed {
+                        token.speed = speed;
+                    }
+                    ui.label(format!("{} ft moved", token.moved))
+                        .on_hover_text("Reset when the creature's turn starts");
+                });
             }
         });
     });
@@ -0,0 +1,284 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    grid::{Grid, GridSettings, Layout},
+    hex::HexCoord,
+    light::{Light, LightLevel},
+    shape::GridShape,
+    wall::{edges_along, WallKind, Walls},
+};
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+struct Point {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Resolution {
+    map_origin: Point,
+    map_size: Point,
+    pixels_per_grid: f32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Portal {
+    // The two ends of the door
+    bounds: Vec<Point>,
+    closed: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct UvttLight {
+    position: Point,
+    range: f32,
+    color: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Environment {
+    ambient_light: Option<String>,
+}
+
+// A Universal VTT map, as exported by Dungeondraft and DungeonFog. Positions are in grid squares
+// from the top left corner
+#[derive(Deserialize, Debug)]
+pub struct Uvtt {
+    resolution: Resolution,
+    #[serde(default)]
+    line_of_sight: Vec<Vec<Point>>,
+    #[serde(default)]
+    objects_line_of_sight: Vec<Vec<Point>>,
+    #[serde(default)]
+    portals: Vec<Portal>,
+    #[serde(default)]
+    lights: Vec<UvttLight>,
+    #[serde(default)]
+    environment: Environment,
+    // Base64 encoded image file
+    #[serde(default)]
+    image: String,
+}
+
+// What a Universal VTT map becomes on a hex grid
+pub struct UvttMap {
+    // The embedded image file and its extension
+    pub image: Option<(Vec<u8>, &'static str)>,
+    pub shape: GridShape,
+    pub settings: GridSettings,
+    pub walls: Walls,
+    pub lights: Vec<(HexCoord, Light)>,
+    pub ambient: LightLevel,
+}
+
+impl Uvtt {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Not a Universal VTT map: {}", e))
+    }
+
+    // Lays hexes of the given layout over the map, one for each square and as wide across their
+    // flats, centred on the image
+    pub fn to_map(&self, layout: Layout) -> Result<UvttMap, String> {
+        let res = &self.resolution;
+        let ppg = res.pixels_per_grid;
+        if ppg <= 0.0 {
+            return Err("The map has no grid size".to_string());
+        }
+        let size = Vec2::new(res.map_size.x, res.map_size.y) * ppg;
+
+        let settings = GridSettings {
+            hex_size: ppg / 3.0_f32.sqrt(),
+            spacing: 0.0,
+            layout,
+            origin: Vec2::ZERO,
+            rotation: 0.0,
+        };
+        // Rows of flat topped hexes run up the map
+        let (along, across) = match layout {
+            Layout::PointyTop => (size.x, size.y),
+            Layout::FlatTop => (size.y, size.x),
+        };
+        let shape = GridShape::Rectangle {
+            width: (along / ppg).ceil() as i32 + 1,
+            height: (across / (1.5 * settings.hex_size)).ceil() as i32 + 1,
+        };
+        let grid = Grid::new(shape.clone(), settings);
+
+        // The background image is centred on the origin, with y going up instead of down
+        let to_world = |p: &Point| {
+            Vec2::new(
+                (p.x - res.map_origin.x) * ppg - size.x / 2.0,
+                size.y / 2.0 - (p.y - res.map_origin.y) * ppg,
+            )
+        };
+
+        let mut walls = Walls::default();
+        for line in self.line_of_sight.iter().chain(&self.objects_line_of_sight) {
+            for pair in line.windows(2) {
+                for edge in edges_along(&grid, to_world(&pair[0]), to_world(&pair[1])) {
+                    walls.set(edge, Some(WallKind::Wall));
+                }
+            }
+        }
+        // Doors take the place of the walls they're set in
+        for portal in &self.portals {
+            if let [a, b, ..] = portal.bounds[..] {
+                for edge in edges_along(&grid, to_world(&a), to_world(&b)) {
+                    walls.set(
+                        edge,
+                        Some(WallKind::Door {
+                            open: !portal.closed,
+                        }),
+                    );
+                }
+            }
+        }
+
+        // Ranges are in squares, which are as wide as the hexes
+        let lights = self
+            .lights
+            .iter()
+            .map(|l| {
+                let light = Light {
+                    bright: (l.range / 2.0).round() as i32,
+                    dim: l.range.round().max(1.0) as i32,
+                    color: parse_color(&l.color)?,
+                };
+                Ok((grid.pos_to_hex_coord(&to_world(&l.position)), light))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let ambient = match &self.environment.ambient_light {
+            Some(color) => {
+                let color = parse_color(color)?;
+                match color.r().max(color.g()).max(color.b()) {
+                    b if b >= 0.9 => LightLevel::Bright,
+                    b if b >= 0.4 => LightLevel::Dim,
+                    _ => LightLevel::Dark,
+                }
+            }
+            None => LightLevel::Bright,
+        };
+
+        let image = if self.image.is_empty() {
+            None
+        } else {
+            let bytes = BASE64_STANDARD
+                .decode(self.image.trim())
+                .map_err(|e| format!("Couldn't decode the map's image: {}", e))?;
+            let extension = image_extension(&bytes)
+                .ok_or_else(|| "The map's image isn't a PNG, JPEG or WebP".to_string())?;
+            Some((bytes, extension))
+        };
+
+        Ok(UvttMap {
+            image,
+            shape,
+            settings,
+            walls,
+            lights,
+            ambient,
+        })
+    }
+}
+
+// Colors are written AARRGGBB, the alpha is dropped since lights are shaded by level
+fn parse_color(hex: &str) -> Result<Color, String> {
+    let digits = hex.trim_start_matches('#');
+    let argb = u32::from_str_radix(digits, 16)
+        .ok()
+        .filter(|_| digits.len() == 6 || digits.len() == 8)
+        .ok_or_else(|| format!("Bad color {}", hex))?;
+
+    let [_, r, g, b] = argb.to_be_bytes();
+    Ok(Color::rgb_u8(r, g, b))
+}
+
+// Tells the image format apart by the first bytes of the file
+fn image_extension(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [0xFF, 0xD8, 0xFF, ..] => Some("jpg"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 4 by 4 map split down the middle by a wall with a closed door in it and a red light in
+    // the top left
+    const MAP: &str = r#"{
+        "format": 0.3,
+        "resolution": {
+            "map_origin": { "x": 0, "y": 0 },
+            "map_size": { "x": 4, "y": 4 },
+            "pixels_per_grid": 100
+        },
+        "line_of_sight": [
+            [{ "x": 2, "y": 0 }, { "x": 2, "y": 1.5 }],
+            [{ "x": 2, "y": 2.5 }, { "x": 2, "y": 4 }]
+        ],
+        "portals": [{
+            "position": { "x": 2, "y": 2 },
+            "bounds": [{ "x": 2, "y": 1.5 }, { "x": 2, "y": 2.5 }],
+            "rotation": 1.5708,
+            "closed": true,
+            "freestanding": false
+        }],
+        "environment": { "baked_lighting": true, "ambient_light": "ff202020" },
+        "lights": [{
+            "position": { "x": 1, "y": 1 },
+            "range": 4,
+            "intensity": 1,
+            "color": "ffff0000",
+            "shadows": true
+        }],
+        "image": "iVBORw0KGgo="
+    }"#;
+
+    #[test]
+    fn walls_doors_and_lights_come_across() {
+        let mut map = Uvtt::parse(MAP).unwrap().to_map(Layout::PointyTop).unwrap();
+
+        assert!((map.settings.hex_size * 3.0_f32.sqrt() - 100.0).abs() < 1e-3);
+        assert_eq!(map.image.as_ref().map(|(_, ext)| *ext), Some("png"));
+        assert_eq!(map.ambient, LightLevel::Dark);
+
+        // The wall runs down the middle of the image, with the door halfway along
+        let (left, right) = (HexCoord { q: -1, r: 0 }, HexCoord { q: 1, r: 0 });
+        assert!(map.walls.blocks_line(&left, &right));
+        assert!(map
+            .walls
+            .blocks_line(&HexCoord { q: -2, r: 2 }, &HexCoord { q: 0, r: 2 }));
+        assert!(map.walls.count(|k| matches!(k, WallKind::Door { .. })) > 0);
+
+        map.walls.set_doors(true);
+        assert!(!map.walls.blocks_line(&left, &right));
+        assert!(map
+            .walls
+            .blocks_line(&HexCoord { q: -2, r: 2 }, &HexCoord { q: 0, r: 2 }));
+
+        let [(_, light)] = map.lights[..] else {
+            panic!("expected one light");
+        };
+        assert_eq!((light.bright, light.dim), (2, 4));
+        assert_eq!(light.color, Color::rgb_u8(255, 0, 0));
+    }
+
+    #[test]
+    fn bad_maps_are_refused() {
+        assert!(Uvtt::parse("{}").is_err());
+        assert!(parse_color("ffzz0000").is_err());
+
+        let no_grid = MAP.replace("\"pixels_per_grid\": 100", "\"pixels_per_grid\": 0");
+        assert!(Uvtt::parse(&no_grid)
+            .unwrap()
+            .to_map(Layout::FlatTop)
+            .is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use bevy::{prelude::*, render::view::RenderLayers};
+
+use crate::{
+    fog::{Fog, FogState},
+    layer::Layer,
+    token::Token,
+};
+
+// Render layers on top of the shared layer 0, for what only one of the views shows
+pub const GM_ONLY: u8 = 1;
+pub const PLAYER_ONLY: u8 = 2;
+
+#[derive(States, PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub enum ViewMode {
+    // Everything, with the fog see through
+    #[default]
+    Gm,
+    // Only what the players should see
+    Player,
+}
+
+impl ViewMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewMode::Gm => "GM",
+            ViewMode::Player => "Player",
+        }
+    }
+
+    // What a camera showing the view renders
+    pub fn render_layers(&self) -> RenderLayers {
+        match self {
+            ViewMode::Gm => RenderLayers::from_layers(&[0, GM_ONLY]),
+            ViewMode::Player => RenderLayers::from_layers(&[0, PLAYER_ONLY]),
+        }
+    }
+
+    // Only rendered by the main camera in this view, for what's being done on this screen
+    pub fn own_layer(&self) -> RenderLayers {
+        match self {
+            ViewMode::Gm => RenderLayers::layer(GM_ONLY),
+            ViewMode::Player => RenderLayers::layer(PLAYER_ONLY),
+        }
+    }
+}
+
+// Set when the app is run for the players, so the view can't be switched back to the GM's
+#[derive(Resource)]
+pub struct ViewLocked;
+
+#[derive(Event)]
+pub enum ViewEvent {
+    // A second window on native, or a second browser tab on the web, showing the player view
+    OpenPlayerView,
+}
+
+// Camera of the player window, follows the main camera
+#[derive(Component)]
+pub struct PlayerCamera {
+    window: Entity,
+}
+
+// Query filter for the camera of the main window
+pub type MainCamera = (With<Camera>, Without<PlayerCamera>);
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<ViewMode>()
+            .add_event::<ViewEvent>()
+            .add_systems(
+                Update,
+                (
+                    on_view_event,
+                    apply_view_mode,
+                    filter_tokens,
+                    follow_main_camera,
+                    close_player_view,
+                ),
+            );
+
+        if started_as_player() {
+            app.insert_resource(ViewLocked)
+                .insert_resource(NextState(Some(ViewMode::Player)));
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        web::build(app);
+    }
+}
+
+// Render layers of everything drawn on a layer
+pub fn layer_render_layers(layer: Layer) -> RenderLayers {
+    match layer {
+        Layer::GmNotes => RenderLayers::layer(GM_ONLY),
+        _ => RenderLayers::default(),
+    }
+}
+
+// Hidden tokens and enemies in the fog are only shown to the GM
+pub fn visible_to_players(token: &Token, fog: &Fog) -> bool {
+    !token.hidden && (token.is_party() || fog.state(&token.coords) == FogState::Visible)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn started_as_player() -> bool {
+    false
+}
+
+// Player tabs are opened with ?view=player
+#[cfg(target_arch = "wasm32")]
+fn started_as_player() -> bool {
+    web_sys::window()
+        .and_then(|w| w.location().search().ok())
+        .is_some_and(|s| s.contains("view=player"))
+}
+
+fn on_view_event(
+    mut events: EventReader<ViewEvent>,
+    mut commands: Commands,
+    player_camera_q: Query<(), With<PlayerCamera>>,
+) {
+    for event in events.read() {
+        match event {
+            ViewEvent::OpenPlayerView => {
+                if player_camera_q.is_empty() {
+                    open_player_view(&mut commands);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_player_view(commands: &mut Commands) {
+    use bevy::{
+        core_pipeline::tonemapping::Tonemapping, render::camera::RenderTarget, window::WindowRef,
+    };
+
+    let window = commands
+        .spawn(Window {
+            title: "Hexalon - Players".to_string(),
+            ..Default::default()
+        })
+        .id();
+
+    commands.spawn((
+        Camera2dBundle {
+            camera: Camera {
+                hdr: true,
+                target: RenderTarget::Window(WindowRef::Entity(window)),
+                ..Default::default()
+            },
+            tonemapping: Tonemapping::TonyMcMapface,
+            ..Default::default()
+        },
+        ViewMode::Player.render_layers(),
+        PlayerCamera { window },
+    ));
+}
+
+// The tab runs its own app, kept in sync with this one through snapshots
+#[cfg(target_arch = "wasm32")]
+fn open_player_view(_commands: &mut Commands) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let location = window.location();
+    let (Ok(origin), Ok(path)) = (location.origin(), location.pathname()) else {
+        return;
+    };
+
+    if let Err(e) =
+        window.open_with_url_and_target(&format!("{}{}?view=player", origin, path), "_blank")
+    {
+        log::error!("Couldn't open the player view: {:?}", e);
+    }
+}
+
+// Points the main camera at the layers of the current view, with gizmos drawn for it alone
+fn apply_view_mode(
+    mut commands: Commands,
+    mode: Res<State<ViewMode>>,
+    mut gizmo_config: ResMut<GizmoConfig>,
+    cam_q: Query<(Entity, Option<&RenderLayers>), MainCamera>,
+) {
+    if gizmo_config.render_layers != mode.own_layer() {
+        gizmo_config.render_layers = mode.own_layer();
+    }
+
+    let layers = mode.render_layers();
+
+    for (e, current) in &cam_q {
+        if current != Some(&layers) {
+            commands.entity(e).insert(layers);
+        }
+    }
+}
+
+fn filter_tokens(
+    mut commands: Commands,
+    fog_q: Query<Ref<Fog>>,
+    token_q: Query<(Entity, Ref<Token>, Option<&Children>)>,
+) {
+    let Ok(fog) = fog_q.get_single() else {
+        return;
+    };
+
+    for (e, token, children) in &token_q {
+        if !fog.is_changed() && !token.is_changed() {
+            continue;
+        }
+
+        let layers = if visible_to_players(&token, &fog) {
+            RenderLayers::default()
+        } else {
+            RenderLayers::layer(GM_ONLY)
+        };
+
+        // Render layers aren't inherited, so the name goes too
+        commands.entity(e).insert(layers);
+        for child in children.into_iter().flatten() {
+            commands.entity(*child).insert(layers);
+        }
+    }
+}
+
+fn follow_main_camera(
+    main_q: Query<(&Transform, &OrthographicProjection), MainCamera>,
+    mut player_q: Query<(&mut Transform, &mut OrthographicProjection), With<PlayerCamera>>,
+) {
+    let Ok((main_t, main_proj)) = main_q.get_single() else {
+        return;
+    };
+
+    for (mut t, mut proj) in &mut player_q {
+        *t = *main_t;
+        proj.scale = main_proj.scale;
+    }
+}
+
+// Drops the player camera once its window has been closed
+fn close_player_view(
+    mut commands: Commands,
+    camera_q: Query<(Entity, &PlayerCamera)>,
+    window_q: Query<(), With<Window>>,
+) {
+    for (e, camera) in &camera_q {
+        if window_q.get(camera.window).is_err() {
+            commands.entity(e).despawn_recursive();
+        }
+    }
+}
+
+// Player tabs are sent snapshots of the GM's tab over a broadcast channel
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, rc::Rc};
+
+    use bevy::prelude::*;
+    use wasm_bindgen::{prelude::*, JsCast};
+    use web_sys::{BroadcastChannel, MessageEvent};
+
+    use super::{ViewLocked, ViewMode};
+    use crate::snapshot::{Snapshot, SnapshotEvent, SnapshotSource};
+
+    const CHANNEL_NAME: &str = "hexalon";
+    // Seconds between checks for changes worth sending
+    const SEND_INTERVAL: f32 = 0.5;
+    // Sent by new player tabs so the GM's tab sends everything again
+    const HELLO: &str = "hello";
+
+    struct Channel {
+        channel: BroadcastChannel,
+        received: Rc<RefCell<Vec<String>>>,
+        // Last snapshot sent, so unchanged state isn't sent again
+        last: Option<Snapshot>,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+    }
+
+    pub fn build(app: &mut App) {
+        let channel = match BroadcastChannel::new(CHANNEL_NAME) {
+            Ok(channel) => channel,
+            Err(e) => {
+                log::error!("Player view unavailable: {:?}", e);
+                return;
+            }
+        };
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let queue = received.clone();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |e: MessageEvent| {
+            if let Some(data) = e.data().as_string() {
+                queue.borrow_mut().push(data);
+            }
+        });
+        channel.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        if app.world.contains_resource::<ViewLocked>() {
+            let _ = channel.post_message(&JsValue::from_str(HELLO));
+        }
+
+        app.insert_non_send_resource(Channel {
+            channel,
+            received,
+            last: None,
+            _on_message: on_message,
+        })
+        .add_systems(Update, (send_snapshots, receive_snapshots));
+    }
+
+    fn send_snapshots(
+        mut channel: NonSendMut<Channel>,
+        time: Res<Time>,
+        mut timer: Local<f32>,
+        mode: Res<State<ViewMode>>,
+        locked: Option<Res<ViewLocked>>,
+        source: SnapshotSource,
+    ) {
+        if locked.is_some() || *mode.get() != ViewMode::Gm {
+            return;
+        }
+
+        *timer += time.delta_seconds();
+        if *timer < SEND_INTERVAL {
+            return;
+        }
+        *timer = 0.0;
+
+        let Some(snapshot) = source.capture().map(Snapshot::for_players) else {
+            return;
+        };
+        if channel.last.as_ref() == Some(&snapshot) {
+            return;
+        }
+
+        match serde_json::to_string(&snapshot) {
+            Ok(data) => {
+                let _ = channel.channel.post_message(&JsValue::from_str(&data));
+                channel.last = Some(snapshot);
+            }
+            Err(e) => log::error!("Couldn't send the player view: {}", e),
+        }
+    }
+
+    fn receive_snapshots(
+        mut channel: NonSendMut<Channel>,
+        locked: Option<Res<ViewLocked>>,
+        mut snapshot_event: EventWriter<SnapshotEvent>,
+    ) {
+        let received: Vec<String> = channel.received.borrow_mut().drain(..).collect();
+
+        for data in received {
+            if data == HELLO {
+                // Everything goes out again with the next send
+                channel.last = None;
+            } else if locked.is_some() {
+                match serde_json::from_str::<Snapshot>(&data) {
+                    Ok(snapshot) => snapshot_event.send(SnapshotEvent(snapshot)),
+                    Err(e) => log::error!("Bad snapshot from the GM: {}", e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{hex::HexCoord, token::TokenType};
+
+    #[test]
+    fn players_only_see_what_the_party_can() {
+        let coords = HexCoord { q: 2, r: 0 };
+        let mut fog = Fog::default();
+        fog.enabled = true;
+        let party = Token::new("a", "Fighter", TokenType::Party, &coords, &Color::BLUE);
+        let mut enemy = Token::new("b", "Goblin", TokenType::Enemy, &coords, &Color::RED);
+
+        assert!(visible_to_players(&party, &fog));
+        assert!(!visible_to_players(&enemy, &fog));
+
+        fog.reveal(&[coords]);
+        assert!(visible_to_players(&enemy, &fog));
+
+        enemy.hidden = true;
+        assert!(!visible_to_players(&enemy, &fog));
+
+        assert_eq!(
+            layer_render_layers(Layer::GmNotes),
+            RenderLayers::layer(GM_ONLY)
+        );
+        assert!(ViewMode::Gm
+            .render_layers()
+            .intersects(&layer_render_layers(Layer::GmNotes)));
+        assert!(!ViewMode::Player
+            .render_layers()
+            .intersects(&layer_render_layers(Layer::GmNotes)));
+    }
+}
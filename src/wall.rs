@@ -0,0 +1,440 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    window::PrimaryWindow,
+};
+use bevy_egui::EguiContexts;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    grid::{Grid, GridSettings},
+    hex::HexCoord,
+    layer::{Layer, Layers},
+    texture::world_uv,
+    view::{MainCamera, ViewMode},
+};
+
+lazy_static! {
+    static ref WALL_COLOR: Color = Color::rgb(0.1, 0.1, 0.1);
+    static ref DOOR_COLOR: Color = Color::rgb(0.55, 0.35, 0.15);
+    static ref OPEN_DOOR_COLOR: Color = Color::rgba(0.55, 0.35, 0.15, 0.35);
+}
+
+// Thickness of the drawn walls, relative to the hex size
+const WALL_WIDTH: f32 = 0.12;
+
+// The side shared by two neighbouring hexes, the same whichever of them it's seen from
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Edge {
+    a: HexCoord,
+    b: HexCoord,
+}
+
+impl Edge {
+    pub fn new(a: HexCoord, b: HexCoord) -> Self {
+        if (a.r, a.q) <= (b.r, b.q) {
+            Edge { a, b }
+        } else {
+            Edge { a: b, b: a }
+        }
+    }
+
+    pub fn hexes(&self) -> (HexCoord, HexCoord) {
+        (self.a, self.b)
+    }
+
+    // Ends of the side between the two hexes
+    pub fn ends(&self, grid: &Grid) -> (Vec2, Vec2) {
+        let (a, b) = (
+            grid.hex_coord_to_pos(&self.a),
+            grid.hex_coord_to_pos(&self.b),
+        );
+        let mid = (a + b) / 2.0;
+        let along = (b - a).perp().normalize_or_zero() * grid.settings.hex_size / 2.0;
+        (mid - along, mid + along)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum WallKind {
+    Wall,
+    Door { open: bool },
+}
+
+impl WallKind {
+    // Only open doors let sight, light and tokens through
+    pub fn blocks(&self) -> bool {
+        !matches!(self, WallKind::Door { open: true })
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            WallKind::Wall => *WALL_COLOR,
+            WallKind::Door { open: false } => *DOOR_COLOR,
+            WallKind::Door { open: true } => *OPEN_DOOR_COLOR,
+        }
+    }
+}
+
+// Walls and doors along the sides of hexes, stored as a list since the keys aren't strings
+#[derive(Component, Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(from = "Vec<(Edge, WallKind)>", into = "Vec<(Edge, WallKind)>")]
+pub struct Walls {
+    edges: HashMap<Edge, WallKind>,
+}
+
+impl From<Vec<(Edge, WallKind)>> for Walls {
+    fn from(edges: Vec<(Edge, WallKind)>) -> Self {
+        Walls {
+            edges: edges.into_iter().collect(),
+        }
+    }
+}
+
+impl From<Walls> for Vec<(Edge, WallKind)> {
+    // Sorted so the same walls always serialize the same
+    fn from(walls: Walls) -> Self {
+        let mut edges: Vec<(Edge, WallKind)> = walls.edges.into_iter().collect();
+        edges.sort_by_key(|(e, _)| (e.a.r, e.a.q, e.b.r, e.b.q));
+        edges
+    }
+}
+
+impl Walls {
+    pub fn get(&self, edge: &Edge) -> Option<WallKind> {
+        self.edges.get(edge).copied()
+    }
+
+    pub fn set(&mut self, edge: Edge, kind: Option<WallKind>) {
+        match kind {
+            Some(kind) => self.edges.insert(edge, kind),
+            None => self.edges.remove(&edge),
+        };
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Edge, &WallKind)> {
+        self.edges.iter()
+    }
+
+    pub fn count(&self, kind: impl Fn(&WallKind) -> bool) -> usize {
+        self.edges.values().filter(|k| kind(k)).count()
+    }
+
+    pub fn clear(&mut self) {
+        self.edges.clear();
+    }
+
+    // Whether a wall or closed door stands between two neighbouring hexes
+    pub fn blocks(&self, a: &HexCoord, b: &HexCoord) -> bool {
+        self.get(&Edge::new(*a, *b)).is_some_and(|k| k.blocks())
+    }
+
+    // True when the straight line from start to end is cut by a wall or closed door
+    pub fn blocks_line(&self, start: &HexCoord, end: &HexCoord) -> bool {
+        if self.edges.is_empty() {
+            return false;
+        }
+
+        let n = start.distance(end);
+        // Like line_of_sight, the line is only blocked when both sides of it are
+        let cut = |epsilon: f32| {
+            let mut prev = *start;
+            (1..=n).any(|i| {
+                let next = HexCoord::lerp_nudged(start, end, i as f32 / n as f32, epsilon);
+                let blocked = self.blocks(&prev, &next);
+                prev = next;
+                blocked
+            })
+        };
+        cut(1e-3) && cut(-1e-3)
+    }
+
+    // Opens a closed door and closes an open one, returning false if there's no door there
+    pub fn toggle_door(&mut self, edge: &Edge) -> bool {
+        match self.edges.get_mut(edge) {
+            Some(WallKind::Door { open }) => {
+                *open = !*open;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn set_doors(&mut self, open: bool) {
+        for kind in self.edges.values_mut() {
+            if let WallKind::Door { open: o } = kind {
+                *o = open;
+            }
+        }
+    }
+
+    // The door closest to pos, if there's one within half a hex of it
+    pub fn door_at(&self, grid: &Grid, pos: Vec2) -> Option<Edge> {
+        self.edges
+            .iter()
+            .filter(|(_, k)| matches!(k, WallKind::Door { .. }))
+            .map(|(e, _)| {
+                let (a, b) = e.ends(grid);
+                (*e, ((a + b) / 2.0).distance(pos))
+            })
+            .filter(|(_, d)| *d <= grid.settings.hex_size / 2.0)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(e, _)| e)
+    }
+}
+
+// Whether the segments p0 p1 and q0 q1 cross, touching the ends of q counts
+fn crosses(p0: Vec2, p1: Vec2, q0: Vec2, q1: Vec2) -> bool {
+    let (r, s) = (p1 - p0, q1 - q0);
+    let denom = r.perp_dot(s);
+    if denom.abs() < f32::EPSILON {
+        return false;
+    }
+
+    let t = (q0 - p0).perp_dot(s) / denom;
+    let u = (q0 - p0).perp_dot(r) / denom;
+    t > 0.0 && t < 1.0 && (0.0..=1.0).contains(&u)
+}
+
+// Sides of hexes a line drawn from a to b cuts off, those between any two neighbours whose
+// centres it separates
+pub fn edges_along(grid: &Grid, a: Vec2, b: Vec2) -> HashSet<Edge> {
+    // Nudged so it never runs exactly through a centre, which would wall that hex in
+    let nudge = (b - a).perp().normalize_or_zero() * grid.settings.hex_size * 1e-3;
+    let (a, b) = (a + nudge, b + nudge);
+
+    let step = grid.settings.hex_size / 2.0;
+    let samples = ((b - a).length() / step).ceil().max(1.0) as usize;
+
+    let mut near = HashSet::new();
+    for i in 0..=samples {
+        let hex = grid.pos_to_hex_coord(&a.lerp(b, i as f32 / samples as f32));
+        near.insert(hex);
+        near.extend(HexCoord::DIRECTIONS.iter().map(|d| &hex + d));
+    }
+
+    near.iter()
+        .flat_map(|c| HexCoord::DIRECTIONS.iter().map(move |d| (*c, c + d)))
+        .filter(|(c, n)| crosses(grid.hex_coord_to_pos(c), grid.hex_coord_to_pos(n), a, b))
+        .map(|(c, n)| Edge::new(c, n))
+        .collect()
+}
+
+// Set while clicks open and close doors instead of drawing
+#[derive(Component, Default)]
+pub struct DoorTool {
+    pub active: bool,
+}
+
+// The mesh the walls are drawn with
+#[derive(Component, Default)]
+struct WallMesh {
+    entity: Option<Entity>,
+    settings: Option<GridSettings>,
+}
+
+pub struct Plugin;
+impl bevy::prelude::Plugin for Plugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup)
+            .add_systems(Update, toggle_doors.run_if(in_state(ViewMode::Gm)))
+            .add_systems(PostUpdate, sync_walls);
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((DoorTool::default(), WallMesh::default()));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn toggle_doors(
+    mut contexts: EguiContexts,
+    buttons: Res<Input<MouseButton>>,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    cam_q: Query<(&Camera, &GlobalTransform), MainCamera>,
+    door_tool_q: Query<&DoorTool>,
+    mut walls_q: Query<&mut Walls>,
+    grid_q: Query<&Grid>,
+) {
+    if !door_tool_q.single().active
+        || !buttons.just_pressed(MouseButton::Left)
+        || contexts.ctx_mut().wants_pointer_input()
+    {
+        return;
+    }
+
+    let (cam, cam_transform) = cam_q.single();
+    let Some(pos) = window_q
+        .single()
+        .cursor_position()
+        .and_then(|cursor| cam.viewport_to_world_2d(cam_transform, cursor))
+    else {
+        return;
+    };
+
+    let mut walls = walls_q.single_mut();
+    if let Some(door) = walls.door_at(grid_q.single(), pos) {
+        walls.toggle_door(&door);
+    }
+}
+
+// A thick line along every wall and door
+fn build_wall_mesh(grid: &Grid, walls: &Walls) -> Mesh {
+    let mut positions = Vec::with_capacity(walls.edges.len() * 4);
+    let mut uvs = Vec::with_capacity(walls.edges.len() * 4);
+    let mut colors = Vec::with_capacity(walls.edges.len() * 4);
+    let mut indices = Vec::with_capacity(walls.edges.len() * 6);
+
+    let half_width = grid.settings.hex_size * WALL_WIDTH / 2.0;
+    for (edge, kind) in &walls.edges {
+        let (a, b) = edge.ends(grid);
+        let side = (b - a).perp().normalize_or_zero() * half_width;
+        let color = kind.color().as_linear_rgba_f32();
+        let start = positions.len() as u32;
+
+        for p in [a - side, b - side, b + side, a + side] {
+            positions.push([p.x, p.y, 0.0]);
+            uvs.push(world_uv(p));
+            colors.push(color);
+        }
+        indices.extend([start, start + 1, start + 2, start, start + 2, start + 3]);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+// Redraws the walls whenever they or the grid's settings change
+#[allow(clippy::too_many_arguments)]
+fn sync_walls(
+    mut commands: Commands,
+    mut wall_mesh_q: Query<&mut WallMesh>,
+    walls_q: Query<Ref<Walls>>,
+    grid_q: Query<&Grid>,
+    layers_q: Query<&Layers>,
+    handle_q: Query<&Mesh2dHandle>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let (Ok(mut wall_mesh), Ok(walls), Ok(grid), Ok(layers)) = (
+        wall_mesh_q.get_single_mut(),
+        walls_q.get_single(),
+        grid_q.get_single(),
+        layers_q.get_single(),
+    ) else {
+        return;
+    };
+
+    if !walls.is_changed() && wall_mesh.settings == Some(grid.settings) {
+        return;
+    }
+    wall_mesh.settings = Some(grid.settings);
+
+    match (walls.edges.is_empty(), wall_mesh.entity) {
+        (false, Some(e)) => {
+            if let Ok(handle) = handle_q.get(e) {
+                meshes.insert(handle.0.id(), build_wall_mesh(grid, &walls));
+            }
+        }
+        (false, None) => {
+            let settings = layers.get(Layer::Objects);
+            let e = commands
+                .spawn((
+                    MaterialMesh2dBundle {
+                        mesh: meshes.add(build_wall_mesh(grid, &walls)).into(),
+                        material: materials
+                            .add(ColorMaterial::from(Color::WHITE.with_a(settings.opacity))),
+                        // Over the paint on the objects layer, under the GM's notes
+                        transform: Transform::from_translation(
+                            Vec3::Z * (Layer::Objects.z() + 0.5),
+                        ),
+                        visibility: if settings.visible {
+                            Visibility::Inherited
+                        } else {
+                            Visibility::Hidden
+                        },
+                        ..Default::default()
+                    },
+                    Layer::Objects,
+                ))
+                .id();
+            wall_mesh.entity = Some(e);
+        }
+        (true, Some(e)) => {
+            commands.entity(e).despawn_recursive();
+            wall_mesh.entity = None;
+        }
+        (true, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{grid::tests::test_grid, pathfinding::find_path};
+
+    #[test]
+    fn walls_block_sight_until_the_door_opens() {
+        let grid = test_grid(10);
+        let (start, end) = (HexCoord { q: -2, r: 0 }, HexCoord { q: 2, r: 0 });
+        let door = Edge::new(HexCoord { q: 0, r: 0 }, HexCoord { q: 1, r: 0 });
+
+        let mut walls = Walls::default();
+        assert!(!walls.blocks_line(&start, &end));
+
+        walls.set(door, Some(WallKind::Door { open: false }));
+        assert!(walls.blocks_line(&start, &end));
+        assert!(walls.blocks(&HexCoord { q: 1, r: 0 }, &HexCoord { q: 0, r: 0 }));
+
+        // Tokens walk around a lone closed door
+        let path = find_path(&grid, &start, &end, |a, b| {
+            (!walls.blocks(a, b)).then_some(1.0)
+        });
+        assert_eq!(path.unwrap().cost, 5.0);
+
+        assert!(walls.toggle_door(&door));
+        assert!(!walls.blocks_line(&start, &end));
+        let pos = {
+            let (a, b) = door.ends(&grid);
+            (a + b) / 2.0
+        };
+        assert_eq!(walls.door_at(&grid, pos), Some(door));
+
+        // Survives a round trip through JSON
+        let json = serde_json::to_string(&walls).unwrap();
+        assert_eq!(serde_json::from_str::<Walls>(&json).unwrap(), walls);
+    }
+
+    #[test]
+    fn lines_become_the_edges_they_cut() {
+        let grid = test_grid(10);
+        let (left, right) = (HexCoord { q: 0, r: 0 }, HexCoord { q: 1, r: 0 });
+        let (a, b) = (grid.hex_coord_to_pos(&left), grid.hex_coord_to_pos(&right));
+        let mid = (a + b) / 2.0;
+
+        // A short line across the middle cuts only the one side
+        let side = (b - a).perp() * 0.25;
+        let edges = edges_along(&grid, mid - side, mid + side);
+        assert_eq!(edges, [Edge::new(left, right)].into());
+
+        // A long one cuts a whole row of them, leaving no gaps
+        let up = Vec2::Y * grid.settings.hex_size * 6.0;
+        let edges = edges_along(&grid, mid - up, mid + up);
+        let mut walls = Walls::default();
+        for e in &edges {
+            walls.set(*e, Some(WallKind::Wall));
+        }
+        assert!(edges.len() >= 8);
+        assert!(walls.blocks_line(&HexCoord { q: 0, r: 0 }, &HexCoord { q: 3, r: 0 }));
+        assert!(walls.blocks_line(&HexCoord { q: -1, r: 1 }, &HexCoord { q: 1, r: 1 }));
+        assert!(!walls.blocks_line(&HexCoord { q: -2, r: 0 }, &HexCoord { q: 0, r: 0 }));
+    }
+}